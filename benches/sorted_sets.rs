@@ -0,0 +1,63 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rusty_pelican::core::domain::skiplist::SkipList;
+
+const SIZES: [usize; 4] = [1_000, 10_000, 100_000, 1_000_000];
+
+fn leaderboard(size: usize) -> SkipList {
+    let mut list = SkipList::new();
+    for i in 0..size {
+        list.insert((i % 1000) as f64, &format!("user:{i}"));
+    }
+    list
+}
+
+/* Rank lookups should stay flat as the set grows, where a walk from the
+   lowest score grows with it. */
+fn ranks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zrank");
+    for size in SIZES {
+        let list = leaderboard(size);
+        let (score, member) = list.range(size / 2, size / 2 + 1).next().map(|(s, m)| (s, m.clone())).unwrap();
+        group.bench_with_input(BenchmarkId::new("skip list", size), &size, |b, _| {
+            b.iter(|| list.rank(black_box(score), black_box(&member)))
+        });
+        group.bench_with_input(BenchmarkId::new("linear walk", size), &size, |b, _| {
+            b.iter(|| list.iter().position(|(s, m)| s == black_box(score) && *m == member))
+        });
+    }
+    group.finish();
+}
+
+fn rank_ranges(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zrange");
+    for size in SIZES {
+        let list = leaderboard(size);
+        group.bench_with_input(BenchmarkId::new("middle ten", size), &size, |b, &size| {
+            b.iter(|| list.range(black_box(size / 2), black_box(size / 2 + 10)).count())
+        });
+        group.bench_with_input(BenchmarkId::new("last ten, reversed", size), &size, |b, &size| {
+            b.iter(|| list.range(black_box(size - 10), black_box(size)).rev().count())
+        });
+    }
+    group.finish();
+}
+
+fn updates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zadd");
+    for size in SIZES {
+        let mut list = leaderboard(size);
+        let member = format!("user:{}", size / 2);
+        let mut score = ((size / 2) % 1000) as f64;
+        group.bench_with_input(BenchmarkId::new("move a member", size), &size, |b, _| {
+            b.iter(|| {
+                list.remove(score, &member);
+                score = (score + 1f64) % 1000f64;
+                list.insert(score, &member);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ranks, rank_ranges, updates);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+use std::env;
+use std::io;
+use std::path;
+use std::process;
+use std::time;
+
+use rusty_pelican::core::tx_log;
+
+/* Reads a transaction log through the way a startup does and reports what
+   it finds, without writing to it. With --fix, everything from the first
+   bad entry on moves into a .discarded file next to the log. Exits with 1
+   if the log is left bad, and 2 if it could not be checked at all. */
+fn main() {
+    let arguments = env::args().skip(1).collect::<Vec<_>>();
+    let fix = arguments.iter().any(|argument| argument == "--fix");
+    let paths = arguments.iter().filter(|argument| !argument.starts_with("--")).collect::<Vec<_>>();
+    let [path] = paths.as_slice() else {
+        eprintln!("Usage: pelican-check-log [--fix] <transactions.log>");
+        process::exit(2)
+    };
+
+    match check(path::Path::new(path), fix) {
+        Ok(true)  => (),
+        Ok(false) => process::exit(1),
+        Err(e)    => {
+            eprintln!("pelican-check-log: {e}");
+            process::exit(2)
+        },
+    }
+}
+
+fn unix_seconds(at: time::SystemTime) -> u64 {
+    at.duration_since(time::UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+/* False if the log is bad and was left so. */
+fn check(path: &path::Path, fix: bool) -> io::Result<bool> {
+    if !path.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no such log", path.display())))
+    }
+    let replay = tx_log::ReplayView::open(path, &tx_log::Revision::default())?;
+    let mut continuity = tx_log::Continuity::whole_log();
+    let (mut good, mut first, mut last, mut problem) = (0, None, None, None);
+    for (offset, entry) in replay.entries() {
+        match continuity.check_read(entry) {
+            Ok((_, entry)) => {
+                good += 1;
+                first.get_or_insert((entry.revision.clone(), entry.at));
+                last = Some((entry.revision, entry.at));
+            },
+            Err(inconsistency) => {
+                problem = Some((offset, inconsistency));
+                break
+            },
+        }
+    }
+
+    println!("{}: {} bytes, {good} good entries.", path.display(), replay.size()?);
+    if replay.is_legacy()? {
+        println!("In the line format from before; the next startup writes it out in the current one.");
+    }
+    if let (Some((from, began)), Some((to, ended))) = (first, last) {
+        println!("Revisions {from} to {to}, written from {} to {} (Unix time).", unix_seconds(began), unix_seconds(ended));
+    }
+    if let Some((reversal, times)) = continuity.time_reversals() {
+        println!(
+            "Time goes back {times} time(s), first at revision {}, from {} to {} (Unix time); harmless, but was the clock set back?",
+            reversal.revision, unix_seconds(reversal.previous), unix_seconds(reversal.found)
+        );
+    }
+    let Some((offset, inconsistency)) = problem else {
+        println!("OK.");
+        return Ok(true)
+    };
+    println!("At byte {offset}: {inconsistency}.");
+    if fix {
+        let discarded = tx_log::LoggedTransactions::open(path, ())?.discard_log_from(offset)?;
+        println!("Fixed: moved the last {discarded} bytes to a .discarded file.");
+        Ok(true)
+    } else {
+        println!("Run with --fix to cut the log short there.");
+        Ok(false)
+    }
+}
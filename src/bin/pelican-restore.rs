@@ -0,0 +1,40 @@
+use std::env;
+use std::io;
+use std::path;
+use std::process;
+
+use rusty_pelican::core::*;
+
+/* Restores a data directory as it was at an earlier point into a new one,
+   without starting a server: the best snapshot from no later than then,
+   and the log replayed up to it. */
+fn main() {
+    let arguments = env::args().skip(1).collect::<Vec<_>>();
+    let (point, paths) = match arguments.as_slice() {
+        [flag, value, from, into] =>
+            (tx_log::PointInTime::from_flag(flag, value), [from, into]),
+        _otherwise =>
+            (None, [&String::new(), &String::new()]),
+    };
+    let Some(point) = point else {
+        eprintln!("Usage: pelican-restore (--until-revision N | --until-time UNIX-SECONDS) <from> <into>");
+        process::exit(2)
+    };
+
+    let [from, into] = paths.map(path::Path::new);
+    if let Err(e) = restore(from, into, &point) {
+        eprintln!("pelican-restore: {e}");
+        process::exit(1)
+    }
+}
+
+fn restore(from: &path::Path, into: &path::Path, point: &tx_log::PointInTime) -> io::Result<()> {
+    if !from.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no such data directory", from.display())))
+    }
+    let data = tx_log::LoggedTransactions::in_directory(into, domain::ttl::Lifetimes::new(Datasets::default()))?;
+    let mut state = StateContext::new(data);
+    print!("{}", state.restore_point_in_time(from, point)?);
+    println!("Saved into {}.", into.display());
+    Ok(())
+}
@@ -1,7 +1,9 @@
+use std::cmp;
 use std::convert::TryFrom;
 use std::io;
 use std::fmt;
 use std::str;
+use std::time;
 
 use crate::core::resp::*;
 use crate::core::domain::*;
@@ -14,25 +16,164 @@ pub enum ConnectionManagement {
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ServerManagement {
-    DbSize, Command(CommandOption), Info(Topic), BgSave,
+    DbSize, Command(CommandOption), Info(Topic), BgSave, Save, LastSave,
+    ConfigGet(String), ConfigSet(String, String),
+    Debug(DebugOption), BgRewriteAof,
+}
+
+/* Hooks for tests; ADVANCE-CLOCK needs a server started with --manual-clock. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugOption {
+    SetActiveExpire(bool),
+    AdvanceClock(u64),          /* Milliseconds. */
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PubSub {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+    PatternSubscribe(Vec<String>),
+    PatternUnsubscribe(Vec<String>),
+    Publish(String, String),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Topic {
-    Keyspace, Server, Named(String),
+    Keyspace, Server, Stats, Persistence, Everything, Named(String),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Generic {
-    Ttl(String),
-    Expire(String, u64),
+    Ttl(String, TimeUnit),
+    ExpireTime(String, TimeUnit),
+    Expire { key: String, at: Deadline, condition: ExpiryCondition, },
+    Persist(String),
     Keys(String),
-    Scan { cursor:  usize,
+    Scan { cursor:  u64,
            pattern: Option<String>,
            count:   Option<usize>,
            tpe:     Option<String>, },
-    Exists(String),
+    Exists(Vec<String>),
     Type(String),
+    Delete(Vec<String>),
+    Unlink(Vec<String>),
+    Rename { from: String, to: String, only_if_absent: bool, },
+    Copy { from: String, to: String, replace: bool, },
+    Touch(Vec<String>),
+    RandomKey,
+    FlushAll,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeUnit {
+    Seconds, Milliseconds,
+}
+
+impl TimeUnit {
+    /* Truncated, as EXPIRETIME has it. */
+    pub fn count(&self, duration: time::Duration) -> i64 {
+        match self {
+            TimeUnit::Seconds      => duration.as_secs() as i64,
+            TimeUnit::Milliseconds => duration.as_millis() as i64,
+        }
+    }
+
+    /* Rounded to the nearest, as TTL has it. */
+    pub fn round(&self, duration: time::Duration) -> i64 {
+        match self {
+            TimeUnit::Seconds      => ((duration.as_millis() + 500) / 1000) as i64,
+            TimeUnit::Milliseconds => duration.as_millis() as i64,
+        }
+    }
+}
+
+/* In milliseconds, from now or from the epoch. Anything in the past,
+   negative included, means right away. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Deadline {
+    After(i64),                 /* EXPIRE, PEXPIRE */
+    At(i64),                    /* EXPIREAT, PEXPIREAT */
+}
+
+impl Deadline {
+    fn parse(amount: &str, unit: TimeUnit, absolute: bool) -> io::Result<Self> {
+        let amount: i64 = Command::decode(amount)?;
+        let millis = match unit {
+            TimeUnit::Seconds      => amount.checked_mul(1000),
+            TimeUnit::Milliseconds => Some(amount),
+        }.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid expire time"))?;
+        Ok(if absolute { Deadline::At(millis) } else { Deadline::After(millis) })
+    }
+
+    /* In whole milliseconds since the epoch, which is how the transaction
+       log keeps it. None when the sum does not fit in a SystemTime. */
+    pub fn resolve(&self, now: time::SystemTime) -> Option<time::SystemTime> {
+        let millis = match self {
+            Deadline::After(millis) => epoch_millis(now).checked_add(*millis)?,
+            Deadline::At(millis)    => *millis,
+        };
+        if millis <= 0 {
+            Some(cmp::min(now, time::UNIX_EPOCH))
+        } else {
+            time::UNIX_EPOCH.checked_add(time::Duration::from_millis(millis as u64))
+        }
+    }
+}
+
+pub fn epoch_millis(at: time::SystemTime) -> i64 {
+    at.duration_since(time::UNIX_EPOCH).map_or(0, |since| since.as_millis() as i64)
+}
+
+/* A key without a deadline counts as living forever, so GT never applies
+   to it and LT always does. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpiryCondition {
+    Always,
+    IfNone,                     /* NX */
+    IfSome,                     /* XX */
+    IfLater,                    /* GT, XX GT */
+    IfSooner,                   /* LT */
+    IfSomeAndSooner,            /* XX LT */
+}
+
+impl ExpiryCondition {
+    fn parse(flags: &[&str]) -> io::Result<Self> {
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        for flag in flags {
+            match flag.to_ascii_uppercase().as_str() {
+                "NX"       => nx = true,
+                "XX"       => xx = true,
+                "GT"       => gt = true,
+                "LT"       => lt = true,
+                _otherwise => return Command::wrong_category(),
+            }
+        }
+        match (nx, xx, gt, lt) {
+            (false, false, false, false) => Ok(ExpiryCondition::Always),
+            (true, false, false, false)  => Ok(ExpiryCondition::IfNone),
+            (false, true, false, false)  => Ok(ExpiryCondition::IfSome),
+            (false, _, true, false)      => Ok(ExpiryCondition::IfLater),
+            (false, false, false, true)  => Ok(ExpiryCondition::IfSooner),
+            (false, true, false, true)   => Ok(ExpiryCondition::IfSomeAndSooner),
+            _otherwise                   => Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "NX and XX, GT or LT options at the same time are not compatible"
+            )),
+        }
+    }
+
+    pub fn admits(&self, current: Option<time::SystemTime>, proposed: time::SystemTime) -> bool {
+        match (self, current) {
+            (ExpiryCondition::Always, _)                  => true,
+            (ExpiryCondition::IfNone, current)            => current.is_none(),
+            (ExpiryCondition::IfSome, current)            => current.is_some(),
+            (ExpiryCondition::IfLater, Some(current))     => proposed > current,
+            (ExpiryCondition::IfLater, None)              => false,
+            (ExpiryCondition::IfSooner, None)             => true,
+            (ExpiryCondition::IfSooner, Some(current)) |
+            (ExpiryCondition::IfSomeAndSooner, Some(current)) => proposed < current,
+            (ExpiryCondition::IfSomeAndSooner, None)      => false,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -44,6 +185,7 @@ pub enum CommandOption {
 pub enum Command {
     ConnectionManagement(ConnectionManagement),
     ServerManagement(ServerManagement),
+    PubSub(PubSub),
     Generic(Generic),
     Lists(lists::ListApi),
     Strings(keyvalues::StringsApi),
@@ -58,8 +200,8 @@ impl Command {
 
     fn unknown(command: &Message) -> io::Result<Self> {
         match command.try_as_bulk_array().as_deref() {
-            Some([unknown @ ..]) => Ok(Command::Unknown(unknown.join(" "))),
-            _otherwise           => Self::wrong_category(),
+            Some(unknown) => Ok(Command::Unknown(unknown.join(" "))),
+            _otherwise    => Self::wrong_category(),
         }
     }
 
@@ -71,6 +213,120 @@ impl Command {
             |e: A::Err| io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
         )
     }
+
+    fn decode_score_range(min: &str, max: &str) -> io::Result<sorted_sets::ScoreRange> {
+        sorted_sets::ScoreRange::parse(min, max).ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidInput, "min or max is not a float")
+        )
+    }
+
+    fn decode_lex_range(min: &str, max: &str) -> io::Result<sorted_sets::LexRange> {
+        sorted_sets::LexRange::parse(min, max).ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidInput, "min or max not valid string range item")
+        )
+    }
+
+    fn decode_range_query(start: &str, stop: &str, options: &[&str]) -> io::Result<(sorted_sets::RangeQuery, bool)> {
+        sorted_sets::RangeQuery::parse(start, stop, options).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidInput, e)
+        )
+    }
+
+    /* The legacy range commands are ZRANGE with some options implied. */
+    fn decode_range(
+        key:     &str,
+        start:   &str,
+        stop:    &str,
+        implied: &[&str],
+        options: &[&str]
+    ) -> io::Result<sorted_sets::SortedSetApi> {
+        let options = implied.iter().chain(options).copied().collect::<Vec<_>>();
+        let (query, with_scores) = Command::decode_range_query(start, stop, &options)?;
+        Ok(sorted_sets::SortedSetApi::Range { key: key.to_string(), query, with_scores })
+    }
+
+    fn decode_combination(name: &str, args: &[&str]) -> io::Result<(sorted_sets::Combination, bool)> {
+        let name = name.to_ascii_uppercase();
+        let operation = if name.starts_with("ZUNION") {
+            sorted_sets::SetOperation::Union
+        } else if name.starts_with("ZINTER") {
+            sorted_sets::SetOperation::Intersection
+        } else {
+            sorted_sets::SetOperation::Difference
+        };
+        sorted_sets::Combination::parse(operation, args).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidInput, e)
+        )
+    }
+
+    /* MATCH, COUNT and, for SCAN itself, TYPE; in any order. */
+    fn decode_scan_options(options: &[&str], with_type: bool) -> io::Result<(Option<String>, Option<usize>, Option<String>)> {
+        let (mut pattern, mut count, mut tpe) = (None, None, None);
+        for option in options.chunks(2) {
+            match option {
+                [name, value] if name.eq_ignore_ascii_case("MATCH") =>
+                    pattern = Some(value.to_string()),
+                [name, value] if name.eq_ignore_ascii_case("COUNT") =>
+                    match Command::decode(value)? {
+                        0 => return Err(io::Error::new(io::ErrorKind::InvalidInput, "COUNT must be positive")),
+                        n => count = Some(n),
+                    },
+                [name, value] if with_type && name.eq_ignore_ascii_case("TYPE") =>
+                    tpe = Some(value.to_string()),
+                _otherwise =>
+                    return Command::wrong_category(),
+            }
+        }
+        Ok((pattern, count, tpe))
+    }
+
+    /* The unit and whether it is a point in time, for the EXPIRE family. */
+    fn decode_expire(name: &str) -> (TimeUnit, bool) {
+        let unit = if name.starts_with(['P', 'p']) { TimeUnit::Milliseconds } else { TimeUnit::Seconds };
+        (unit, name.ends_with("AT") || name.ends_with("at"))
+    }
+
+    fn decode_pop_from(name: &str) -> sorted_sets::PopFrom {
+        if name.eq_ignore_ascii_case("ZPOPMAX") || name.eq_ignore_ascii_case("BZPOPMAX") {
+            sorted_sets::PopFrom::Max
+        } else {
+            sorted_sets::PopFrom::Min
+        }
+    }
+
+    /* Seconds, fractions allowed; zero means forever. */
+    fn decode_timeout(image: &str) -> io::Result<f64> {
+        Command::decode(image).and_then(|timeout: f64|
+            if timeout.is_finite() && timeout >= 0f64 {
+                Ok(timeout)
+            } else {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "timeout is negative"))
+            }
+        )
+    }
+
+    /* A negative count asks for that many with repeats, so its size is taken
+       as is; bounded well before a reply that big could be allocated. */
+    fn decode_random_count(image: &str) -> io::Result<i64> {
+        Command::decode(image).and_then(|count: i64|
+            if (-i64::MAX / 2..=i64::MAX / 2).contains(&count) {
+                Ok(count)
+            } else {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "value is out of range"))
+            }
+        )
+    }
+
+    /* f64 happily parses "nan"; a score must not be one. */
+    fn decode_score(image: &str) -> io::Result<f64> {
+        Command::decode(image).and_then(|score: f64|
+            if score.is_nan() {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "value is not a valid float"))
+            } else {
+                Ok(score)
+            }
+        )
+    }
 }
 
 impl TryFrom<&Message> for Command {
@@ -82,6 +338,7 @@ impl TryFrom<&Message> for Command {
             .or_else(|_| sorted_sets::SortedSetApi::try_from(command).map(Command::SortedSets))
             .or_else(|_| ConnectionManagement::try_from(command).map(Command::ConnectionManagement))
             .or_else(|_| ServerManagement::try_from(command).map(Command::ServerManagement))
+            .or_else(|_| PubSub::try_from(command).map(Command::PubSub))
             .or_else(|_| Generic::try_from(command).map(Command::Generic))
             .or_else(|_| Command::unknown(command))
     }
@@ -118,14 +375,53 @@ impl TryFrom<&Message> for ServerManagement {
             Some(["DBSIZE" | "dbsize"])                     => Ok(ServerManagement::DbSize),
             Some(["INFO" | "info", "keyspace"])             => Ok(ServerManagement::Info(Topic::Keyspace)),
             Some(["INFO" | "info", "server"])               => Ok(ServerManagement::Info(Topic::Server)),
+            Some(["INFO" | "info", "stats"])                => Ok(ServerManagement::Info(Topic::Stats)),
+            Some(["INFO" | "info", "persistence"])          => Ok(ServerManagement::Info(Topic::Persistence)),
+            Some(["INFO" | "info", "all" | "everything" | "default"])
+                                                            => Ok(ServerManagement::Info(Topic::Everything)),
             Some(["INFO" | "info", topic])                  => Ok(ServerManagement::Info(Topic::Named(topic.to_string()))),
-            Some(["INFO" | "info"])                         => Ok(ServerManagement::Info(Topic::Named("topic.to_string()".to_string()))),
+            Some(["INFO" | "info"])                         => Ok(ServerManagement::Info(Topic::Everything)),
             Some(["BGSAVE" | "bgsave"])                     => Ok(ServerManagement::BgSave),
+            Some(["SAVE" | "save"])                         => Ok(ServerManagement::Save),
+            Some(["LASTSAVE" | "lastsave"])                 => Ok(ServerManagement::LastSave),
+            Some(["BGREWRITEAOF" | "bgrewriteaof"])         => Ok(ServerManagement::BgRewriteAof),
+            Some(["CONFIG" | "config", "GET" | "get", name])
+                                                            => Ok(ServerManagement::ConfigGet(name.to_string())),
+            Some(["CONFIG" | "config", "SET" | "set", name, value])
+                                                            => Ok(ServerManagement::ConfigSet(name.to_string(), value.to_string())),
+            Some(["DEBUG" | "debug", "SET-ACTIVE-EXPIRE" | "set-active-expire", flag @ ("0" | "1")])
+                                                            => Ok(ServerManagement::Debug(DebugOption::SetActiveExpire(*flag == "1"))),
+            Some(["DEBUG" | "debug", "ADVANCE-CLOCK" | "advance-clock", millis])
+                                                            => Ok(ServerManagement::Debug(DebugOption::AdvanceClock(Command::decode(millis)?))),
             _otherwise                                      => Command::wrong_category(),
         }
     }
 }
 
+impl TryFrom<&Message> for PubSub {
+    type Error = io::Error;
+    fn try_from(command: &Message) -> Result<Self, Self::Error> {
+        fn to_owned(xs: &[&str]) -> Vec<String> {
+            xs.iter().map(|&s| s.into()).collect()
+        }
+
+        match command.try_as_bulk_array().as_deref() {
+            Some(["SUBSCRIBE" | "subscribe", channels @ ..]) if !channels.is_empty() =>
+                Ok(PubSub::Subscribe(to_owned(channels))),
+            Some(["UNSUBSCRIBE" | "unsubscribe", channels @ ..]) =>
+                Ok(PubSub::Unsubscribe(to_owned(channels))),
+            Some(["PSUBSCRIBE" | "psubscribe", patterns @ ..]) if !patterns.is_empty() =>
+                Ok(PubSub::PatternSubscribe(to_owned(patterns))),
+            Some(["PUNSUBSCRIBE" | "punsubscribe", patterns @ ..]) =>
+                Ok(PubSub::PatternUnsubscribe(to_owned(patterns))),
+            Some(["PUBLISH" | "publish", channel, message]) =>
+                Ok(PubSub::Publish(channel.to_string(), message.to_string())),
+            _otherwise =>
+                Command::wrong_category(),
+        }
+    }
+}
+
 /* In generic.rs too? */
 impl TryFrom<&Message> for Generic {
     type Error = io::Error;
@@ -133,30 +429,51 @@ impl TryFrom<&Message> for Generic {
         match command.try_as_bulk_array().as_deref() {
             Some(["KEYS" | "keys", pattern]) =>
                 Ok(Generic::Keys(pattern.to_string())),
-            Some(["SCAN" | "scan", cursor]) =>
-                Ok(Generic::Scan {
-                    cursor: Command::decode(cursor)?, pattern: None, count: None, tpe: None
-                }),
-            Some(["SCAN" | "scan", cursor, "COUNT" | "count", count]) =>
-                Ok(Generic::Scan {
-                    cursor: Command::decode(cursor)?, 
-                    pattern: None, 
-                    count: Some(Command::decode(count)?),
-                    tpe: None,
-                }),
-            Some(["SCAN" | "scan", cursor, "MATCH" | "match", pattern, "COUNT" | "count", count]) =>
-                Ok(Generic::Scan {
-                    cursor: Command::decode(cursor)?,
-                    pattern: Some(pattern.to_string()),
-                    count: Some(Command::decode(count)?),
-                    tpe: None,
-                }),
+            Some(["SCAN" | "scan", cursor, options @ ..]) => {
+                let (pattern, count, tpe) = Command::decode_scan_options(options, true)?;
+                Ok(Generic::Scan { cursor: Command::decode(cursor)?, pattern, count, tpe })
+            },
             Some(["TTL" | "ttl", key]) =>
-                Ok(Generic::Ttl(key.to_string())),
-            Some(["EXPIRE" | "expire", key, ttl]) =>
-                Ok(Generic::Expire(key.to_string(), Command::decode(ttl)?)),
-            Some(["EXISTS" | "exists", key]) =>
-                Ok(Generic::Exists(key.to_string())),
+                Ok(Generic::Ttl(key.to_string(), TimeUnit::Seconds)),
+            Some(["PTTL" | "pttl", key]) =>
+                Ok(Generic::Ttl(key.to_string(), TimeUnit::Milliseconds)),
+            Some(["EXPIRETIME" | "expiretime", key]) =>
+                Ok(Generic::ExpireTime(key.to_string(), TimeUnit::Seconds)),
+            Some(["PEXPIRETIME" | "pexpiretime", key]) =>
+                Ok(Generic::ExpireTime(key.to_string(), TimeUnit::Milliseconds)),
+            Some([name @ ("EXPIRE" | "expire" | "PEXPIRE" | "pexpire" |
+                          "EXPIREAT" | "expireat" | "PEXPIREAT" | "pexpireat"), key, amount, flags @ ..]) => {
+                let (unit, absolute) = Command::decode_expire(name);
+                Ok(Generic::Expire {
+                    key:       key.to_string(),
+                    at:        Deadline::parse(amount, unit, absolute)?,
+                    condition: ExpiryCondition::parse(flags)?,
+                })
+            },
+            Some(["PERSIST" | "persist", key]) =>
+                Ok(Generic::Persist(key.to_string())),
+            Some(["EXISTS" | "exists", keys @ ..]) if !keys.is_empty() =>
+                Ok(Generic::Exists(keys.iter().map(|&s| s.into()).collect())),
+            Some(["DEL" | "del", keys @ ..]) if !keys.is_empty() =>
+                Ok(Generic::Delete(keys.iter().map(|&s| s.into()).collect())),
+            Some(["UNLINK" | "unlink", keys @ ..]) if !keys.is_empty() =>
+                Ok(Generic::Unlink(keys.iter().map(|&s| s.into()).collect())),
+            Some(["TOUCH" | "touch", keys @ ..]) if !keys.is_empty() =>
+                Ok(Generic::Touch(keys.iter().map(|&s| s.into()).collect())),
+            Some(["RENAME" | "rename", from, to]) =>
+                Ok(Generic::Rename { from: from.to_string(), to: to.to_string(), only_if_absent: false }),
+            Some(["RENAMENX" | "renamenx", from, to]) =>
+                Ok(Generic::Rename { from: from.to_string(), to: to.to_string(), only_if_absent: true }),
+            Some(["COPY" | "copy", from, to]) =>
+                Ok(Generic::Copy { from: from.to_string(), to: to.to_string(), replace: false }),
+            Some(["COPY" | "copy", from, to, "REPLACE" | "replace"]) =>
+                Ok(Generic::Copy { from: from.to_string(), to: to.to_string(), replace: true }),
+            Some(["RANDOMKEY" | "randomkey"]) =>
+                Ok(Generic::RandomKey),
+            /* There is only the one database, and flushing is never lazy. */
+            Some(["FLUSHALL" | "flushall" | "FLUSHDB" | "flushdb"]) |
+            Some(["FLUSHALL" | "flushall" | "FLUSHDB" | "flushdb", "ASYNC" | "async" | "SYNC" | "sync"]) =>
+                Ok(Generic::FlushAll),
             Some(["TYPE" | "type", key]) =>
                 Ok(Generic::Type(key.to_string())),
             _otherwise =>
@@ -237,7 +554,7 @@ impl TryFrom<&Message> for sorted_sets::SortedSetApi {
                 let entries = state.entries.chunks(2).map(|pär| {
                     match pär {
                         [score, member] => 
-                            Command::decode(score).map(|score: f64| (score, member.to_string())),
+                            Command::decode_score(score).map(|score| (score, member.to_string())),
                         bad_company =>
                             Err(io::Error::new(io::ErrorKind::InvalidInput, format!("bad format {bad_company:?}")))
                     }
@@ -245,22 +562,121 @@ impl TryFrom<&Message> for sorted_sets::SortedSetApi {
 
                 Ok(sorted_sets::SortedSetApi::Add { key: key.to_string(), entries, options: state.options, })
             }
-            Some(["ZRANGE" | "zrange", key, start, stop, "BYSCORE" | "byscore"]) => {
-                Ok(sorted_sets::SortedSetApi::RangeByScore(
-                    key.to_string(), Command::decode(start)?, Command::decode(stop)?
-                ))
-            }
-            Some(["ZRANGE" | "zrange", key, start, stop]) => {
-                Ok(sorted_sets::SortedSetApi::RangeByRank(
-                    key.to_string(), Command::decode(start)?, Command::decode(stop)?
-                ))
+            Some(["ZRANGE" | "zrange", key, start, stop, options @ ..]) =>
+                Command::decode_range(key, start, stop, &[], options),
+            Some(["ZREVRANGE" | "zrevrange", key, start, stop, options @ ..]) =>
+                Command::decode_range(key, start, stop, &["REV"], options),
+            Some(["ZRANGEBYSCORE" | "zrangebyscore", key, min, max, options @ ..]) =>
+                Command::decode_range(key, min, max, &["BYSCORE"], options),
+            Some(["ZREVRANGEBYSCORE" | "zrevrangebyscore", key, max, min, options @ ..]) =>
+                Command::decode_range(key, max, min, &["BYSCORE", "REV"], options),
+            Some(["ZRANGEBYLEX" | "zrangebylex", key, min, max, options @ ..]) =>
+                Command::decode_range(key, min, max, &["BYLEX"], options),
+            Some(["ZREVRANGEBYLEX" | "zrevrangebylex", key, max, min, options @ ..]) =>
+                Command::decode_range(key, max, min, &["BYLEX", "REV"], options),
+            Some(["ZRANGESTORE" | "zrangestore", destination, key, start, stop, options @ ..]) => {
+                let (query, with_scores) = Command::decode_range_query(start, stop, options)?;
+                if with_scores {
+                    Command::wrong_category()
+                } else {
+                    Ok(sorted_sets::SortedSetApi::RangeStore {
+                        destination: destination.to_string(), key: key.to_string(), query,
+                    })
+                }
             }
             Some(["ZRANK" | "zrank", key, member]) => {
                 Ok(sorted_sets::SortedSetApi::Rank(key.to_string(), member.to_string()))
             }
+            Some(["ZREVRANK" | "zrevrank", key, member]) => {
+                Ok(sorted_sets::SortedSetApi::ReverseRank(key.to_string(), member.to_string()))
+            }
             Some(["ZSCORE" | "zscore", key, member]) => {
                 Ok(sorted_sets::SortedSetApi::Score(key.to_string(), member.to_string()))
             }
+            Some(["ZMSCORE" | "zmscore", key, members @ ..]) if !members.is_empty() =>
+                Ok(sorted_sets::SortedSetApi::Scores(
+                    key.to_string(), members.iter().map(|&s| s.into()).collect()
+                )),
+            Some(["ZREM" | "zrem", key, members @ ..]) if !members.is_empty() =>
+                Ok(sorted_sets::SortedSetApi::Remove(
+                    key.to_string(), members.iter().map(|&s| s.into()).collect()
+                )),
+            Some(["ZINCRBY" | "zincrby", key, increment, member]) =>
+                Ok(sorted_sets::SortedSetApi::IncrementBy(
+                    key.to_string(), Command::decode_score(increment)?, member.to_string()
+                )),
+            Some(["ZCARD" | "zcard", key]) =>
+                Ok(sorted_sets::SortedSetApi::Cardinality(key.to_string())),
+            Some(["ZCOUNT" | "zcount", key, min, max]) =>
+                Ok(sorted_sets::SortedSetApi::Count(key.to_string(), Command::decode_score_range(min, max)?)),
+            Some([name @ ("ZPOPMIN" | "zpopmin" | "ZPOPMAX" | "zpopmax"), key, count @ ..]) if count.len() < 2 =>
+                Ok(sorted_sets::SortedSetApi::Pop {
+                    key: key.to_string(),
+                    from: Command::decode_pop_from(name),
+                    count: count.first().map(|count| Command::decode(count)).transpose()?,
+                }),
+            Some([name @ ("BZPOPMIN" | "bzpopmin" | "BZPOPMAX" | "bzpopmax"), keys @ .., timeout]) if !keys.is_empty() =>
+                Ok(sorted_sets::SortedSetApi::BlockingPop {
+                    keys: keys.iter().map(|&s| s.into()).collect(),
+                    from: Command::decode_pop_from(name),
+                    timeout: Command::decode_timeout(timeout)?,
+                }),
+            Some(["ZREMRANGEBYRANK" | "zremrangebyrank", key, start, stop]) =>
+                Ok(sorted_sets::SortedSetApi::RemoveRangeByRank(
+                    key.to_string(), Command::decode(start)?, Command::decode(stop)?
+                )),
+            Some(["ZREMRANGEBYSCORE" | "zremrangebyscore", key, min, max]) =>
+                Ok(sorted_sets::SortedSetApi::RemoveRangeByScore(
+                    key.to_string(), Command::decode_score_range(min, max)?
+                )),
+            Some(["ZREMRANGEBYLEX" | "zremrangebylex", key, min, max]) =>
+                Ok(sorted_sets::SortedSetApi::RemoveRangeByLex(
+                    key.to_string(), Command::decode_lex_range(min, max)?
+                )),
+            Some([name @ ("ZUNION" | "zunion" | "ZINTER" | "zinter" | "ZDIFF" | "zdiff"), args @ ..]) => {
+                let (combination, with_scores) = Command::decode_combination(name, args)?;
+                Ok(sorted_sets::SortedSetApi::Combine { combination, with_scores })
+            }
+            Some([name @ ("ZUNIONSTORE" | "zunionstore" | "ZINTERSTORE" | "zinterstore" | "ZDIFFSTORE" | "zdiffstore"),
+                  destination, args @ ..]) => {
+                match Command::decode_combination(name, args)? {
+                    (combination, false) =>
+                        Ok(sorted_sets::SortedSetApi::CombineStore { destination: destination.to_string(), combination }),
+                    _with_scores =>
+                        Command::wrong_category(),
+                }
+            }
+            Some(["ZINTERCARD" | "zintercard", numkeys, args @ ..]) => {
+                let numkeys: usize = Command::decode(numkeys)?;
+                match args {
+                    keys if keys.len() == numkeys && numkeys > 0 =>
+                        Ok(sorted_sets::SortedSetApi::IntersectionCardinality {
+                            keys: keys.iter().map(|&s| s.into()).collect(), limit: 0,
+                        }),
+                    [keys @ .., "LIMIT" | "limit", limit] if keys.len() == numkeys && numkeys > 0 =>
+                        Ok(sorted_sets::SortedSetApi::IntersectionCardinality {
+                            keys: keys.iter().map(|&s| s.into()).collect(), limit: Command::decode(limit)?,
+                        }),
+                    _otherwise =>
+                        Command::wrong_category(),
+                }
+            }
+            Some(["ZRANDMEMBER" | "zrandmember", key]) =>
+                Ok(sorted_sets::SortedSetApi::RandomMember { key: key.to_string(), count: None, with_scores: false }),
+            Some(["ZRANDMEMBER" | "zrandmember", key, count]) =>
+                Ok(sorted_sets::SortedSetApi::RandomMember {
+                    key: key.to_string(), count: Some(Command::decode_random_count(count)?), with_scores: false
+                }),
+            Some(["ZRANDMEMBER" | "zrandmember", key, count, "WITHSCORES" | "withscores"]) =>
+                Ok(sorted_sets::SortedSetApi::RandomMember {
+                    key: key.to_string(), count: Some(Command::decode_random_count(count)?), with_scores: true
+                }),
+            Some(["ZSCAN" | "zscan", key, cursor, options @ ..]) => {
+                let (pattern, count, _) = Command::decode_scan_options(options, false)?;
+                Ok(sorted_sets::SortedSetApi::Scan {
+                    key: key.to_string(), cursor: Command::decode(cursor)?, pattern, count,
+                })
+            },
             _otherwise =>
                 Command::wrong_category(),
         }
@@ -301,4 +717,169 @@ mod tests {
             Command::Lists(lists::ListApi::Length("mylist".to_string())),
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pubsub() {
+        assert_eq!(
+            Command::try_from(&make_command(vec!["SUBSCRIBE", "news", "weather"])).unwrap(),
+            Command::PubSub(PubSub::Subscribe(vec!["news".to_string(), "weather".to_string()])),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["PUNSUBSCRIBE"])).unwrap(),
+            Command::PubSub(PubSub::PatternUnsubscribe(vec![])),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["PUBLISH", "news", "hello"])).unwrap(),
+            Command::PubSub(PubSub::Publish("news".to_string(), "hello".to_string())),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["CONFIG", "SET", "notify-keyspace-events", "KEA"])).unwrap(),
+            Command::ServerManagement(ServerManagement::ConfigSet(
+                "notify-keyspace-events".to_string(), "KEA".to_string()
+            )),
+        );
+    }
+
+    #[test]
+    fn persistence() {
+        for (words, expected) in [
+            (vec!["SAVE"], ServerManagement::Save),
+            (vec!["bgsave"], ServerManagement::BgSave),
+            (vec!["LASTSAVE"], ServerManagement::LastSave),
+            (vec!["INFO", "persistence"], ServerManagement::Info(Topic::Persistence)),
+        ] {
+            assert_eq!(Command::try_from(&make_command(words)).unwrap(), Command::ServerManagement(expected));
+        }
+    }
+
+    #[test]
+    fn debug() {
+        assert_eq!(
+            Command::try_from(&make_command(vec!["DEBUG", "set-active-expire", "0"])).unwrap(),
+            Command::ServerManagement(ServerManagement::Debug(DebugOption::SetActiveExpire(false))),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["debug", "ADVANCE-CLOCK", "1500"])).unwrap(),
+            Command::ServerManagement(ServerManagement::Debug(DebugOption::AdvanceClock(1500))),
+        );
+        assert!(matches!(
+            Command::try_from(&make_command(vec!["DEBUG", "SET-ACTIVE-EXPIRE", "yes"])).unwrap(),
+            Command::Unknown(_)
+        ));
+    }
+
+    #[test]
+    fn expiry() {
+        assert_eq!(
+            Command::try_from(&make_command(vec!["PEXPIRE", "k", "1500", "xx", "GT"])).unwrap(),
+            Command::Generic(Generic::Expire {
+                key: "k".to_string(), at: Deadline::After(1500), condition: ExpiryCondition::IfLater,
+            }),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["EXPIREAT", "k", "1700000000"])).unwrap(),
+            Command::Generic(Generic::Expire {
+                key: "k".to_string(), at: Deadline::At(1_700_000_000_000), condition: ExpiryCondition::Always,
+            }),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["pexpiretime", "k"])).unwrap(),
+            Command::Generic(Generic::ExpireTime("k".to_string(), TimeUnit::Milliseconds)),
+        );
+        for malformed in [
+            vec!["EXPIRE", "k", "10", "NX", "GT"],
+            vec!["EXPIRE", "k", "10", "GT", "LT"],
+            vec!["EXPIRE", "k", "10", "SOON"],
+            vec!["EXPIRE", "k", "9223372036854775807"],
+        ] {
+            assert!(matches!(Command::try_from(&make_command(malformed)).unwrap(), Command::Unknown(_)));
+        }
+
+        let now = time::SystemTime::now();
+        let soon = Deadline::After(1500).resolve(now).unwrap();
+        assert_eq!(epoch_millis(soon), epoch_millis(now) + 1500);
+        assert_eq!(soon, time::UNIX_EPOCH + time::Duration::from_millis(epoch_millis(soon) as u64));
+        assert!(Deadline::After(-5).resolve(now) < Some(now));
+        assert_eq!(Deadline::At(-5).resolve(now), Some(time::UNIX_EPOCH));
+        assert_eq!(TimeUnit::Seconds.round(time::Duration::from_millis(1499)), 1);
+        assert_eq!(TimeUnit::Seconds.round(time::Duration::from_millis(1500)), 2);
+    }
+
+    #[test]
+    fn scans() {
+        assert_eq!(
+            Command::try_from(&make_command(vec!["SCAN", "17", "MATCH", "user:*"])).unwrap(),
+            Command::Generic(Generic::Scan {
+                cursor: 17, pattern: Some("user:*".to_string()), count: None, tpe: None,
+            }),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["scan", "0", "type", "zset", "count", "5"])).unwrap(),
+            Command::Generic(Generic::Scan {
+                cursor: 0, pattern: None, count: Some(5), tpe: Some("zset".to_string()),
+            }),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["ZSCAN", "z", "0", "COUNT", "2"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::Scan {
+                key: "z".to_string(), cursor: 0, pattern: None, count: Some(2),
+            }),
+        );
+        for malformed in [vec!["SCAN", "0", "COUNT"], vec!["SCAN", "0", "COUNT", "0"], vec!["ZSCAN", "z", "0", "TYPE", "zset"]] {
+            assert!(matches!(Command::try_from(&make_command(malformed)).unwrap(), Command::Unknown(_)));
+        }
+    }
+
+    #[test]
+    fn sorted_sets() {
+        assert_eq!(
+            Command::try_from(&make_command(vec!["ZPOPMAX", "z", "2"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::Pop {
+                key: "z".to_string(), from: sorted_sets::PopFrom::Max, count: Some(2),
+            }),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["BZPOPMIN", "z1", "z2", "0.5"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::BlockingPop {
+                keys: vec!["z1".to_string(), "z2".to_string()], from: sorted_sets::PopFrom::Min, timeout: 0.5,
+            }),
+        );
+        assert_eq!(
+            Command::try_from(&make_command(vec!["ZCOUNT", "z", "(1", "+inf"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::Count(
+                "z".to_string(), sorted_sets::ScoreRange::parse("(1", "+inf").unwrap(),
+            )),
+        );
+        assert!(matches!(
+            Command::try_from(&make_command(vec!["ZADD", "z", "nan", "a"])).unwrap(),
+            Command::Unknown(_)
+        ));
+        assert_eq!(
+            Command::try_from(&make_command(vec!["ZINTERCARD", "2", "z1", "z2", "LIMIT", "5"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::IntersectionCardinality {
+                keys: vec!["z1".to_string(), "z2".to_string()], limit: 5,
+            }),
+        );
+        assert!(matches!(
+            Command::try_from(&make_command(vec!["ZUNIONSTORE", "out", "2", "z1", "z2", "WEIGHTS", "1", "2"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::CombineStore { .. })
+        ));
+        assert!(matches!(
+            Command::try_from(&make_command(vec!["ZDIFFSTORE", "out", "1", "z1", "WITHSCORES"])).unwrap(),
+            Command::Unknown(_)
+        ));
+        assert_eq!(
+            Command::try_from(&make_command(vec!["ZRANDMEMBER", "z", "-3", "WITHSCORES"])).unwrap(),
+            Command::SortedSets(sorted_sets::SortedSetApi::RandomMember {
+                key: "z".to_string(), count: Some(-3), with_scores: true,
+            }),
+        );
+        for count in ["-9223372036854775808", "-4611686018427387904", "4611686018427387904"] {
+            assert_eq!(
+                sorted_sets::SortedSetApi::try_from(&make_command(vec!["ZRANDMEMBER", "z", count]))
+                    .unwrap_err().to_string(),
+                "value is out of range"
+            );
+        }
+    }
+}
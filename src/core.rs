@@ -2,14 +2,25 @@ pub mod snapshots;
 pub mod tx_log;
 pub mod domain;
 pub mod resp;
+pub mod channels;
+pub mod config;
+pub mod notifications;
+pub mod stats;
+pub mod clock;
 
 use std::collections;
+use std::convert;
+use std::iter;
 use std::thread;
 use std::sync;
+use std::sync::mpsc;
 use std::io;
 use std::io::prelude::*;
 use std::net;
+use std::time;
 use std::ops::Deref;
+use std::path;
+use std::fmt;
 use serde::{Serialize, Deserialize};
 
 use crate::commands::*;
@@ -18,6 +29,7 @@ use ttl::Lifetimes;
 use crate::generic;
 use crate::connections;
 use crate::server;
+use crate::pubsub;
 use crate::core::domain::ttl;
 use tx_log::WriteTransactionSink;
 use snapshots::Snapshots;
@@ -27,21 +39,157 @@ use resp::parser::*;
 pub type State = tx_log::LoggedTransactions<ttl::Lifetimes<Datasets>>;
 
 #[derive(Clone)]
-pub struct StateContext(sync::Arc<sync::RwLock<State>>);
+pub struct StateContext {
+    state:    sync::Arc<sync::RwLock<State>>,
+    config:   sync::Arc<sync::RwLock<config::Configuration>>,
+    channels: sync::Arc<sync::Mutex<channels::Channels>>,
+    writes:   sync::Arc<(sync::Mutex<u64>, sync::Condvar)>,
+    stats:    sync::Arc<stats::Statistics>,
+    saves:    sync::Arc<sync::Mutex<snapshots::SaveStatus>>,
+    clock:    sync::Arc<dyn clock::Clock>,
+    /* DEBUG SET-ACTIVE-EXPIRE turns the background expiry cycle off. */
+    active_expiry: sync::Arc<sync::atomic::AtomicBool>,
+}
+
+/* What a write leaves in the transaction log. */
+pub enum Journal {
+    AsIssued,
+    Rewritten(Message),
+    Nothing,
+}
+
+fn journal_entry(state: &mut State, at: time::SystemTime, message: &Message) -> io::Result<()> {
+    let revision = &state.revision();
+    state.record_evidence(at, revision, message)?;
+    state.bump_revision();
+    Ok(())
+}
+
+/* Every so often, a bounded share of that time goes to expunging. */
+const BACKGROUND_CYCLE_PERIOD: time::Duration = time::Duration::from_millis(100);
+const FSYNC_PERIOD: time::Duration = time::Duration::from_secs(1);
+const EXPIRY_CYCLE_BUDGET: time::Duration = time::Duration::from_millis(25);
 
 impl StateContext {
     pub fn new(state: State) -> Self {
         /* Is Arc really needed here? It's not really passed around.
            RwLock is not clonable. Replace Arc with Box perhaps. */
-        Self(sync::Arc::new(sync::RwLock::new(state)))
+        let clock: sync::Arc<dyn clock::Clock> = sync::Arc::new(clock::MonotonicClock::new());
+        Self {
+            state:    sync::Arc::new(sync::RwLock::new(state)),
+            config:   sync::Arc::default(),
+            channels: sync::Arc::default(),
+            writes:   sync::Arc::default(),
+            stats:    sync::Arc::default(),
+            saves:    Self::save_status(clock.as_ref()),
+            clock,
+            active_expiry: sync::Arc::new(sync::atomic::AtomicBool::new(true)),
+        }
+    }
+
+    /* Time as the clock has it from the start, LASTSAVE included. */
+    pub fn with_clock(self, clock: sync::Arc<dyn clock::Clock>) -> Self {
+        Self { saves: Self::save_status(clock.as_ref()), clock, ..self }
+    }
+
+    fn save_status(clock: &dyn clock::Clock) -> sync::Arc<sync::Mutex<snapshots::SaveStatus>> {
+        sync::Arc::new(sync::Mutex::new(
+            snapshots::SaveStatus::new(clock.now(), tx_log::Revision::default())
+        ))
+    }
+
+    pub fn stats(&self) -> &stats::Statistics {
+        &self.stats
+    }
+
+    pub fn clock(&self) -> &dyn clock::Clock {
+        self.clock.as_ref()
+    }
+
+    pub fn set_active_expiry(&self, enabled: bool) {
+        self.active_expiry.store(enabled, sync::atomic::Ordering::Relaxed);
     }
 
-    pub fn begin_reading(&self) -> io::Result<sync::RwLockReadGuard<State>> {
-        self.0.read().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    pub fn begin_reading(&self) -> io::Result<sync::RwLockReadGuard<'_, State>> {
+        self.state.read().map_err(|e| io::Error::other(e.to_string()))
     }
 
-    pub fn begin_writing(&self) -> io::Result<sync::RwLockWriteGuard<State>> {
-        self.0.write().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    pub fn begin_writing(&self) -> io::Result<sync::RwLockWriteGuard<'_, State>> {
+        self.state.write().map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    pub fn saves(&self) -> io::Result<sync::MutexGuard<'_, snapshots::SaveStatus>> {
+        self.saves.lock().map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    pub fn config(&self) -> io::Result<sync::RwLockReadGuard<'_, config::Configuration>> {
+        self.config.read().map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    pub fn reconfigure(&self) -> io::Result<sync::RwLockWriteGuard<'_, config::Configuration>> {
+        self.config.write().map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    /* Blocking commands take note of the write count, look at the data and
+       then wait for the count to move on; no write can slip in between. */
+    pub fn write_count(&self) -> io::Result<u64> {
+        let (count, _) = &*self.writes;
+        Ok(*count.lock().map_err(|e| io::Error::other(e.to_string()))?)
+    }
+
+    /* False if the deadline passed before anything was written. */
+    pub fn await_write(&self, seen: u64, deadline: Option<time::Instant>) -> io::Result<bool> {
+        let (count, written) = &*self.writes;
+        let mut current = count.lock().map_err(|e| io::Error::other(e.to_string()))?;
+        while *current == seen {
+            if let Some(deadline) = deadline {
+                let now = time::Instant::now();
+                if now >= deadline {
+                    return Ok(false)
+                }
+                current = written.wait_timeout(current, deadline - now)
+                    .map_err(|e| io::Error::other(e.to_string()))?.0;
+            } else {
+                current = written.wait(current).map_err(|e| io::Error::other(e.to_string()))?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn signal_write(&self) -> io::Result<()> {
+        let (count, written) = &*self.writes;
+        *count.lock().map_err(|e| io::Error::other(e.to_string()))? += 1;
+        written.notify_all();
+        Ok(())
+    }
+
+    pub fn subscriber(&self, outbox: mpsc::Sender<Message>) -> channels::Subscriber {
+        channels::Subscriber::new(self.channels.clone(), outbox)
+    }
+
+    pub fn publish(&self, channel: &str, message: &str) -> io::Result<usize> {
+        let channels = self.channels.lock().map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(channels.publish(channel, message))
+    }
+
+    /* Keyspace notifications; whether anything goes out at all is up to
+       `notify-keyspace-events`. */
+    pub fn notify(&self, class: notifications::EventClass, event: &str, key: &str) -> io::Result<()> {
+        let (keyspace, keyevent) = {
+            let events = &self.config()?.notify_keyspace_events;
+            if !events.publishes(class) {
+                return Ok(())
+            }
+            (events.keyspace_channel(key), events.keyevent_channel(event))
+        };
+
+        if let Some(channel) = keyspace {
+            self.publish(&channel, event)?;
+        }
+        if let Some(channel) = keyevent {
+            self.publish(&channel, key)?;
+        }
+        Ok(())
     }
 
     pub fn apply_transaction<F, A, C>(
@@ -53,55 +201,553 @@ impl StateContext {
         F: FnOnce(&mut State) -> A,
         C: Clone,
     {
-        let mut state = self.begin_writing()?;
-        let return_value = unit_of_work(&mut state);
-        let revision = &state.revision();
-        state.record_evidence(revision, command.transaction_message())?;
-        state.bump_revision();
+        self.try_apply_transaction(command, |state| Ok::<_, convert::Infallible>(unit_of_work(state)))
+            .map(|outcome| outcome.unwrap_or_else(|never| match never {}))
+    }
+
+    /* Nothing is logged should the unit of work fail, WRONGTYPE say, so it
+       must fail before it changes anything. */
+    pub fn try_apply_transaction<F, A, E, C>(
+        &self,
+        command: &CommandContext<C>,
+        unit_of_work: F
+    ) -> io::Result<Result<A, E>>
+    where
+        F: FnOnce(&mut State) -> Result<A, E>,
+        C: Clone,
+    {
+        self.apply_journaled_transaction(command, |state| {
+            let return_value = unit_of_work(state);
+            let journal = if return_value.is_ok() { Journal::AsIssued } else { Journal::Nothing };
+            (return_value, journal)
+        })
+    }
+
+    /* For commands that would not replay the same as issued, EXPIRE say,
+       which counts from whenever it runs: the unit of work decides what
+       goes in the log instead. */
+    pub fn apply_journaled_transaction<F, A, C>(
+        &self,
+        command: &CommandContext<C>,
+        unit_of_work: F
+    ) -> io::Result<A>
+    where
+        F: FnOnce(&mut State) -> (A, Journal),
+        C: Clone,
+    {
+        let always = self.config()?.appendfsync == config::AppendFsync::Always;
+        let (return_value, expired) = {
+            let mut state = self.begin_writing()?;
+            let (return_value, journal) = unit_of_work(&mut state);
+            match journal {
+                Journal::AsIssued            => journal_entry(&mut state, self.clock.now(), command.transaction_message())?,
+                Journal::Rewritten(message)  => journal_entry(&mut state, self.clock.now(), &message)?,
+                Journal::Nothing             => (),
+            }
+            if always {
+                state.sync_log(self.clock.now())?;
+            }
+            (return_value, state.take_expired())
+        };
+        self.signal_write()?;
+        self.report_expired(expired)?;
         Ok(return_value)
     }
 
-    pub fn restore_from_disk(&mut self) -> io::Result<()> {
-        self.restore_most_recent_snapshot()?;
-        self.apply_transaction_log()
+    fn report_expired(&self, keys: Vec<String>) -> io::Result<()> {
+        self.stats.count_expired(keys.len());
+        for key in keys {
+            self.notify(notifications::EventClass::Expired, "expired", &key)?;
+        }
+        Ok(())
     }
 
-    fn restore_most_recent_snapshot(&mut self) -> io::Result<()> {
-        self.begin_writing()?.restore_most_recent_snapshot()
+    /* False if the budget ran out with keys still due. */
+    fn expunge_expired(&self, budget: Option<time::Instant>) -> io::Result<bool> {
+        let always = self.config()?.appendfsync == config::AppendFsync::Always;
+        let (completed, expired) = {
+            let mut state = self.begin_writing()?;
+            let completed = state.expunge_expired_until(self.clock(), budget);
+            let expired = state.take_expired();
+            /* Replaying these, rather than expiring anew, restores exactly
+               what was here, whenever that happens. */
+            for key in &expired {
+                journal_entry(&mut state, self.clock.now(), &Message::make_bulk_array(&["DEL".to_string(), key.clone()]))?;
+            }
+            if always && !expired.is_empty() {
+                state.sync_log(self.clock.now())?;
+            }
+            (completed, expired)
+        };
+        self.report_expired(expired)?;
+        Ok(completed)
     }
 
-    fn apply_transaction_log(&self) -> io::Result<()> {
-        {   let state = self.begin_reading()?;
-            for message in state.transaction_log().replay(&state.revision())?.iter() {
-                self.apply(CommandContext::try_from(&message?)?)?;
+    /* A read lock cannot remove anything, so whatever is due goes before
+       each command rather than during it; reads then never see a key past
+       its deadline. Nothing due costs a peek at the earliest deadline. */
+    fn expunge_due(&self) -> io::Result<()> {
+        /* A replayed log brings its own expunges. */
+        let state = self.begin_reading()?;
+        let due = !state.is_replaying() && state.next_deadline()
+            .is_some_and(|at| at < self.clock.now());
+        drop(state);
+        if due {
+            self.expunge_expired(None)?;
+        }
+        Ok(())
+    }
+
+    /* Keys nobody asks for again go away too, the transaction log is
+       synced every second under appendfsync everysec, and rewritten once
+       it has grown enough; snapshots are saved as the save rules say. */
+    /* A step that fails is tried again on the next cycle; the others carry
+       on regardless. */
+    pub fn spawn_background_cycle(&self) -> thread::JoinHandle<()> {
+        let state = self.clone();
+        thread::spawn(move || {
+            let mut synced = state.clock.instant();
+            let report = |outcome: io::Result<()>| if let Err(e) = outcome {
+                println!("spawn_background_cycle: Error `{e}`.");
+            };
+            loop {
+                thread::sleep(BACKGROUND_CYCLE_PERIOD);
+                if state.active_expiry.load(sync::atomic::Ordering::Relaxed) {
+                    report(state.expunge_expired(Some(state.clock.instant() + EXPIRY_CYCLE_BUDGET)).map(|finished|
+                        if !finished {
+                            state.stats.count_expiry_cycle_cut_short();
+                        }
+                    ));
+                }
+                if state.clock.instant() >= synced + FSYNC_PERIOD {
+                    let outcome = state.sync_log_in_background();
+                    if outcome.is_ok() {
+                        synced = state.clock.instant();
+                    }
+                    report(outcome);
+                }
+                report(state.rewrite_log_if_grown());
+                report(state.save_if_due());
+            }
+        })
+    }
+
+    /* Writes carry on while the disk catches up. */
+    fn sync_log_in_background(&self) -> io::Result<()> {
+        if self.config()?.appendfsync != config::AppendFsync::EverySecond {
+            return Ok(())
+        }
+        let Some(sync) = self.begin_reading()?.begin_background_sync()? else { return Ok(()) };
+        sync.run()?;
+        self.begin_writing()?.finish_background_sync(sync, self.clock.now());
+        Ok(())
+    }
+
+    fn rewrite_log_if_grown(&self) -> io::Result<()> {
+        let (percentage, min_size) = {
+            let config = self.config()?;
+            (config.auto_aof_rewrite_percentage, config.auto_aof_rewrite_min_size)
+        };
+        let due = {
+            let state = self.begin_reading()?;
+            let log = state.transaction_log();
+            let growth = log.size().saturating_sub(log.base_size()) * 100 / log.base_size().max(1);
+            percentage > 0 && !state.is_rewriting() && log.size() >= min_size && growth >= percentage
+        };
+        if due {
+            self.rewrite_log()?;
+        }
+        Ok(())
+    }
+
+    /* False if a rewrite is under way already. The keyspace is copied while
+       writes wait, which is quick next to writing it out; that happens in
+       the background, and writes meanwhile go to both logs. */
+    pub fn rewrite_log(&self) -> io::Result<bool> {
+        /* Stamped before anything written meanwhile, so time in the log
+           still runs forwards. */
+        let (rewrite, copy, at) = {
+            let mut state = self.begin_writing()?;
+            let Some(rewrite) = state.begin_rewrite()? else { return Ok(false) };
+            (rewrite, (**state).clone(), self.clock.now())
+        };
+        let state = self.clone();
+        thread::spawn(move || {
+            if let Err(e) = state.complete_log_rewrite(rewrite, copy, at) {
+                println!("rewrite_log: Error `{e}`.");
+            }
+        });
+        Ok(true)
+    }
+
+    /* The copy stands for every write up to its revision, so it goes in
+       at the one before: a snapshot from then or earlier replays it, and a
+       later one skips it. */
+    fn complete_log_rewrite(
+        &self,
+        mut rewrite: tx_log::LogRewrite,
+        copy: Lifetimes<Datasets>,
+        at: time::SystemTime,
+    ) -> io::Result<()> {
+        let written = copy.revision().preceding().map_or(Ok(()), |revision|
+            copy.reconstruction().try_for_each(|message| rewrite.append(at, &revision, &message))
+        );
+        let mut state = self.begin_writing()?;
+        match written {
+            Ok(()) =>
+                state.finish_rewrite(rewrite),
+            Err(e) => {
+                state.abandon_rewrite(rewrite);
+                Err(e)
+            },
+        }
+    }
+
+    pub fn restore_from_disk(&mut self, mode: tx_log::RestoreMode) -> io::Result<RestoreReport> {
+        let directory = self.begin_reading()?.directory().to_path_buf();
+        self.restore(&directory, mode, None)
+    }
+
+    /* Everything in another data directory as it was at an earlier point,
+       into this one, which must be empty; the result is saved here as a
+       snapshot. Nothing in the other directory is touched. */
+    pub fn restore_point_in_time(&mut self, from: &path::Path, point: &tx_log::PointInTime) -> io::Result<RestoreReport> {
+        {
+            let state = self.begin_reading()?;
+            if !state.transaction_log().is_empty() || snapshots::most_recent(state.directory())?.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already holds data", state.directory().display()),
+                ))
+            }
+        }
+        let until = point.resolve(&from.join(tx_log::LOG_FILE))?;
+        let report = self.restore(from, tx_log::RestoreMode::Strict, until.as_ref())?;
+        match until {
+            Some(until) if report.revision != until =>
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{report}Revision {until} is out of reach: nothing covers the revisions in between."),
+                )),
+            _otherwise =>
+                (),
+        }
+        self.save()?;
+        Ok(report)
+    }
+
+    fn restore(&mut self, directory: &path::Path, mode: tx_log::RestoreMode, until: Option<&tx_log::Revision>) -> io::Result<RestoreReport> {
+        let snapshot = self.begin_writing()?.restore_most_recent_snapshot(directory, until)?;
+        let report = self.apply_transaction_log(&directory.join(tx_log::LOG_FILE), snapshot, mode, until)?;
+        *self.saves()? = snapshots::SaveStatus::new(self.clock.now(), report.revision.clone());
+        Ok(report)
+    }
+
+    /* False if a save is under way already. As with a log rewrite, writes
+       wait only while the keyspace is copied, not while it is written. */
+    pub fn save_in_background(&self) -> io::Result<bool> {
+        {
+            let mut saves = self.saves()?;
+            if saves.is_in_progress() {
+                return Ok(false)
+            }
+            saves.begin(self.clock.instant());
+        }
+        let encoding = self.snapshot_encoding()?;
+        /* Cheap, as the keyspace is shared with the copy rather than copied. */
+        let (copy, directory) = {
+            let state = self.begin_reading()?;
+            ((**state).clone(), state.directory().to_path_buf())
+        };
+        let state = self.clone();
+        thread::spawn(move || {
+            let outcome = copy.save_snapshot(&directory, &encoding).and_then(|()| state.retain_snapshots());
+            if let Err(e) = &outcome {
+                println!("save_in_background: Error `{e}`.");
+            }
+            let now = (state.clock.now(), state.clock.instant());
+            match state.saves() {
+                Ok(mut saves) => saves.finish(&outcome, copy.revision(), now),
+                Err(e)        => println!("save_in_background: Error `{e}`."),
             }
+        });
+        Ok(true)
+    }
+
+    /* In the foreground, and nothing is written meanwhile. False if a
+       background save is under way; holding on to the status keeps
+       another from starting. */
+    pub fn save(&self) -> io::Result<bool> {
+        let encoding = self.snapshot_encoding()?;
+        let mut saves = self.saves()?;
+        if saves.is_in_progress() {
+            return Ok(false)
+        }
+        saves.begin(self.clock.instant());
+        let (outcome, revision) = {
+            let state = self.begin_reading()?;
+            (state.save_snapshot(state.directory(), &encoding), state.revision())
+        };
+        let outcome = outcome.and_then(|()| self.retain_snapshots());
+        saves.finish(&outcome, revision, (self.clock.now(), self.clock.instant()));
+        outcome.map(|()| true)
+    }
+
+    fn snapshot_encoding(&self) -> io::Result<snapshots::Encoding> {
+        let config = self.config()?;
+        Ok(snapshots::Encoding {
+            compression:          config.snapshot_compression,
+            compress_values_over: config.snapshot_compress_values_over.try_into().unwrap_or(usize::MAX),
+        })
+    }
+
+    /* Saves whenever one of the save rules says so. */
+    fn save_if_due(&self) -> io::Result<()> {
+        let rules = self.config()?.save.clone();
+        let revision = self.begin_reading()?.revision();
+        if self.saves()?.is_due(&rules, &revision, (self.clock.now(), self.clock.instant())) {
+            self.save_in_background()?;
+        }
+        Ok(())
+    }
+
+    /* Past snapshot-retention snapshots the oldest go, and with them the
+       part of the log that only they needed. The log is scanned without
+       holding anything; should it be rewritten meanwhile, it stays as is. */
+    fn retain_snapshots(&self) -> io::Result<()> {
+        let keep = self.config()?.snapshot_retention;
+        if keep == 0 {
+            return Ok(())
+        }
+        let (directory, log, generation) = {
+            let state = self.begin_reading()?;
+            (state.directory().to_path_buf(), state.transaction_log().path().to_path_buf(), state.generation())
+        };
+        let Some(oldest) = snapshots::retain_newest(&directory, keep)? else { return Ok(()) };
+        let Some(offset) = tx_log::ReplayView::open(&log, &tx_log::Revision::default())?.offset_of(&oldest) else { return Ok(()) };
+        let Some(mut truncation) = self.begin_writing()?.begin_truncation(offset, generation)? else { return Ok(()) };
+        let copied = truncation.copy_kept();
+        let mut state = self.begin_writing()?;
+        match copied {
+            Ok(()) =>
+                state.finish_rewrite(truncation),
+            Err(e) => {
+                state.abandon_rewrite(truncation);
+                Err(e)
+            },
+        }
+    }
+
+    /* Entries are checked before they are replayed; the first one out of
+       place stops a strict restore with an error, and ends a recovering
+       one, which moves it and everything after it out of the log. Short of
+       a given revision, it is always an error: the log may not be ours. A
+       log from another directory is never cut short; replay stops at a
+       torn last entry there, and anything else is an error. */
+    fn apply_transaction_log(
+        &self,
+        log:      &path::Path,
+        snapshot: Option<path::PathBuf>,
+        mode:     tx_log::RestoreMode,
+        until:    Option<&tx_log::Revision>,
+    ) -> io::Result<RestoreReport> {
+        let (since, own) = {
+            let state = self.begin_reading()?;
+            (state.revision(), log == state.transaction_log().path())
+        };
+        let replay = tx_log::ReplayView::open(log, &since)?;
+        let mut report = RestoreReport::new(snapshot, since.clone());
+        let mut continuity = tx_log::Continuity::new(&since);
+        for (offset, entry) in replay.entries() {
+            match continuity.check_read(entry) {
+                Ok((true, entry)) if until.is_some_and(|until| entry.revision >= *until) =>
+                    break,
+                Ok((true, entry)) => {
+                    self.apply(CommandContext::try_from(&entry.message)?)?;
+                    /* Counting replayed entries would not do, since a rewritten
+                       log has a great many of them at the same revision. */
+                    report.revision = entry.revision.succeeding();
+                    self.begin_writing()?.set_revision(report.revision.clone());
+                    report.replayed += 1;
+                },
+                Ok((false, _)) =>
+                    report.covered += 1,
+                /* Never acknowledged, so there is nothing to lose by cutting
+                   it off, as aof-load-truncated does. */
+                Err(tx_log::Inconsistency::Torn) if !own && until.is_none() =>
+                    break,
+                Err(inconsistency) if until.is_some() || !own || (mode == tx_log::RestoreMode::Strict
+                                   && inconsistency != tx_log::Inconsistency::Torn) =>
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{report}Stopped at byte {offset} of the log: {inconsistency}."),
+                    )),
+                Err(inconsistency) => {
+                    let discarded = self.begin_writing()?.discard_log_from(offset)?;
+                    report.stopped = Some((offset, inconsistency, discarded));
+                    break
+                },
+            }
+        }
+        report.log_behind = continuity.ends_before_snapshot();
+        report.time_reversals = continuity.time_reversals();
+
+        self.begin_writing()?.finalize_replay()?;
+        Ok(report)
+    }
+}
+
+/* What a startup found on disk, for the operator. */
+#[derive(Debug)]
+pub struct RestoreReport {
+    snapshot:   Option<path::PathBuf>,
+    since:      tx_log::Revision,
+    revision:   tx_log::Revision,
+    replayed:   usize,
+    covered:    usize,
+    stopped:    Option<(u64, tx_log::Inconsistency, u64)>,
+    log_behind: Option<tx_log::Revision>,
+    time_reversals: Option<(tx_log::TimeReversal, usize)>,
+}
+
+impl RestoreReport {
+    fn new(snapshot: Option<path::PathBuf>, since: tx_log::Revision) -> Self {
+        Self {
+            snapshot, revision: since.clone(), since, replayed: 0, covered: 0, stopped: None, log_behind: None,
+            time_reversals: None,
         }
+    }
+}
 
-        Ok(self.begin_writing()?.finalize_replay())
+impl fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.snapshot {
+            Some(path) => writeln!(f, "Restored {} at revision {}.", path.display(), self.since)?,
+            None       => writeln!(f, "No snapshot to restore.")?,
+        }
+        writeln!(
+            f, "Replayed {} log entries, after {} the snapshot covers, up to revision {}.",
+            self.replayed, self.covered, self.revision
+        )?;
+        if let Some(last) = &self.log_behind {
+            writeln!(f, "Warning: the log ends at revision {last}, short of the snapshot.")?;
+        }
+        if let Some((reversal, times)) = &self.time_reversals {
+            writeln!(
+                f, "Warning: time in the log goes back {times} time(s), first at revision {}, from {:?} to {:?}. \
+                    Was the clock set back?",
+                reversal.revision, reversal.previous, reversal.found
+            )?;
+        }
+        if let Some((offset, inconsistency, discarded)) = &self.stopped {
+            writeln!(
+                f, "Warning: stopped at byte {offset} of the log: {inconsistency}. \
+                    The {discarded} bytes from there on were moved to a .discarded file."
+            )?;
+        }
+        Ok(())
     }
 }
 
 impl ttl::Expungeable for Datasets {
     fn expunge(&mut self, id: &str) {
         /* Should this take a transaction logged route instead? */
-        if self.lists.remove(id).is_none() {
-            if self.strings.remove(id).is_none() {
-                self.sorted_sets.remove(id);
-            }
+        self.remove_key(id);
+    }
+}
+
+/* Whatever ends a key, or replaces it with a new one, ends its deadline
+   too; these stand in for the Datasets methods of the same names. */
+impl Lifetimes<Datasets> {
+    pub fn insert<A: keyspace::Kind>(&mut self, key: &str, value: A) {
+        self.clear_ttl(key);
+        Datasets::insert(self, key, value)
+    }
+
+    pub fn remove_key(&mut self, key: &str) -> bool {
+        self.clear_ttl(key);
+        Datasets::remove_key(self, key)
+    }
+
+    pub fn rename_key(&mut self, from: &str, to: &str) -> bool {
+        let renamed = Datasets::rename_key(self, from, to);
+        if renamed {
+            self.move_ttl(from, to);
+        }
+        renamed
+    }
+
+    pub fn copy_key(&mut self, from: &str, to: &str) -> bool {
+        let copied = Datasets::copy_key(self, from, to);
+        if copied {
+            self.copy_ttl(from, to);
+        }
+        copied
+    }
+
+    pub fn update<A, R, F>(&mut self, key: &str, update: F) -> keyspace::Typed<R>
+    where
+        A: keyspace::Kind,
+        R: Default,
+        F: FnOnce(&mut A) -> R,
+    {
+        let updated = Datasets::update(self, key, update);
+        self.forget_if_gone(key);
+        updated
+    }
+
+    pub fn remove_if_empty(&mut self, key: &str) {
+        Datasets::remove_if_empty(self, key);
+        self.forget_if_gone(key);
+    }
+
+    pub fn remove_all_keys(&mut self) {
+        self.clear_ttls();
+        Datasets::clear(self);
+    }
+
+    /* Commands that build every key up again as it is now, deadlines and
+       all, from nothing. */
+    pub fn reconstruction(&self) -> impl Iterator<Item = Message> + '_ {
+        let flush = Message::make_bulk_array(&["FLUSHALL".to_string()]);
+        iter::once(flush).chain(self.keys().flat_map(|key| {
+            let value = self.value(key).map(|value| value.reconstruction(key)).unwrap_or_default();
+            let deadline = self.deadline(key).map(|at| generic::expire_at(key, at));
+            value.into_iter().chain(deadline)
+        }))
+    }
+
+    fn forget_if_gone(&mut self, key: &str) {
+        if !self.contains_key(key) {
+            self.clear_ttl(key);
         }
     }
 }
 
 impl snapshots::Snapshots for Lifetimes<Datasets> {
-    fn save_snapshot(&self) -> io::Result<()> {
-        snapshots::allocate_new()?.put(self)
+    fn save_snapshot(&self, directory: &path::Path, encoding: &snapshots::Encoding) -> io::Result<()> {
+        snapshots::allocate_new(directory)?.put(self, &self.revision(), encoding)
     }
 
-    fn restore_most_recent_snapshot(&mut self) -> io::Result<()> {
-        if let Some(snapshot) = snapshots::most_recent()? {
-            *self = snapshot.get::<Self>()?;
-        }
-        Ok(())
+    fn restore_most_recent_snapshot(
+        &mut self,
+        directory: &path::Path,
+        until:     Option<&tx_log::Revision>,
+    ) -> io::Result<Option<path::PathBuf>> {
+        let Some((path, restored)) = snapshots::restore_newest::<Self, Lifetimes<LegacyDatasets>, _>(
+            directory, until, |snapshot| snapshot.revision()
+        )? else {
+            return Ok(None)
+        };
+        *self = restored;
+        self.reindex();
+        Ok(Some(path))
+    }
+}
+
+impl From<Lifetimes<LegacyDatasets>> for Lifetimes<Datasets> {
+    fn from(legacy: Lifetimes<LegacyDatasets>) -> Self {
+        legacy.map(Datasets::from)
     }
 }
 
@@ -113,14 +759,32 @@ pub struct SortedSetEntry {
 
 type Keyed<A> = collections::HashMap<String, A>;
 
-fn new_keyed<A>() -> Keyed<A> { collections::HashMap::new() }
+/* A persistent map of shared values: a copy costs next to nothing, and a
+   value is only copied should it be written to while the copy is still
+   around, as when saving in the background. */
+type Keyspace = imbl::HashMap<String, sync::Arc<keyspace::Value>>;
 
-#[derive(Deserialize, Serialize)]
+/* What SCAN goes by is not in snapshots; it is built up again from the
+   keys as they are read. */
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "StoredDatasets")]
 pub struct Datasets {
-    pub lists:       Keyed<collections::VecDeque<String>>,
-    pub strings:     Keyed<String>,
-    pub sorted_sets: Keyed<domain::sorted_sets::OrderedScores>,
-    revision:        tx_log::Revision,
+    keyspace:  Keyspace,
+    revision:  tx_log::Revision,
+    #[serde(skip)]
+    positions: cursors::Index,
+}
+
+#[derive(Deserialize)]
+struct StoredDatasets {
+    keyspace: Keyspace,
+    revision: tx_log::Revision,
+}
+
+impl From<StoredDatasets> for Datasets {
+    fn from(stored: StoredDatasets) -> Self {
+        Self::with_keyspace(stored.keyspace, stored.revision)
+    }
 }
 
 impl Default for Datasets {
@@ -129,10 +793,15 @@ impl Default for Datasets {
 
 impl Datasets {
     pub fn new() -> Self {
-        Self { lists:       new_keyed(),
-               strings:     new_keyed(),
-               sorted_sets: new_keyed(),
-               revision:    tx_log::Revision::default() }
+        Self::with_keyspace(Keyspace::new(), tx_log::Revision::default())
+    }
+
+    fn with_keyspace(keyspace: Keyspace, revision: tx_log::Revision) -> Self {
+        let mut positions = cursors::Index::default();
+        for key in keyspace.keys() {
+            positions.insert(key);
+        }
+        Self { keyspace, revision, positions }
     }
 
     pub fn revision(&self) -> tx_log::Revision { self.revision.clone() }
@@ -141,12 +810,146 @@ impl Datasets {
         self.revision = self.revision().succeeding();
     }
 
+    pub fn set_revision(&mut self, revision: tx_log::Revision) {
+        self.revision = revision;
+    }
+
+    /* Every key goes; the revision stays. */
+    fn clear(&mut self) {
+        self.keyspace.clear();
+        self.positions.clear();
+    }
+
     pub fn keys(&self) -> impl Iterator<Item = &String> {
-        self.lists.keys().chain(
-            self.strings.keys().chain(
-                self.sorted_sets.keys()
-            )
-        )
+        self.keyspace.keys()
+    }
+
+    /* For SCAN, in order of position. */
+    pub fn positions(&self) -> &cursors::Index {
+        &self.positions
+    }
+
+    pub fn value(&self, key: &str) -> Option<&keyspace::Value> {
+        self.keyspace.get(key).map(Deref::deref)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.keyspace.contains_key(key)
+    }
+
+    /* Whatever was there before, of whatever type, is replaced. */
+    pub fn insert<A: keyspace::Kind>(&mut self, key: &str, value: A) {
+        self.put(key, sync::Arc::new(value.wrap()));
+    }
+
+    pub fn remove_key(&mut self, key: &str) -> bool {
+        self.take(key).is_some()
+    }
+
+    /* Moves the value at `from` to `to`, replacing anything already there. */
+    pub fn rename_key(&mut self, from: &str, to: &str) -> bool {
+        if let Some(value) = self.take(from) {
+            self.put(to, value);
+            true
+        } else {
+            false
+        }
+    }
+
+    /* Like rename_key, but leaves `from` in place. */
+    pub fn copy_key(&mut self, from: &str, to: &str) -> bool {
+        match self.keyspace.get(from).cloned() {
+            Some(value) if from != to => {
+                self.put(to, value);
+                true
+            },
+            _otherwise =>
+                false,
+        }
+    }
+
+    /* None for a missing key, WrongType for a key of some other type. */
+    pub fn typed<A: keyspace::Kind>(&self, key: &str) -> keyspace::Typed<Option<&A>> {
+        self.keyspace.get(key)
+            .map(|value| A::cast(value).ok_or(keyspace::WrongType))
+            .transpose()
+    }
+
+    /* Creates an empty A if there is nothing at `key`; who ever does that
+       has to call remove_if_empty afterwards. */
+    pub fn typed_or_default<A>(&mut self, key: &str) -> keyspace::Typed<&mut A>
+    where
+        A: keyspace::Kind + Default
+    {
+        if !self.keyspace.contains_key(key) {
+            self.positions.insert(key);
+        }
+        let value = self.keyspace.entry(key.to_string()).or_insert_with(|| sync::Arc::new(A::default().wrap()));
+        /* Before make_mut, which would copy a shared value only to refuse it. */
+        A::cast(value).ok_or(keyspace::WrongType)?;
+        A::cast_mut(sync::Arc::make_mut(value)).ok_or(keyspace::WrongType)
+    }
+
+    /* Applies `update` to an existing A, deleting the key should that leave
+       it empty. A missing key yields the default. */
+    pub fn update<A, R, F>(&mut self, key: &str, update: F) -> keyspace::Typed<R>
+    where
+        A: keyspace::Kind,
+        R: Default,
+        F: FnOnce(&mut A) -> R,
+    {
+        let Some(value) = self.keyspace.get_mut(key) else { return Ok(R::default()) };
+        A::cast(value).ok_or(keyspace::WrongType)?;
+        let updated = update(A::cast_mut(sync::Arc::make_mut(value)).ok_or(keyspace::WrongType)?);
+        self.remove_if_empty(key);
+        Ok(updated)
+    }
+
+    pub fn remove_if_empty(&mut self, key: &str) {
+        if self.value(key).is_some_and(keyspace::Value::is_empty) {
+            self.take(key);
+        }
+    }
+
+    /* The keyspace and the positions of its keys change together, here. */
+    fn put(&mut self, key: &str, value: sync::Arc<keyspace::Value>) {
+        self.positions.insert(key);
+        self.keyspace.insert(key.to_string(), value);
+    }
+
+    fn take(&mut self, key: &str) -> Option<sync::Arc<keyspace::Value>> {
+        self.positions.remove(key);
+        self.keyspace.remove(key)
+    }
+}
+
+/* Snapshots from before the keyspace was unified kept one map per type. */
+#[derive(Deserialize, Serialize)]
+pub struct LegacyDatasets {
+    lists:       Keyed<collections::VecDeque<String>>,
+    strings:     Keyed<String>,
+    sorted_sets: Keyed<domain::sorted_sets::ScoreTables>,
+    revision:    tx_log::Revision,
+}
+
+impl ttl::Expungeable for LegacyDatasets {
+    fn expunge(&mut self, id: &str) {
+        self.lists.remove(id);
+        self.strings.remove(id);
+        self.sorted_sets.remove(id);
+    }
+}
+
+/* Should a key have been in more than one map, the later kind wins. */
+impl From<LegacyDatasets> for Datasets {
+    fn from(legacy: LegacyDatasets) -> Self {
+        let keyspace = legacy.lists.into_iter()
+            .map(|(key, xs)| (key, keyspace::Kind::wrap(xs)))
+            .chain(legacy.strings.into_iter().map(|(key, x)| (key, keyspace::Kind::wrap(x))))
+            .chain(legacy.sorted_sets.into_iter().map(|(key, xs)| (key, keyspace::Kind::wrap(domain::sorted_sets::OrderedScores::from(xs)))))
+            .map(|(key, value)| (key, sync::Arc::new(value)))
+            .collect();
+        Self::with_keyspace(keyspace, legacy.revision)
     }
 }
 
@@ -183,6 +986,13 @@ impl <'a> TryFrom<&'a Message> for CommandContext<'a, Command> {
 
 impl Executive for StateContext {
     fn apply(&self, command: CommandContext<Command>) -> io::Result<Message> {
+        self.expunge_due()?;
+        self.dispatch(command).or_else(keyspace::WrongType::recover)
+    }
+}
+
+impl StateContext {
+    fn dispatch(&self, command: CommandContext<Command>) -> io::Result<Message> {
         match &*command {
             Command::Lists(sub_command) =>
                 lists::apply(self, CommandContext::new(sub_command.clone(), command.transaction_message())),
@@ -193,9 +1003,14 @@ impl Executive for StateContext {
             Command::Generic(ref sub_command) =>
                 generic::apply(self, CommandContext::new(sub_command.clone(), command.transaction_message())),
             Command::ConnectionManagement(ref sub_command) =>
-                connections::apply(self, &sub_command),
+                connections::apply(self, sub_command),
             Command::ServerManagement(ref sub_command) =>
-                server::apply(self, &sub_command),
+                server::apply(self, sub_command),
+            Command::PubSub(_) =>
+                Ok(Message::Error {
+                    prefix: ErrorPrefix::Err,
+                    message: "Pub/sub commands need a client connection.".to_string(),
+                }),
             Command::Unknown(ref name) =>
                 Ok(Message::Error {
                     prefix: ErrorPrefix::Err,
@@ -216,6 +1031,7 @@ impl RunLoop {
     }
 
     pub fn execute(&self) -> io::Result<()> {
+        self.state.spawn_background_cycle();
         let listener = self.listener.try_clone()?;
         for connection in listener.incoming() {
             match connection {
@@ -231,23 +1047,209 @@ impl RunLoop {
 
     fn handle_connection(state: StateContext, connection: net::TcpStream) -> io::Result<()> {
         let mut reader = io::BufReader::new(&connection);
-        let mut writer = io::BufWriter::new(&connection);
+        let (outbox, replies) = mpsc::channel();
+        let writer = connection.try_clone()?;
+        thread::spawn(move || Self::deliver_replies(writer, replies));
+
+        let mut subscriber = state.subscriber(outbox);
         loop {
             let message = read_message(&mut reader)?;
             let command = CommandContext::try_from(&message)?;
-            let response = state.apply(command)?;
+            let responses = if let Command::PubSub(sub_command) = &*command {
+                pubsub::apply(&state, &mut subscriber, sub_command)?
+            } else {
+                vec![state.apply(command)?]
+            };
+
+            for response in responses {
+                println!("handle_request: responding with `{response}`.");
+                subscriber.reply(response)?;
+            }
+        }
+    }
 
-            println!("handle_request: responding with `{response}`.");
-            writer.write_all(String::from(response).as_bytes())?;
+    /* Pushed pub/sub messages arrive here too, which is why writing
+       happens on a thread of its own. */
+    fn deliver_replies(connection: net::TcpStream, replies: mpsc::Receiver<Message>) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(connection);
+        for reply in replies {
+            writer.write_all(String::from(reply).as_bytes())?;
             writer.flush()?;
         }
+        Ok(())
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    /* How is this tested? */
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs;
+    use rand::{distributions::Alphanumeric, Rng};
+
+    fn temp_directory() -> path::PathBuf {
+        let name = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(25)
+            .map(char::from)
+            .collect::<String>();
+        temp_dir().join(name)
+    }
+
+    fn open(directory: &path::Path, clock: &sync::Arc<clock::ManualClock>) -> StateContext {
+        let data = tx_log::LoggedTransactions::in_directory(directory, Lifetimes::new(Datasets::default())).unwrap();
+        StateContext::new(data).with_clock(clock.clone())
+    }
+
+    fn run(state: &StateContext, words: &[&str]) -> Message {
+        let message = Message::make_bulk_array(&words.iter().map(|word| word.to_string()).collect::<Vec<_>>());
+        state.apply(CommandContext::try_from(&message).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn point_in_time() {
+        let began = time::UNIX_EPOCH + time::Duration::from_secs(1_000);
+        let clock = sync::Arc::new(clock::ManualClock::new(began));
+        let from = temp_directory();
+        let mut state = open(&from, &clock);
+        assert_eq!(state.saves().unwrap().last_save(), began);
+        state.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        /* All of the log stays, from the very first revision. */
+        state.reconfigure().unwrap().snapshot_retention = 0;
+        for words in [["SET", "a", "1"], ["SET", "a", "2"], ["DEL", "a", "b"]] {
+            run(&state, &words);
+            clock.advance(time::Duration::from_secs(10));
+            if words[2] == "1" {
+                assert!(state.save().unwrap());
+            }
+        }
+
+        let mut into = vec![];
+        let mut restore = |point| {
+            into.push(temp_directory());
+            let mut restored = open(into.last().unwrap(), &clock);
+            restored.restore_point_in_time(&from, &point).map(|_| run(&restored, &["GET", "a"]))
+        };
+        let until = |revision: usize| tx_log::PointInTime::Revision(revision.into());
+        let before = |seconds: u64| tx_log::PointInTime::Time(began + time::Duration::from_secs(seconds));
+        assert_eq!(restore(until(0)).unwrap(), Message::Nil);
+        assert_eq!(restore(until(1)).unwrap(), Message::BulkString("1".to_string()));
+        assert_eq!(restore(until(2)).unwrap(), Message::BulkString("2".to_string()));
+        assert_eq!(restore(until(3)).unwrap(), Message::Nil);
+        assert!(restore(until(4)).is_err());
+        assert_eq!(restore(before(15)).unwrap(), Message::BulkString("2".to_string()));
+        assert_eq!(restore(before(60)).unwrap(), Message::Nil);
+
+        /* What was restored is there to start from, and cannot be restored
+           over. */
+        let mut restarted = open(&into[2], &clock);
+        restarted.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        assert_eq!(run(&restarted, &["GET", "a"]), Message::BulkString("2".to_string()));
+        assert_eq!(
+            restarted.restore_point_in_time(&from, &until(1)).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        for directory in into.iter().chain([&from]) {
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn torn_source_logs_are_left_alone() {
+        let began = time::UNIX_EPOCH + time::Duration::from_secs(1_000);
+        let clock = sync::Arc::new(clock::ManualClock::new(began));
+        let from = temp_directory();
+        let mut state = open(&from, &clock);
+        state.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        for value in ["1", "2", "3"] {
+            run(&state, &["SET", "a", value]);
+        }
+        let source = from.join(tx_log::LOG_FILE);
+        let mut bytes = fs::read(&source).unwrap();
+        bytes.extend([5, 0, 0]);
+        fs::write(&source, &bytes).unwrap();
+
+        let into = temp_directory();
+        let mut restored = open(&into, &clock);
+        let after = tx_log::PointInTime::Time(began + time::Duration::from_secs(60));
+        restored.restore_point_in_time(&from, &after).unwrap();
+        assert_eq!(run(&restored, &["GET", "a"]), Message::BulkString("3".to_string()));
+        assert_eq!(fs::read(&source).unwrap(), bytes);
+        assert!(!from.join("transactions.log.discarded").exists() && !into.join("transactions.log.discarded").exists());
+
+        let mut restarted = open(&into, &clock);
+        restarted.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        assert_eq!(run(&restarted, &["GET", "a"]), Message::BulkString("3".to_string()));
+        for directory in [from, into] {
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn copies_share_values_until_written() {
+        let mut data = Lifetimes::new(Datasets::new());
+        data.insert("a", "1".to_string());
+        data.insert("b", "2".to_string());
+        let copy = data.clone();
+        assert!(std::ptr::eq(data.value("a").unwrap(), copy.value("a").unwrap()));
+        assert!(data.update("a", |x: &mut collections::VecDeque<String>| x.len()).is_err());
+        assert!(data.typed_or_default::<collections::VecDeque<String>>("a").is_err());
+        assert!(std::ptr::eq(data.value("a").unwrap(), copy.value("a").unwrap()));
+
+        data.update("a", |x: &mut String| x.push('0')).unwrap();
+        data.remove_key("b");
+        assert_eq!(copy.typed::<String>("a").unwrap(), Some(&"1".to_string()));
+        assert_eq!(data.typed::<String>("a").unwrap(), Some(&"10".to_string()));
+        assert!(copy.contains_key("b") && !data.contains_key("b"));
+    }
+
+    #[test]
+    fn failed_renames_are_not_logged() {
+        let clock = sync::Arc::new(clock::ManualClock::new(time::UNIX_EPOCH + time::Duration::from_secs(1_000)));
+        let directory = temp_directory();
+        let mut state = open(&directory, &clock);
+        state.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        run(&state, &["SET", "a", "1"]);
+        run(&state, &["SET", "b", "2"]);
+        assert!(matches!(run(&state, &["RENAME", "missing", "c"]), Message::Error { .. }));
+        assert_eq!(run(&state, &["RENAMENX", "a", "b"]), Message::Integer(0));
+        assert_eq!(run(&state, &["COPY", "a", "b"]), Message::Integer(0));
+        assert_eq!(state.begin_reading().unwrap().revision(), 2.into());
+
+        assert_eq!(run(&state, &["RENAMENX", "a", "c"]), Message::Integer(1));
+        assert_eq!(state.begin_reading().unwrap().revision(), 3.into());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn retains_snapshots() {
+        let clock = sync::Arc::new(clock::ManualClock::new(time::UNIX_EPOCH + time::Duration::from_secs(1_000)));
+        let directory = temp_directory();
+        let mut state = open(&directory, &clock);
+        state.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        state.reconfigure().unwrap().snapshot_retention = 2;
+        for value in ["1", "2", "3"] {
+            run(&state, &["SET", "a", value]);
+            assert!(state.save().unwrap());
+        }
+        run(&state, &["SET", "a", "4"]);
+
+        let snapshots = fs::read_dir(&directory).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with("snapshot-"))
+            .count();
+        assert_eq!(snapshots, 2);
+        /* Only what came after the older of the two. */
+        let log = tx_log::ReplayView::open(&directory.join(tx_log::LOG_FILE), &tx_log::Revision::default()).unwrap();
+        assert_eq!(log.iter().map(|entry| entry.unwrap().0).collect::<Vec<_>>(), vec![2.into(), 3.into()]);
+
+        let mut restarted = open(&directory, &clock);
+        restarted.restore_from_disk(tx_log::RestoreMode::Strict).unwrap();
+        assert_eq!(run(&restarted, &["GET", "a"]), Message::BulkString("4".to_string()));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
 
 //    #[test]
 //    fn questionable_syntax() {
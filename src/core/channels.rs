@@ -0,0 +1,194 @@
+use std::collections;
+use std::io;
+use std::sync;
+use std::sync::atomic;
+use std::sync::mpsc;
+
+use crate::core::resp::Message;
+use crate::globs;
+
+type Outbox = mpsc::Sender<Message>;
+type Subscribers = collections::HashMap<usize, Outbox>;
+
+/* Who listens to what, shared by every connection. Delivery is a send on
+   the subscriber's outbox, so publishing never waits on a socket. */
+#[derive(Default)]
+pub struct Channels {
+    by_name:    collections::HashMap<String, Subscribers>,
+    by_pattern: collections::HashMap<String, Subscribers>,
+    /* Compiled once per pattern, not once per message. */
+    globs:      collections::HashMap<String, globs::Glob>,
+}
+
+impl Channels {
+    pub fn publish(&self, channel: &str, payload: &str) -> usize {
+        fn deliver(subscribers: &Subscribers, message: &Message) -> usize {
+            subscribers.values()
+                .filter(|outbox| outbox.send(message.clone()).is_ok())
+                .count()
+        }
+
+        let by_name = self.by_name.get(channel).map_or(0, |subscribers| {
+            let message = Message::make_bulk_array(&[
+                "message".to_string(), channel.to_string(), payload.to_string(),
+            ]);
+            deliver(subscribers, &message)
+        });
+
+        let by_pattern = self.by_pattern.iter()
+            .filter(|(pattern, _)| self.globs.get(*pattern).is_some_and(|g| g.matches(channel)))
+            .map(|(pattern, subscribers)| {
+                let message = Message::make_bulk_array(&[
+                    "pmessage".to_string(), pattern.to_string(), channel.to_string(), payload.to_string(),
+                ]);
+                deliver(subscribers, &message)
+            })
+            .sum::<usize>();
+
+        by_name + by_pattern
+    }
+
+    fn subscribe(registry: &mut collections::HashMap<String, Subscribers>, name: &str, id: usize, outbox: &Outbox) {
+        registry.entry(name.to_string())
+            .or_default()
+            .insert(id, outbox.clone());
+    }
+
+    fn subscribe_pattern(&mut self, pattern: &str, id: usize, outbox: &Outbox) {
+        Self::subscribe(&mut self.by_pattern, pattern, id, outbox);
+        self.globs.entry(pattern.to_string())
+            .or_insert_with(|| globs::Glob::new(pattern));
+    }
+
+    fn unsubscribe_pattern(&mut self, pattern: &str, id: usize) {
+        Self::unsubscribe(&mut self.by_pattern, pattern, id);
+        if !self.by_pattern.contains_key(pattern) {
+            self.globs.remove(pattern);
+        }
+    }
+
+    fn unsubscribe(registry: &mut collections::HashMap<String, Subscribers>, name: &str, id: usize) {
+        if let collections::hash_map::Entry::Occupied(mut subscribers) = registry.entry(name.to_string()) {
+            subscribers.get_mut().remove(&id);
+            if subscribers.get().is_empty() {
+                subscribers.remove_entry();
+            }
+        }
+    }
+}
+
+static NEXT_SUBSCRIBER_ID: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+/* One per connection. Replies and pushed messages share the outbox so that
+   they reach the client in the order they were produced. */
+pub struct Subscriber {
+    id:       usize,
+    outbox:   Outbox,
+    channels: sync::Arc<sync::Mutex<Channels>>,
+    names:    collections::BTreeSet<String>,
+    patterns: collections::BTreeSet<String>,
+}
+
+impl Subscriber {
+    pub fn new(channels: sync::Arc<sync::Mutex<Channels>>, outbox: Outbox) -> Self {
+        Self {
+            id: NEXT_SUBSCRIBER_ID.fetch_add(1, atomic::Ordering::Relaxed),
+            outbox,
+            channels,
+            names:    collections::BTreeSet::new(),
+            patterns: collections::BTreeSet::new(),
+        }
+    }
+
+    pub fn reply(&self, message: Message) -> io::Result<()> {
+        self.outbox.send(message).map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
+    }
+
+    pub fn subscription_count(&self) -> usize {
+        self.names.len() + self.patterns.len()
+    }
+
+    pub fn channel_names(&self) -> Vec<String> {
+        self.names.iter().cloned().collect()
+    }
+
+    pub fn channel_patterns(&self) -> Vec<String> {
+        self.patterns.iter().cloned().collect()
+    }
+
+    pub fn subscribe(&mut self, name: &str) -> io::Result<usize> {
+        Channels::subscribe(&mut self.registry()?.by_name, name, self.id, &self.outbox);
+        self.names.insert(name.to_string());
+        Ok(self.subscription_count())
+    }
+
+    pub fn unsubscribe(&mut self, name: &str) -> io::Result<usize> {
+        Channels::unsubscribe(&mut self.registry()?.by_name, name, self.id);
+        self.names.remove(name);
+        Ok(self.subscription_count())
+    }
+
+    pub fn subscribe_pattern(&mut self, pattern: &str) -> io::Result<usize> {
+        self.registry()?.subscribe_pattern(pattern, self.id, &self.outbox);
+        self.patterns.insert(pattern.to_string());
+        Ok(self.subscription_count())
+    }
+
+    pub fn unsubscribe_pattern(&mut self, pattern: &str) -> io::Result<usize> {
+        self.registry()?.unsubscribe_pattern(pattern, self.id);
+        self.patterns.remove(pattern);
+        Ok(self.subscription_count())
+    }
+
+    fn registry(&self) -> io::Result<sync::MutexGuard<'_, Channels>> {
+        self.channels.lock().map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        if let Ok(mut channels) = self.channels.lock() {
+            for name in &self.names {
+                Channels::unsubscribe(&mut channels.by_name, name, self.id);
+            }
+            for pattern in &self.patterns {
+                channels.unsubscribe_pattern(pattern, self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(words: &[&str]) -> Message {
+        Message::make_bulk_array(&words.iter().map(|&s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn publish_and_unsubscribe() {
+        let channels = sync::Arc::new(sync::Mutex::new(Channels::default()));
+        let (outbox, inbox) = mpsc::channel();
+        let mut subscriber = Subscriber::new(channels.clone(), outbox);
+
+        assert_eq!(subscriber.subscribe("news").unwrap(), 1);
+        assert_eq!(subscriber.subscribe_pattern("new*").unwrap(), 2);
+        assert_eq!(channels.lock().unwrap().publish("news", "hello"), 2);
+        assert_eq!(channels.lock().unwrap().publish("weather", "sunny"), 0);
+        assert_eq!(
+            inbox.try_iter().collect::<Vec<_>>(),
+            vec![
+                message(&["message", "news", "hello"]),
+                message(&["pmessage", "new*", "news", "hello"]),
+            ]
+        );
+
+        assert_eq!(subscriber.unsubscribe("news").unwrap(), 1);
+        assert_eq!(channels.lock().unwrap().publish("news", "again"), 1);
+
+        drop(subscriber);
+        assert_eq!(channels.lock().unwrap().publish("news", "gone"), 0);
+        assert!(channels.lock().unwrap().by_pattern.is_empty());
+    }
+}
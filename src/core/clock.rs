@@ -0,0 +1,87 @@
+use std::sync::atomic;
+use std::time;
+
+/* Where expiry, and the transaction log, get the time from. The wall clock
+   is read once, at the start, and then runs on like a stopwatch: setting
+   the system clock neither brings keys back nor expires them early. */
+pub trait Clock: Send + Sync {
+    fn now(&self) -> time::SystemTime;
+    fn instant(&self) -> time::Instant;
+
+    /* Only a manual clock can be told what time it is. */
+    fn as_manual(&self) -> Option<&ManualClock> { None }
+}
+
+pub struct MonotonicClock {
+    started_at: time::SystemTime,
+    started:    time::Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self { started_at: time::SystemTime::now(), started: time::Instant::now() }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self { Self::new() }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> time::SystemTime {
+        self.started_at + self.started.elapsed()
+    }
+
+    fn instant(&self) -> time::Instant {
+        time::Instant::now()
+    }
+}
+
+/* Stands still until advanced, for tests and DEBUG ADVANCE-CLOCK. */
+pub struct ManualClock {
+    started_at: time::SystemTime,
+    started:    time::Instant,
+    elapsed:    atomic::AtomicU64,  /* Nanoseconds. */
+}
+
+impl ManualClock {
+    pub fn new(started_at: time::SystemTime) -> Self {
+        Self { started_at, started: time::Instant::now(), elapsed: atomic::AtomicU64::default() }
+    }
+
+    pub fn advance(&self, by: time::Duration) {
+        self.elapsed.fetch_add(by.as_nanos() as u64, atomic::Ordering::SeqCst);
+    }
+
+    fn elapsed(&self) -> time::Duration {
+        time::Duration::from_nanos(self.elapsed.load(atomic::Ordering::SeqCst))
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> time::SystemTime {
+        self.started_at + self.elapsed()
+    }
+
+    fn instant(&self) -> time::Instant {
+        self.started + self.elapsed()
+    }
+
+    fn as_manual(&self) -> Option<&ManualClock> { Some(self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new(time::UNIX_EPOCH);
+        let instant = clock.instant();
+        assert_eq!(clock.now(), time::UNIX_EPOCH);
+        clock.advance(time::Duration::from_millis(1500));
+        assert_eq!(clock.now(), time::UNIX_EPOCH + time::Duration::from_millis(1500));
+        assert_eq!(clock.instant() - instant, time::Duration::from_millis(1500));
+        assert!(MonotonicClock::new().as_manual().is_none());
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use std::str;
+
+use crate::core::notifications;
+use crate::globs;
+
+/* When the transaction log goes to disk: before each write is answered,
+   about once a second, or whenever the operating system sees fit. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AppendFsync {
+    Always, EverySecond, No,
+}
+
+impl str::FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(image: &str) -> Result<Self, Self::Err> {
+        match image.to_lowercase().as_str() {
+            "always"   => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySecond),
+            "no"       => Ok(AppendFsync::No),
+            _otherwise => Err("argument(s) must be one of the following: always, everysec, no".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for AppendFsync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppendFsync::Always      => write!(f, "always"),
+            AppendFsync::EverySecond => write!(f, "everysec"),
+            AppendFsync::No          => write!(f, "no"),
+        }
+    }
+}
+
+/* How snapshots are compressed as a whole: LZ4 is cheap, zstd smaller. */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    No, #[default] Lz4, Zstd,
+}
+
+impl str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(image: &str) -> Result<Self, Self::Err> {
+        match image.to_lowercase().as_str() {
+            "no"       => Ok(Compression::No),
+            "lz4"      => Ok(Compression::Lz4),
+            "zstd"     => Ok(Compression::Zstd),
+            _otherwise => Err("argument(s) must be one of the following: no, lz4, zstd".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::No   => write!(f, "no"),
+            Compression::Lz4  => write!(f, "lz4"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/* `save 3600 1 300 100`: a snapshot once an hour has passed with a change
+   made, or five minutes with a hundred. Empty, there are none. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveRules(pub Vec<(u64, usize)>);
+
+impl Default for SaveRules {
+    fn default() -> Self {
+        Self(vec![(3600, 1), (300, 100), (60, 10000)])
+    }
+}
+
+impl str::FromStr for SaveRules {
+    type Err = String;
+
+    fn from_str(image: &str) -> Result<Self, Self::Err> {
+        let numbers = image.split_whitespace()
+            .map(|number| number.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "Invalid save parameters".to_string())?;
+        match numbers.chunks_exact(2) {
+            pairs if pairs.remainder().is_empty() =>
+                Ok(Self(pairs.map(|pair| (pair[0], pair[1] as usize)).collect())),
+            _otherwise =>
+                Err("Invalid save parameters".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SaveRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(rules) = self;
+        let numbers = rules.iter().map(|(seconds, changes)| format!("{seconds} {changes}")).collect::<Vec<_>>();
+        write!(f, "{}", numbers.join(" "))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Configuration {
+    pub notify_keyspace_events:        notifications::KeyspaceEvents,
+    /* The transaction log is rewritten once it has grown by this much since
+       the last time, and is at least so big. Zero percent never does. */
+    pub auto_aof_rewrite_percentage:   u64,
+    pub auto_aof_rewrite_min_size:     u64,
+    pub appendfsync:                   AppendFsync,
+    pub save:                          SaveRules,
+    /* Older snapshots are deleted, and the log before the oldest kept is
+       cut away. Zero keeps everything. */
+    pub snapshot_retention:            usize,
+    pub snapshot_compression:          Compression,
+    /* String values at least this long are compressed one by one in
+       snapshots as well. Zero leaves them be. */
+    pub snapshot_compress_values_over: u64,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            notify_keyspace_events:        notifications::KeyspaceEvents::default(),
+            auto_aof_rewrite_percentage:   100,
+            auto_aof_rewrite_min_size:     64 * 1024 * 1024,
+            appendfsync:                   AppendFsync::EverySecond,
+            save:                          SaveRules::default(),
+            snapshot_retention:            5,
+            snapshot_compression:          Compression::default(),
+            snapshot_compress_values_over: 0,
+        }
+    }
+}
+
+/* A size in bytes, as in `64mb`: k, m and g count in thousands, kb, mb and
+   gb in multiples of 1024. */
+fn parse_memory(image: &str) -> Result<u64, String> {
+    let image = image.to_lowercase();
+    let digits = image.find(|c: char| !c.is_ascii_digit()).unwrap_or(image.len());
+    let (amount, unit) = image.split_at(digits);
+    let multiplier = match unit {
+        ""   | "b" => 1,
+        "k"        => 1000,
+        "kb"       => 1024,
+        "m"        => 1000 * 1000,
+        "mb"       => 1024 * 1024,
+        "g"        => 1000 * 1000 * 1000,
+        "gb"       => 1024 * 1024 * 1024,
+        _otherwise => return Err("argument must be a memory value".to_string()),
+    };
+    amount.parse::<u64>().ok()
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+impl Configuration {
+    const PARAMETERS: [&'static str; 8] = [
+        "notify-keyspace-events",
+        "auto-aof-rewrite-percentage",
+        "auto-aof-rewrite-min-size",
+        "appendfsync",
+        "save",
+        "snapshot-retention",
+        "snapshot-compression",
+        "snapshot-compress-values-over",
+    ];
+
+    /* CONFIG GET takes a glob, so answer with every parameter it matches. */
+    pub fn get(&self, pattern: &str) -> Vec<(String, String)> {
+        let pattern = pattern.to_lowercase();
+        let glob = globs::Glob::new(&pattern);
+        Self::PARAMETERS.iter()
+            .filter(|&&name| glob.matches(name))
+            .filter_map(|&name| Some((name.to_string(), self.get_parameter(name)?)))
+            .collect()
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "notify-keyspace-events"        => Some(self.notify_keyspace_events.to_string()),
+            "auto-aof-rewrite-percentage"   => Some(self.auto_aof_rewrite_percentage.to_string()),
+            "auto-aof-rewrite-min-size"     => Some(self.auto_aof_rewrite_min_size.to_string()),
+            "appendfsync"                   => Some(self.appendfsync.to_string()),
+            "save"                          => Some(self.save.to_string()),
+            "snapshot-retention"            => Some(self.snapshot_retention.to_string()),
+            "snapshot-compression"          => Some(self.snapshot_compression.to_string()),
+            "snapshot-compress-values-over" => Some(self.snapshot_compress_values_over.to_string()),
+            _otherwise                      => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = |reason: String| format!("Invalid argument '{value}' for CONFIG SET '{name}' - {reason}");
+        match name.to_lowercase().as_str() {
+            "notify-keyspace-events" =>
+                self.notify_keyspace_events = value.parse().map_err(invalid)?,
+            "auto-aof-rewrite-percentage" =>
+                self.auto_aof_rewrite_percentage = value.parse()
+                    .map_err(|_| invalid("argument couldn't be parsed into an integer".to_string()))?,
+            "auto-aof-rewrite-min-size" =>
+                self.auto_aof_rewrite_min_size = parse_memory(value).map_err(invalid)?,
+            "appendfsync" =>
+                self.appendfsync = value.parse().map_err(invalid)?,
+            "save" =>
+                self.save = value.parse().map_err(invalid)?,
+            "snapshot-retention" =>
+                self.snapshot_retention = value.parse()
+                    .map_err(|_| invalid("argument couldn't be parsed into an integer".to_string()))?,
+            "snapshot-compression" =>
+                self.snapshot_compression = value.parse().map_err(invalid)?,
+            "snapshot-compress-values-over" =>
+                self.snapshot_compress_values_over = parse_memory(value).map_err(invalid)?,
+            _otherwise =>
+                return Err(format!("Unknown option or number of arguments for CONFIG SET - '{name}'")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_and_set() {
+        let mut config = Configuration::default();
+        assert_eq!(
+            config.get("notify-keyspace-events"),
+            vec![("notify-keyspace-events".to_string(), "".to_string())]
+        );
+        config.set("NOTIFY-KEYSPACE-EVENTS", "Ex").unwrap();
+        assert_eq!(
+            config.get("notify-*"),
+            vec![("notify-keyspace-events".to_string(), "xE".to_string())]
+        );
+        assert!(config.set("notify-keyspace-events", "?").is_err());
+        assert!(config.set("no-such-thing", "1").is_err());
+        assert!(config.get("no-such-*").is_empty());
+
+        config.set("auto-aof-rewrite-min-size", "2mb").unwrap();
+        config.set("auto-aof-rewrite-percentage", "50").unwrap();
+        assert_eq!(
+            config.get("auto-aof-*"),
+            vec![
+                ("auto-aof-rewrite-percentage".to_string(), "50".to_string()),
+                ("auto-aof-rewrite-min-size".to_string(), "2097152".to_string()),
+            ]
+        );
+        assert_eq!(parse_memory("64K"), Ok(64000));
+        assert!(config.set("auto-aof-rewrite-min-size", "lots").is_err());
+        assert!(config.set("auto-aof-rewrite-percentage", "-1").is_err());
+
+        assert_eq!(config.get("appendfsync"), vec![("appendfsync".to_string(), "everysec".to_string())]);
+        config.set("appendfsync", "ALWAYS").unwrap();
+        assert_eq!(config.appendfsync, AppendFsync::Always);
+        assert!(config.set("appendfsync", "sometimes").is_err());
+
+        assert_eq!(config.get("save"), vec![("save".to_string(), "3600 1 300 100 60 10000".to_string())]);
+        config.set("save", " 900  1 ").unwrap();
+        assert_eq!(config.save, SaveRules(vec![(900, 1)]));
+        config.set("save", "").unwrap();
+        assert_eq!(config.save, SaveRules(vec![]));
+        assert!(config.set("save", "900").is_err());
+        assert!(config.set("save", "900 -1").is_err());
+        config.set("snapshot-retention", "2").unwrap();
+        assert_eq!(config.get("snapshot-r*"), vec![("snapshot-retention".to_string(), "2".to_string())]);
+
+        assert_eq!(config.snapshot_compression, Compression::Lz4);
+        config.set("snapshot-compression", "ZSTD").unwrap();
+        assert_eq!(config.snapshot_compression, Compression::Zstd);
+        assert!(config.set("snapshot-compression", "gzip").is_err());
+        config.set("snapshot-compress-values-over", "1kb").unwrap();
+        assert_eq!(config.get("snapshot-compress-*"), vec![("snapshot-compress-values-over".to_string(), "1024".to_string())]);
+    }
+}
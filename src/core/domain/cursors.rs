@@ -0,0 +1,142 @@
+use crate::core::resp;
+
+/* SCAN and its relatives walk entries in order of a fixed 64 bit hash, and
+   the cursor is the hash to resume from. Since an entry's place never
+   changes, whatever is there for the whole scan is returned once, however
+   much is added or removed around it. Entries that come and go meanwhile
+   may or may not show up. Zero starts a scan and ends it. */
+pub struct Page<A> {
+    pub cursor: u64,
+    pub items:  Vec<A>,
+}
+
+pub const DEFAULT_COUNT: usize = 10;
+
+/* FNV-1a, which is the same in every run and with every Rust release, as
+   DefaultHasher is not promised to be: a cursor handed out before an
+   upgrade means the same after it. */
+fn position(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte|
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    )
+}
+
+/* Every name in a collection by its position, kept up to date as names
+   come and go, so that a page costs no more than what is on it. */
+#[derive(Clone, Default)]
+pub struct Index(imbl::OrdMap<u64, Vec<String>>);
+
+impl Index {
+    pub fn insert(&mut self, name: &str) {
+        let names = self.0.entry(position(name)).or_default();
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        let at = position(name);
+        if let Some(names) = self.0.get_mut(&at) {
+            names.retain(|n| n != name);
+            if names.is_empty() {
+                self.0.remove(&at);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /* The names from `cursor` on, `count` of them, that are `wanted`; as
+       with COUNT in Redis, those passed over count too. Names that share a
+       position go on the same page, so a page can run over. */
+    pub fn page<F>(&self, cursor: u64, count: usize, wanted: F) -> Page<&String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut visited = 0;
+        let mut items = vec![];
+        for (&at, names) in self.0.range(cursor..) {
+            if visited >= count.max(1) {
+                return Page { cursor: at, items }
+            }
+            visited += names.len();
+            items.extend(names.iter().filter(|name| wanted(name)));
+        }
+        Page { cursor: 0, items }
+    }
+}
+
+impl From<Page<String>> for resp::Message {
+    fn from(page: Page<String>) -> Self {
+        resp::Message::make_array(vec![
+            resp::Message::BulkString(page.cursor.to_string()),
+            resp::Message::make_bulk_array(&page.items),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections;
+
+    fn scan(items: &collections::HashSet<String>, count: usize, mut visit: impl FnMut(&mut Index)) -> Vec<String> {
+        let mut index = Index::default();
+        for item in items {
+            index.insert(item);
+        }
+        let mut seen = vec![];
+        let mut cursor = 0;
+        loop {
+            let page = index.page(cursor, count, |_| true);
+            let page = Page { cursor: page.cursor, items: page.items.into_iter().cloned().collect::<Vec<_>>() };
+            assert!(page.items.len() >= count.min(items.len()) || page.cursor == 0);
+            seen.extend(page.items);
+            if page.cursor == 0 {
+                break seen
+            }
+            cursor = page.cursor;
+            visit(&mut index);
+        }
+    }
+
+    #[test]
+    fn survives_growth_and_shrinkage() {
+        let stable = (0..500).map(|i| format!("key:{i}")).collect::<collections::HashSet<_>>();
+
+        let seen = scan(&stable, 7, |_| ());
+        assert_eq!(seen.len(), stable.len());
+        assert_eq!(seen.into_iter().collect::<collections::HashSet<_>>(), stable);
+
+        let mut round = 0;
+        let seen = scan(&stable, 10, |index| {
+            round += 1;
+            for i in 0..50 {
+                index.insert(&format!("new:{round}:{i}"));
+            }
+            for i in 0..50 {
+                index.remove(&format!("new:{}:{i}", round - 1));
+            }
+        }).into_iter().collect::<collections::HashSet<_>>();
+        assert!(stable.is_subset(&seen));
+    }
+
+    #[test]
+    fn positions_are_stable() {
+        assert_eq!(position(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(position("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(position("key:1"), 0x715a_daf2_962f_5ac9);
+
+        let mut index = Index::default();
+        index.insert("a");
+        index.insert("a");
+        index.insert("b");
+        let page = index.page(0, 10, |name| name != "b");
+        assert_eq!((page.cursor, page.items), (0, vec![&"a".to_string()]));
+        index.remove("a");
+        index.remove("a");
+        assert_eq!(index.page(0, 10, |_| true).items, vec![&"b".to_string()]);
+    }
+}
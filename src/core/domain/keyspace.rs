@@ -0,0 +1,174 @@
+use std::collections;
+use std::error;
+use std::fmt;
+use std::io;
+use serde::{Deserialize, Serialize};
+
+use crate::core::domain::sorted_sets::OrderedScores;
+use crate::core::{resp, snapshots};
+
+pub type Key = String;
+
+/* Every key holds exactly one of these. A skip list head is large, so
+   sorted sets are boxed to keep the rest small. */
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Value {
+    String(#[serde(with = "snapshots::compressed")] String),
+    List(collections::VecDeque<String>),
+    SortedSet(Box<OrderedScores>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_)    => "string",
+            Value::List(_)      => "list",
+            Value::SortedSet(_) => "zset",
+        }
+    }
+
+    /* The commands that make this from nothing, as in a rewritten log. Big
+       collections take a few, rather than one enormous one. */
+    pub fn reconstruction(&self, key: &str) -> Vec<resp::Message> {
+        const ELEMENTS_PER_COMMAND: usize = 64;
+        let command = |name: &str, arguments: Vec<String>| {
+            resp::Message::make_bulk_array(&[vec![name.to_string(), key.to_string()], arguments].concat())
+        };
+        match self {
+            Value::String(x) =>
+                vec![command("SET", vec![x.clone()])],
+            Value::List(xs) =>
+                xs.iter().cloned().collect::<Vec<_>>()
+                    .chunks(ELEMENTS_PER_COMMAND)
+                    .map(|elements| command("RPUSH", elements.to_vec()))
+                    .collect(),
+            Value::SortedSet(xs) =>
+                xs.entries().collect::<Vec<_>>()
+                    .chunks(ELEMENTS_PER_COMMAND)
+                    .map(|entries| command(
+                        "ZADD",
+                        entries.iter().flat_map(|(score, member)| [score.to_string(), member.to_string()]).collect()
+                    ))
+                    .collect(),
+        }
+    }
+
+    /* Collections go away with their last element; strings may be empty. */
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_)     => false,
+            Value::List(xs)      => xs.is_empty(),
+            Value::SortedSet(xs) => xs.is_empty(),
+        }
+    }
+}
+
+/* The Rust type behind each Value variant, so that access can be typed. */
+pub trait Kind: Sized {
+    fn cast(value: &Value) -> Option<&Self>;
+    fn cast_mut(value: &mut Value) -> Option<&mut Self>;
+    fn wrap(self) -> Value;
+}
+
+impl Kind for String {
+    fn cast(value: &Value) -> Option<&Self> {
+        if let Value::String(x) = value { Some(x) } else { None }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        if let Value::String(x) = value { Some(x) } else { None }
+    }
+
+    fn wrap(self) -> Value { Value::String(self) }
+}
+
+impl Kind for collections::VecDeque<String> {
+    fn cast(value: &Value) -> Option<&Self> {
+        if let Value::List(xs) = value { Some(xs) } else { None }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        if let Value::List(xs) = value { Some(xs) } else { None }
+    }
+
+    fn wrap(self) -> Value { Value::List(self) }
+}
+
+impl Kind for OrderedScores {
+    fn cast(value: &Value) -> Option<&Self> {
+        if let Value::SortedSet(xs) = value { Some(xs) } else { None }
+    }
+
+    fn cast_mut(value: &mut Value) -> Option<&mut Self> {
+        if let Value::SortedSet(xs) = value { Some(xs) } else { None }
+    }
+
+    fn wrap(self) -> Value { Value::SortedSet(Box::new(self)) }
+}
+
+/* A command hit a key holding some other type. It travels as the payload
+   of an io::Error, and Executive::apply makes a reply of it again. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WrongType;
+
+pub type Typed<A> = Result<A, WrongType>;
+
+impl WrongType {
+    pub fn recover(error: io::Error) -> io::Result<resp::Message> {
+        match error.get_ref().and_then(|e| e.downcast_ref::<WrongType>()) {
+            Some(_) => Ok(resp::Message::from(WrongType)),
+            None    => Err(error),
+        }
+    }
+}
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operation against a key holding the wrong kind of value")
+    }
+}
+
+impl error::Error for WrongType {}
+
+impl From<WrongType> for io::Error {
+    fn from(wrong_type: WrongType) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, wrong_type)
+    }
+}
+
+impl From<WrongType> for resp::Message {
+    fn from(wrong_type: WrongType) -> Self {
+        resp::Message::Error {
+            prefix:  resp::ErrorPrefix::Named("WRONGTYPE".to_string()),
+            message: wrong_type.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconstruction() {
+        let command = |words: &[&str]| resp::Message::make_bulk_array(
+            &words.iter().map(|word| word.to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!(Value::String("v".to_string()).reconstruction("k"), vec![command(&["SET", "k", "v"])]);
+
+        let list = Value::List((0..100).map(|i| i.to_string()).collect());
+        let commands = list.reconstruction("k");
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1].try_as_bulk_array().unwrap().len(), 2 + 36);
+    }
+
+    #[test]
+    fn wrong_type_round_trip() {
+        let reply = WrongType::recover(io::Error::from(WrongType)).unwrap();
+        assert_eq!(
+            String::from(reply),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert!(WrongType::recover(io::Error::other("disk on fire")).is_err());
+    }
+}
@@ -1,9 +1,9 @@
-use std::collections;
 use std::io;
 
 use crate::core;
+use crate::core::domain::keyspace::Typed;
+use crate::core::notifications::EventClass;
 use crate::core::resp;
-use std::time;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum StringsApi {
@@ -14,33 +14,24 @@ pub enum StringsApi {
 
 pub trait KeyValues {
     fn set(&mut self, key: &str, value: &str);
-    fn get(&self, key: &str) -> Result<String, io::Error>;
+    fn get(&self, key: &str) -> Typed<Option<String>>;
     fn mget(&self, keys: Vec<&str>) -> Vec<Option<String>>;
 }
 
-fn string_prefix(xs: &collections::VecDeque<String>) -> String {
-    xs.iter().take(5)
-      .map(ToString::to_string).collect::<Vec<_>>()
-      .join(",")
-}
-
 impl KeyValues for core::State {
+    /* Replaces a value of any type. */
     fn set(&mut self, key: &str, value: &str) {
-        self.strings.insert(key.to_string(), value.to_string());
-        /* These ought to be somewhere else, really. */
-        self.expunge_expired(&time::SystemTime::now());
+        self.insert(key, value.to_string());
     }
 
-    fn get(&self, key: &str) -> Result<String, io::Error> {
-        self.strings
-            .get(key).map(ToString::to_string)
-            .or_else(|| self.lists.get(key).map(string_prefix))
-            .ok_or(io::Error::new(io::ErrorKind::NotFound, key))
+    fn get(&self, key: &str) -> Typed<Option<String>> {
+        Ok(self.typed::<String>(key)?.cloned())
     }
 
+    /* Keys holding something other than a string are just missing here. */
     fn mget(&self, keys: Vec<&str>) -> Vec<Option<String>> {
         keys.iter()
-            .map(|key| self.get(key).ok())
+            .map(|key| self.get(key).ok().flatten())
             .collect()
     }
 }
@@ -51,15 +42,12 @@ pub fn apply(
 ) -> Result<resp::Message, io::Error> {
     match &*command {
         StringsApi::Set(key, value) => {
-            state.apply_transaction(&command, |data| {
-                data.set(key, value);
-                resp::Message::SimpleString("OK".to_string())
-            })
+            state.apply_transaction(&command, |data| data.set(key, value))?;
+            state.notify(EventClass::String, "set", key)?;
+            Ok(resp::Message::SimpleString("OK".to_string()))
         },
         StringsApi::Get(key) =>
-            Ok(resp::Message::BulkString(
-                state.begin_reading()?.get(key)?                
-            )),
+            Ok(state.begin_reading()?.get(key)?.map_or(resp::Message::Nil, resp::Message::BulkString)),
         StringsApi::Mget(keys) => {
             let keys = keys.iter().map(|s| s.as_str()).collect();
             let elements = state.begin_reading()?.mget(keys).into_iter().map(|value|
@@ -75,27 +63,31 @@ mod tests {
     use super::*;
     use crate::core;
     use crate::core::tx_log;
+    use crate::core::domain::keyspace::WrongType;
     use crate::core::domain::ttl;
-    use collections::VecDeque;
+    use std::collections::VecDeque;
 
     fn make_domain() -> Result<core::State, io::Error> {
-        Ok(tx_log::LoggedTransactions::new(
+        tx_log::LoggedTransactions::new(
             ttl::Lifetimes::new(core::Datasets::new())
-        )?)
+        )
     }
 
     #[test]
     fn set() {
         let mut st = make_domain().unwrap();
         st.set("apan:1", "value");
-        assert_eq!(st.strings.get("apan:1"), Some(&"value".to_string()));
-        assert_eq!(st.strings.len(), 1);
+        assert_eq!(st.typed::<String>("apan:1"), Ok(Some(&"value".to_string())));
+        assert_eq!(st.keys().count(), 1);
     }
 
     #[test]
     fn set_wrong_type() {
-//        let mut st = core::DomainState::new(core::PersistentState::empty());
-//        assert_eq!(st.append("key", "element").err(), "WRONGTYPE");
+        let mut st = make_domain().unwrap();
+        st.insert("key", VecDeque::from(["element".to_string()]));
+        assert_eq!(st.get("key"), Err(WrongType));
+        st.set("key", "value");
+        assert_eq!(st.get("key"), Ok(Some("value".to_string())));
     }
 
     #[test]
@@ -103,8 +95,9 @@ mod tests {
         let mut st = make_domain().unwrap();
         st.set("apan:1", "value");
         st.set("apan:2", "not_value");
-        assert_eq!(st.get("apan:1").map_err(|e| e.to_string()), Ok("value".to_string()));
-        assert_eq!(st.get("apan:2").map_err(|e| e.to_string()), Ok("not_value".to_string()));
+        assert_eq!(st.get("apan:1"), Ok(Some("value".to_string())));
+        assert_eq!(st.get("apan:2"), Ok(Some("not_value".to_string())));
+        assert_eq!(st.get("apan:3"), Ok(None));
     }
 
     #[test]
@@ -113,18 +106,18 @@ mod tests {
         st.set("apan:1", "value");
         st.set("apan:2", "not_value");
         st.set("apan:4", "something else");
-        st.lists.insert("apan:5".to_string(), VecDeque::from([
+        st.insert("apan:5", VecDeque::from([
             "a value".to_string(),
             "two value".to_string(),
         ]));
-        assert_eq!(st.strings.len(), 3);
+        assert_eq!(st.keys().count(), 4);
         assert_eq!(
             st.mget(vec!["apan:1", "apan:2", "apan:3", "apan:5"]),
             vec![
                 Some("value".to_string()), 
                 Some("not_value".to_string()), 
                 None,
-                Some("a value,two value".to_string())
+                None,
             ]
         );
     }
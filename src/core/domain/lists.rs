@@ -1,9 +1,10 @@
 use std::io;
 use std::cmp;
-use std::time;
 use std::collections;
 
 use crate::core;
+use crate::core::domain::keyspace::Typed;
+use crate::core::notifications::EventClass;
 use crate::core::resp;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -15,26 +16,27 @@ pub enum ListApi {
     Range(String, i32, i32),
 }
 
+type List = collections::VecDeque<String>;
+
 pub trait Lists {
-    fn range(&self, key: &str, start: i32, stop: i32) -> Vec<String>;
+    fn range(&self, key: &str, start: i32, stop: i32) -> Typed<Vec<String>>;
 
     /* Replace `to_exixting` with a two-variant. */
-    fn append(&mut self, key: &str, element: &str, to_existing: bool) -> usize;
-    fn prepend(&mut self, key: &str, element: &str, to_existing: bool) -> usize;
+    fn append(&mut self, key: &str, element: &str, to_existing: bool) -> Typed<usize>;
+    fn prepend(&mut self, key: &str, element: &str, to_existing: bool) -> Typed<usize>;
 
-    /* This has a tri-state error condition. More datatypes probably do - solve 
-       this with a domain level-error type. */
-    fn set_element(&mut self, key: &str, index: usize, element: &str) -> bool;
-    fn length(&self, key: &str) -> usize;
+    /* False for a missing key as well as for an index out of range. */
+    fn set_element(&mut self, key: &str, index: usize, element: &str) -> Typed<bool>;
+    fn length(&self, key: &str) -> Typed<usize>;
 }
 
 impl Lists for core::State {
-    fn range(&self, key: &str, start: i32, stop: i32) -> Vec<String> {
-        let length = self.length(key) as i32;
+    fn range(&self, key: &str, start: i32, stop: i32) -> Typed<Vec<String>> {
+        let Some(xs) = self.typed::<List>(key)? else { return Ok(vec![]) };
+        let length = xs.len() as i32;
         if start >= length {
-            vec![]
+            Ok(vec![])
         } else {
-            // Division by zero on length == 0.
             let effective_start = ((start + length) % length) as usize;
             let effective_stop = if stop < 0 {
                 ((stop + length) % length + 1) as usize
@@ -43,58 +45,50 @@ impl Lists for core::State {
             };
 
             if effective_start <= effective_stop {
-                self.lists.get(&key.to_string())
-                    .unwrap_or(&collections::VecDeque::from(vec![]))
-                    .range(effective_start..effective_stop)
-                    .map(|s| s.to_string())
-                    .collect()
+                Ok(xs.range(effective_start..effective_stop)
+                     .map(|s| s.to_string())
+                     .collect())
             } else {
-                vec![]
+                Ok(vec![])
             }
         }
     }
 
-    fn append(&mut self, key: &str, element: &str, to_existing: bool) -> usize {
-        let xs =
-            self.lists
-                .entry(key.to_string())
-                .and_modify(|xs| xs.push_back(element.to_string()));
-
-        if !to_existing {
-            xs.or_insert_with(|| collections::VecDeque::from(vec![element.to_string()]));
-        }
-        self.expunge_expired(&time::SystemTime::now());
-        self.length(key)
+    fn append(&mut self, key: &str, element: &str, to_existing: bool) -> Typed<usize> {
+        let push = |xs: &mut List| {
+            xs.push_back(element.to_string());
+            xs.len()
+        };
+        let length = if to_existing {
+            self.update(key, push)?
+        } else {
+            push(self.typed_or_default(key)?)
+        };
+        Ok(length)
     }
 
-    fn prepend(&mut self, key: &str, element: &str, to_existing: bool) -> usize {
-        let xs =
-            self.lists
-                .entry(key.to_string())
-                .and_modify(|xs| xs.push_front(element.to_string()));
-
-        if !to_existing {
-            xs.or_insert_with(|| collections::VecDeque::from(vec![element.to_string()]));
-        }    
-        self.length(key)
+    fn prepend(&mut self, key: &str, element: &str, to_existing: bool) -> Typed<usize> {
+        let push = |xs: &mut List| {
+            xs.push_front(element.to_string());
+            xs.len()
+        };
+        if to_existing {
+            self.update(key, push)
+        } else {
+            Ok(push(self.typed_or_default(key)?))
+        }
     }
 
-    fn set_element(&mut self, key: &str, index: usize, element: &str) -> bool {
-        matches!(
-            self.lists
-                .entry(key.to_string())
-                .and_modify(|list|
-                    if let Some(existing) = list.get_mut(index) {
-                        *existing = element.to_string();
-                    }
-                 ),
-            collections::hash_map::Entry::Occupied(_)
+    fn set_element(&mut self, key: &str, index: usize, element: &str) -> Typed<bool> {
+        self.update(key, |xs: &mut List|
+            xs.get_mut(index)
+              .map(|existing| *existing = element.to_string())
+              .is_some()
         )
     }
 
-    fn length(&self, key: &str) -> usize {
-        self.lists
-            .get(key).map_or(0, |v| v.len())
+    fn length(&self, key: &str) -> Typed<usize> {
+        Ok(self.typed::<List>(key)?.map_or(0, List::len))
     }
 }
 
@@ -105,39 +99,44 @@ pub fn apply(
     match &*command {
         ListApi::Length(key) =>
             Ok(resp::Message::Integer(
-                state.begin_reading()?.length(key) as i64
+                state.begin_reading()?.length(key)? as i64
             )),
         ListApi::Append(key, elements, to_existing) => {
-            state.apply_transaction(&command, |data| {
-                let new_length = elements.iter().fold(0, |_, element| {
+            let new_length = state.try_apply_transaction(&command, |data| {
+                elements.iter().try_fold(0, |_, element| {
                     data.append(key, element, *to_existing)
-                });
-                resp::Message::Integer(new_length as i64)
-            })
+                })
+            })??;
+            if new_length > 0 {
+                state.notify(EventClass::List, "rpush", key)?;
+            }
+            Ok(resp::Message::Integer(new_length as i64))
         },
         ListApi::Prepend(key, elements, to_existing) => {
-            state.apply_transaction(&command, |data| {
-                let new_length = elements.iter().fold(0, |_, element| {
+            let new_length = state.try_apply_transaction(&command, |data| {
+                elements.iter().try_fold(0, |_, element| {
                     data.prepend(key, element, *to_existing)
-                });
-                resp::Message::Integer(new_length as i64)
-            })
+                })
+            })??;
+            if new_length > 0 {
+                state.notify(EventClass::List, "lpush", key)?;
+            }
+            Ok(resp::Message::Integer(new_length as i64))
         },
         ListApi::Set(key, index, element) => {
-            state.apply_transaction(&command, |data| {
-                if data.set_element(key, *index, element) {
-                    resp::Message::SimpleString("OK".to_string())
-                } else {
-                    resp::Message::Error {
-                        prefix: resp::ErrorPrefix::Err,
-                        message: "Index out of range".to_string()
-                    }
-                }
-            })
+            if state.try_apply_transaction(&command, |data| data.set_element(key, *index, element))?? {
+                state.notify(EventClass::List, "lset", key)?;
+                Ok(resp::Message::SimpleString("OK".to_string()))
+            } else {
+                Ok(resp::Message::Error {
+                    prefix: resp::ErrorPrefix::Err,
+                    message: "Index out of range".to_string()
+                })
+            }
         },
         ListApi::Range(key, start, stop) =>
             Ok(resp::Message::make_bulk_array(
-                state.begin_reading()?.range(key, *start, *stop).as_slice()
+                state.begin_reading()?.range(key, *start, *stop)?.as_slice()
             )),
     }
 }
@@ -150,31 +149,32 @@ mod tests {
     use crate::core::domain::ttl;
     use crate::core::tx_log;
     use super::Lists;
+    use crate::core::domain::keyspace::WrongType;
 
     fn make_domain() -> Result<core::State, io::Error> {
-        Ok(tx_log::LoggedTransactions::new(
+        tx_log::LoggedTransactions::new(
             ttl::Lifetimes::new(core::Datasets::new())
-        )?)
+        )
     }
 
     #[test]
     fn adding() {
         let mut st = make_domain().unwrap();
-        assert_eq!(st.length("key"), 0);
-        st.append("key", "1", false);
-        st.append("key", "2", false);
-        st.prepend("key", "3", false);
-        assert_eq!(st.length("key"), 3);
-        assert_eq!(st.lists.len(), 1);
-        st.prepend("key2", "1", false);
-        st.append("key2", "2", false);
-        assert_eq!(st.length("key"), 3);
-        assert_eq!(st.length("key2"), 2);
-        assert_eq!(st.lists.len(), 2);
-        assert_eq!(st.lists.get("key"), Some(&VecDeque::from([
+        assert_eq!(st.length("key"), Ok(0));
+        st.append("key", "1", false).unwrap();
+        st.append("key", "2", false).unwrap();
+        st.prepend("key", "3", false).unwrap();
+        assert_eq!(st.length("key"), Ok(3));
+        assert_eq!(st.keys().count(), 1);
+        st.prepend("key2", "1", false).unwrap();
+        st.append("key2", "2", false).unwrap();
+        assert_eq!(st.length("key"), Ok(3));
+        assert_eq!(st.length("key2"), Ok(2));
+        assert_eq!(st.keys().count(), 2);
+        assert_eq!(st.typed::<List>("key").unwrap(), Some(&VecDeque::from([
             "3".to_string(), "1".to_string(), "2".to_string()
         ])));
-        assert_eq!(st.lists.get("key2"), Some(&VecDeque::from([
+        assert_eq!(st.typed::<List>("key2").unwrap(), Some(&VecDeque::from([
             "1".to_string(), "2".to_string()
         ])));
     }
@@ -182,21 +182,22 @@ mod tests {
     #[test]
     fn add_to_existing() {
         let mut st = make_domain().unwrap();
-        assert_eq!(st.append("key", "element", true), 0);
-        assert_eq!(st.append("key", "element", false), 1);
-        assert_eq!(st.append("key", "element", true), 2);
-        assert_eq!(st.prepend("key2", "element", true), 0);
-        assert_eq!(st.prepend("key2", "element", false), 1);
-        assert_eq!(st.prepend("key2", "element", true), 2);
+        assert_eq!(st.append("key", "element", true), Ok(0));
+        assert_eq!(st.append("key", "element", false), Ok(1));
+        assert_eq!(st.append("key", "element", true), Ok(2));
+        assert_eq!(st.prepend("key2", "element", true), Ok(0));
+        assert_eq!(st.prepend("key2", "element", false), Ok(1));
+        assert_eq!(st.prepend("key2", "element", true), Ok(2));
     }
 
     #[test]
     fn set() {
         let mut st = make_domain().unwrap();
-        assert_eq!(st.set_element("key", 0, "element3"), false);
-        st.append("key", "element2", false);
-        assert_eq!(st.set_element("key", 0, "element"), true);
-        assert_eq!(st.range("key", 0, 10), vec!["element".to_string()]);
+        assert_eq!(st.set_element("key", 0, "element3"), Ok(false));
+        st.append("key", "element2", false).unwrap();
+        assert_eq!(st.set_element("key", 0, "element"), Ok(true));
+        assert_eq!(st.set_element("key", 1, "element"), Ok(false));
+        assert_eq!(st.range("key", 0, 10).unwrap(), vec!["element".to_string()]);
     }
 
     #[test]
@@ -204,26 +205,45 @@ mod tests {
         let mut st = make_domain().unwrap();
         
         for i in 1..10 {
-            st.append("key", &i.to_string(), false);
+            st.append("key", &i.to_string(), false).unwrap();
         }
         assert_eq!(
-            st.range("key", 0, 100),
+            st.range("key", 0, 100).unwrap(),
             (1..10).map(|i| i.to_string()).collect::<Vec<_>>()
         );
         assert_eq!(
-            st.range("key", 0, -1),
+            st.range("key", 0, -1).unwrap(),
             (1..10).map(|i| i.to_string()).collect::<Vec<_>>()
         );
         assert_eq!(
-            st.range("key", 0, -2),
+            st.range("key", 0, -2).unwrap(),
             (1..9).map(|i| i.to_string()).collect::<Vec<_>>()
         );
         assert_eq!(
-            st.range("key", 5, -2),
+            st.range("key", 5, -2).unwrap(),
             (6..9).map(|i| i.to_string()).collect::<Vec<_>>()
         );
-        assert_eq!(st.range("key", 15, -2), Vec::<String>::new());
-        assert_eq!(st.range("key", 0, 1), vec!["1".to_string()]);
-        assert_eq!(st.range("key", 1, 1), Vec::<String>::new());
+        assert_eq!(st.range("key", 15, -2).unwrap(), Vec::<String>::new());
+        assert_eq!(st.range("key", 0, 1).unwrap(), vec!["1".to_string()]);
+        assert_eq!(st.range("key", 1, 1).unwrap(), Vec::<String>::new());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn wrong_type() {
+        use crate::core::domain::keyvalues::KeyValues;
+
+        let mut st = make_domain().unwrap();
+        st.set("key", "value");
+        assert_eq!(st.append("key", "element", false), Err(WrongType));
+        assert_eq!(st.prepend("key", "element", true), Err(WrongType));
+        assert_eq!(st.length("key"), Err(WrongType));
+        assert_eq!(st.range("key", 0, -1), Err(WrongType));
+        assert_eq!(st.get("key"), Ok(Some("value".to_string())));
+
+        /* SET replaces whatever the key held. */
+        st.append("list", "element", false).unwrap();
+        st.set("list", "value");
+        assert_eq!(st.length("list"), Err(WrongType));
+        assert_eq!(st.get("list"), Ok(Some("value".to_string())));
+    }
+}
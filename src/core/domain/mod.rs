@@ -1,4 +1,7 @@
+pub mod cursors;
 pub mod lists;
 pub mod keyvalues;
+pub mod keyspace;
 pub mod sorted_sets;
-pub mod ttl;
\ No newline at end of file
+pub mod skiplist;
+pub mod ttl;
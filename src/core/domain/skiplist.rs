@@ -0,0 +1,354 @@
+use std::cmp;
+use rand::Rng;
+
+const MAX_LEVEL: usize = 32;
+
+/* Each level of a node points some number of places ahead; the span is how
+   many, which is what makes ranks add up on the way down. */
+#[derive(Clone, Copy, Debug)]
+struct Level {
+    forward: Option<usize>,
+    span:    usize,
+}
+
+#[derive(Clone)]
+struct Node {
+    score:    f64,
+    member:   String,
+    backward: Option<usize>,
+    levels:   Vec<Level>,
+}
+
+impl Node {
+    fn precedes(&self, score: f64, member: &str) -> bool {
+        self.score.total_cmp(&score)
+            .then_with(|| self.member.as_str().cmp(member))
+            .is_lt()
+    }
+}
+
+/* Members ordered by score and then by member, like a Redis zset, with
+   every rank lookup in O(log n). Nodes live in an arena and refer to each
+   other by index; `None` in a link position means the head. */
+#[derive(Clone)]
+pub struct SkipList {
+    nodes:  Vec<Option<Node>>,
+    vacant: Vec<usize>,
+    head:   [Level; MAX_LEVEL],
+    tail:   Option<usize>,
+    level:  usize,
+    len:    usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        Self {
+            nodes:  vec![],
+            vacant: vec![],
+            head:   [Level { forward: None, span: 0 }; MAX_LEVEL],
+            tail:   None,
+            level:  1,
+            len:    0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn node(&self, id: usize) -> &Node {
+        self.nodes[id].as_ref().expect("skip list links a vacant node")
+    }
+
+    fn node_mut(&mut self, id: usize) -> &mut Node {
+        self.nodes[id].as_mut().expect("skip list links a vacant node")
+    }
+
+    fn level_at(&self, at: Option<usize>, i: usize) -> Level {
+        at.map_or(self.head[i], |id| self.node(id).levels[i])
+    }
+
+    fn level_at_mut(&mut self, at: Option<usize>, i: usize) -> &mut Level {
+        match at {
+            Some(id) => &mut self.node_mut(id).levels[i],
+            None     => &mut self.head[i],
+        }
+    }
+
+    /* p = 1/4, as in Redis. */
+    fn random_level() -> usize {
+        let mut rng = rand::thread_rng();
+        let mut level = 1;
+        while level < MAX_LEVEL && rng.gen_ratio(1, 4) {
+            level += 1;
+        }
+        level
+    }
+
+    /* The last node on every level that comes before (score, member),
+       along with its rank. */
+    fn predecessors(&self, score: f64, member: &str) -> ([Option<usize>; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [None; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = None;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            while let Level { forward: Some(next), span } = self.level_at(x, i) {
+                if self.node(next).precedes(score, member) {
+                    rank[i] += span;
+                    x = Some(next);
+                } else {
+                    break
+                }
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+
+    /* The member must not be in the list already. */
+    pub fn insert(&mut self, score: f64, member: &str) {
+        let (mut update, mut rank) = self.predecessors(score, member);
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = None;
+                self.head[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let levels = (0..level).map(|i| {
+            let preceding = self.level_at(update[i], i);
+            Level { forward: preceding.forward, span: preceding.span - (rank[0] - rank[i]) }
+        }).collect::<Vec<_>>();
+        let forward = levels[0].forward;
+        let node = Node { score, member: member.to_string(), backward: update[0], levels };
+        let id = if let Some(id) = self.vacant.pop() {
+            self.nodes[id] = Some(node);
+            id
+        } else {
+            self.nodes.push(Some(node));
+            self.nodes.len() - 1
+        };
+
+        for i in 0..level {
+            *self.level_at_mut(update[i], i) = Level { forward: Some(id), span: rank[0] - rank[i] + 1 };
+        }
+        for (i, &preceding) in update.iter().enumerate().take(self.level).skip(level) {
+            self.level_at_mut(preceding, i).span += 1;
+        }
+        match forward {
+            Some(next) => self.node_mut(next).backward = Some(id),
+            None       => self.tail = Some(id),
+        }
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, score: f64, member: &str) -> bool {
+        let (update, _) = self.predecessors(score, member);
+        let Some(id) = self.level_at(update[0], 0).forward else { return false };
+        if self.node(id).score.total_cmp(&score).is_ne() || self.node(id).member != member {
+            return false
+        }
+
+        let Node { backward, levels, .. } = self.nodes[id].take().expect("skip list links a vacant node");
+        for (i, &preceding) in update.iter().enumerate().take(self.level) {
+            let link = self.level_at_mut(preceding, i);
+            if link.forward == Some(id) {
+                link.span += levels[i].span;
+                link.span -= 1;
+                link.forward = levels[i].forward;
+            } else {
+                link.span -= 1;
+            }
+        }
+        match levels[0].forward {
+            Some(next) => self.node_mut(next).backward = backward,
+            None       => self.tail = backward,
+        }
+        while self.level > 1 && self.head[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.vacant.push(id);
+        self.len -= 1;
+        true
+    }
+
+    /* How many entries come before the first one that does not satisfy
+       `before`, which has to hold for a prefix of the list and no more. */
+    pub fn position<F>(&self, before: F) -> usize
+    where
+        F: Fn(f64, &str) -> bool
+    {
+        let mut rank = 0;
+        let mut x = None;
+        for i in (0..self.level).rev() {
+            while let Level { forward: Some(next), span } = self.level_at(x, i) {
+                let node = self.node(next);
+                if before(node.score, &node.member) {
+                    rank += span;
+                    x = Some(next);
+                } else {
+                    break
+                }
+            }
+        }
+        rank
+    }
+
+    /* Zero-based. */
+    pub fn rank(&self, score: f64, member: &str) -> Option<usize> {
+        let rank = self.position(|s, m| s.total_cmp(&score).then_with(|| m.cmp(member)).is_lt());
+        self.at(rank)
+            .filter(|&id| self.node(id).member == member)
+            .map(|_| rank)
+    }
+
+    /* The entry at a zero-based rank, found in logarithmic time. */
+    pub fn get(&self, rank: usize) -> Option<(f64, &String)> {
+        self.at(rank).map(|id| {
+            let node = self.node(id);
+            (node.score, &node.member)
+        })
+    }
+
+    fn at(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None
+        }
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = None;
+        for i in (0..self.level).rev() {
+            while let Level { forward: Some(next), span } = self.level_at(x, i) {
+                if traversed + span <= target {
+                    traversed += span;
+                    x = Some(next);
+                } else {
+                    break
+                }
+            }
+            if traversed == target {
+                return x
+            }
+        }
+        None
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(0, self.len)
+    }
+
+    /* Ranks `start` up to but not including `end`, clamped to the list. */
+    pub fn range(&self, start: usize, end: usize) -> Iter<'_> {
+        let end = cmp::min(end, self.len);
+        let remaining = end.saturating_sub(start);
+        Iter {
+            list:  self,
+            front: if remaining > 0 { self.at(start) } else { None },
+            back:  if remaining > 0 { self.at(end - 1) } else { None },
+            remaining,
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    list:      &'a SkipList,
+    front:     Option<usize>,
+    back:      Option<usize>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (f64, &'a String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None
+        }
+        let node = self.list.node(self.front?);
+        self.front = node.levels[0].forward;
+        self.remaining -= 1;
+        Some((node.score, &node.member))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None
+        }
+        let node = self.list.node(self.back?);
+        self.back = node.backward;
+        self.remaining -= 1;
+        Some((node.score, &node.member))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn against_a_sorted_vec() {
+        let mut rng = rand::thread_rng();
+        let mut list = SkipList::new();
+        let mut model: Vec<(f64, String)> = vec![];
+        for _ in 0..2000 {
+            let score = rng.gen_range(0..50) as f64;
+            let member = format!("m{}", rng.gen_range(0..200));
+            if let Some(i) = model.iter().position(|(_, m)| *m == member) {
+                let (score, member) = model.remove(i);
+                assert!(list.remove(score, &member));
+            } else {
+                list.insert(score, &member);
+                model.push((score, member));
+                model.sort_by(|(a, m), (b, n)| a.total_cmp(b).then_with(|| m.cmp(n)));
+            }
+            assert_eq!(list.len(), model.len());
+        }
+
+        let entries = list.iter().map(|(s, m)| (s, m.clone())).collect::<Vec<_>>();
+        assert_eq!(entries, model);
+        let reversed = list.iter().rev().map(|(s, m)| (s, m.clone())).collect::<Vec<_>>();
+        assert_eq!(reversed, model.iter().rev().cloned().collect::<Vec<_>>());
+        for (rank, (score, member)) in model.iter().enumerate() {
+            assert_eq!(list.rank(*score, member), Some(rank));
+            assert_eq!(list.get(rank), Some((*score, member)));
+        }
+        assert_eq!(list.get(model.len()), None);
+        assert_eq!(list.rank(0f64, "absent"), None);
+
+        let (start, end) = (model.len() / 3, model.len() / 2);
+        let window = list.range(start, end).map(|(s, m)| (s, m.clone())).collect::<Vec<_>>();
+        assert_eq!(window, model[start..end]);
+        assert_eq!(list.range(start, end).rev().count(), end - start);
+        assert_eq!(list.range(end, start).count(), 0);
+        assert_eq!(list.position(|s, _| s < 25f64), model.iter().filter(|(s, _)| *s < 25f64).count());
+
+        for (score, member) in &model {
+            assert!(list.remove(*score, member));
+        }
+        assert!(list.is_empty());
+        assert_eq!(list.iter().next(), None);
+        assert!(!list.remove(1f64, "m1"));
+    }
+}
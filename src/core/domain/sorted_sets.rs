@@ -1,29 +1,325 @@
 use std::io;
 use std::cmp;
 use std::collections;
+use std::ops;
+use std::time;
+use rand::{seq, Rng};
 use serde::*;
 
 use crate::core;
+use crate::core::domain::cursors;
+use crate::core::domain::keyspace::Typed;
+use crate::core::domain::skiplist;
+use crate::core::notifications::EventClass;
 use crate::core::resp;
+use crate::globs;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum SortedSetApi {
     Add { key: String, entries: Vec<(f64, String)>, options: AddOptions, },
-    RangeByRank(String, usize, usize),
-    RangeByScore(String, f64, f64),
+    Range { key: String, query: RangeQuery, with_scores: bool, },
+    RangeStore { destination: String, key: String, query: RangeQuery, },
     Rank(String, String),
+    ReverseRank(String, String),
     Score(String, String),
+    Scores(String, Vec<String>),
+    Remove(String, Vec<String>),
+    IncrementBy(String, f64, String),
+    Cardinality(String),
+    Count(String, ScoreRange),
+    Pop { key: String, from: PopFrom, count: Option<usize>, },
+    BlockingPop { keys: Vec<String>, from: PopFrom, timeout: f64, },
+    RemoveRangeByRank(String, i64, i64),
+    RemoveRangeByScore(String, ScoreRange),
+    RemoveRangeByLex(String, LexRange),
+    RandomMember { key: String, count: Option<i64>, with_scores: bool, },
+    Combine { combination: Combination, with_scores: bool, },
+    CombineStore { destination: String, combination: Combination, },
+    IntersectionCardinality { keys: Vec<String>, limit: usize, },
+    Scan { key: String, cursor: u64, pattern: Option<String>, count: Option<usize>, },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SetOperation {
+    Union,                      /* ZUNION */
+    Intersection,               /* ZINTER */
+    Difference,                 /* ZDIFF */
+}
+
+impl SetOperation {
+    fn event_name(&self) -> &str {
+        match self {
+            SetOperation::Union        => "zunionstore",
+            SetOperation::Intersection => "zinterstore",
+            SetOperation::Difference   => "zdiffstore",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Aggregate {
+    Sum, Min, Max,
+}
+
+impl Aggregate {
+    fn parse(word: &str) -> Option<Aggregate> {
+        match word.to_ascii_uppercase().as_str() {
+            "SUM"      => Some(Aggregate::Sum),
+            "MIN"      => Some(Aggregate::Min),
+            "MAX"      => Some(Aggregate::Max),
+            _otherwise => None,
+        }
+    }
+
+    /* inf + -inf comes out as 0 rather than NaN, like in Redis. */
+    fn apply(&self, lhs: f64, rhs: f64) -> f64 {
+        match self {
+            Aggregate::Sum => Some(lhs + rhs).filter(|x| !x.is_nan()).unwrap_or(0f64),
+            Aggregate::Min => lhs.min(rhs),
+            Aggregate::Max => lhs.max(rhs),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Combination {
+    operation: SetOperation,
+    keys:      Vec<String>,
+    weights:   Vec<f64>,
+    aggregate: Aggregate,
+}
+
+impl Combination {
+    /* `numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX]
+       [WITHSCORES]`; ZDIFF only knows about WITHSCORES. Yields the
+       combination and whether WITHSCORES was given. */
+    pub fn parse(operation: SetOperation, args: &[&str]) -> Result<(Self, bool), String> {
+        let syntax_error = || "syntax error".to_string();
+        let (numkeys, args) = args.split_first().ok_or_else(syntax_error)?;
+        let numkeys = numkeys.parse::<usize>()
+            .map_err(|_| "value is not an integer or out of range".to_string())?;
+        if numkeys == 0 {
+            return Err("at least 1 input key is needed".to_string())
+        } else if args.len() < numkeys {
+            return Err(syntax_error())
+        }
+
+        let (keys, options) = args.split_at(numkeys);
+        let mut weights = vec![1f64; numkeys];
+        let (mut aggregate, mut with_scores) = (Aggregate::Sum, false);
+        let mut words = options.iter();
+        while let Some(word) = words.next() {
+            match word.to_ascii_uppercase().as_str() {
+                "WITHSCORES" =>
+                    with_scores = true,
+                "WEIGHTS" if operation != SetOperation::Difference =>
+                    for weight in weights.iter_mut() {
+                        *weight = words.next()
+                            .and_then(|w| w.parse::<f64>().ok())
+                            .filter(|w| !w.is_nan())
+                            .ok_or("weight value is not a float")?;
+                    },
+                "AGGREGATE" if operation != SetOperation::Difference =>
+                    aggregate = words.next().and_then(|w| Aggregate::parse(w)).ok_or_else(syntax_error)?,
+                _otherwise =>
+                    return Err(syntax_error()),
+            }
+        }
+
+        let keys = keys.iter().map(|&key| key.into()).collect();
+        Ok((Self { operation, keys, weights, aggregate }, with_scores))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PopFrom {
+    Min,                        /* ZPOPMIN */
+    Max,                        /* ZPOPMAX */
+}
+
+impl PopFrom {
+    fn command_name(&self) -> &str {
+        match self {
+            PopFrom::Min => "ZPOPMIN",
+            PopFrom::Max => "ZPOPMAX",
+        }
+    }
+
+    fn event_name(&self) -> &str {
+        match self {
+            PopFrom::Min => "zpopmin",
+            PopFrom::Max => "zpopmax",
+        }
+    }
+}
+
+/* `min max` as in ZCOUNT: inclusive unless prefixed with `(`, and -inf and
+   +inf are fine. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreRange {
+    min: ops::Bound<f64>,
+    max: ops::Bound<f64>,
+}
+
+impl ScoreRange {
+    pub fn parse(min: &str, max: &str) -> Option<Self> {
+        fn parse_bound(image: &str) -> Option<ops::Bound<f64>> {
+            let (image, bound): (&str, fn(f64) -> ops::Bound<f64>) =
+                if let Some(exclusive) = image.strip_prefix('(') {
+                    (exclusive, ops::Bound::Excluded)
+                } else {
+                    (image, ops::Bound::Included)
+                };
+            image.parse::<f64>().ok().filter(|score| !score.is_nan()).map(bound)
+        }
+
+        Some(Self { min: parse_bound(min)?, max: parse_bound(max)? })
+    }
+
+    /* Whether a score falls below the range. */
+    fn precedes(&self, score: f64) -> bool {
+        match self.min {
+            ops::Bound::Included(min) => score < min,
+            ops::Bound::Excluded(min) => score <= min,
+            ops::Bound::Unbounded     => false,
+        }
+    }
+
+    /* Whether a score lies beyond the range. */
+    fn exceeded_by(&self, score: f64) -> bool {
+        match self.max {
+            ops::Bound::Included(max) => score > max,
+            ops::Bound::Excluded(max) => score >= max,
+            ops::Bound::Unbounded     => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexBound {
+    Lowest,                     /* - */
+    Highest,                    /* + */
+    Inclusive(String),          /* [member */
+    Exclusive(String),          /* (member */
+}
+
+/* `min max` as in ZRANGEBYLEX. Only meaningful when every member has the
+   same score. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexRange {
+    min: LexBound,
+    max: LexBound,
+}
+
+impl LexRange {
+    pub fn parse(min: &str, max: &str) -> Option<Self> {
+        fn parse_bound(image: &str) -> Option<LexBound> {
+            match image {
+                "-" => Some(LexBound::Lowest),
+                "+" => Some(LexBound::Highest),
+                _   => image.strip_prefix('[').map(|m| LexBound::Inclusive(m.to_string()))
+                           .or_else(|| image.strip_prefix('(').map(|m| LexBound::Exclusive(m.to_string()))),
+            }
+        }
+
+        Some(Self { min: parse_bound(min)?, max: parse_bound(max)? })
+    }
+
+    fn contains(&self, member: &str) -> bool {
+        let above_min = match &self.min {
+            LexBound::Lowest           => true,
+            LexBound::Highest          => false,
+            LexBound::Inclusive(min)   => member >= min.as_str(),
+            LexBound::Exclusive(min)   => member > min.as_str(),
+        };
+        let below_max = match &self.max {
+            LexBound::Lowest           => false,
+            LexBound::Highest          => true,
+            LexBound::Inclusive(max)   => member <= max.as_str(),
+            LexBound::Exclusive(max)   => member < max.as_str(),
+        };
+        above_min && below_max
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RangeBy {
+    Rank(i64, i64),
+    Score(ScoreRange),          /* BYSCORE */
+    Lex(LexRange),              /* BYLEX */
+}
+
+/* Everything ZRANGE can be asked, and what the legacy ZRANGEBYSCORE and
+   friends are rewritten into. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeQuery {
+    by:      RangeBy,
+    reverse: bool,              /* REV */
+    limit:   Option<(i64, i64)>,/* LIMIT offset count */
+}
+
+impl RangeQuery {
+    /* `start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`.
+       With REV, start is the upper bound for scores and lexicographic
+       ranges. Yields the query and whether WITHSCORES was given. */
+    pub fn parse(start: &str, stop: &str, options: &[&str]) -> Result<(Self, bool), String> {
+        let (mut by_score, mut by_lex, mut reverse, mut with_scores) = (false, false, false, false);
+        let mut limit = None;
+        let mut words = options.iter();
+        while let Some(word) = words.next() {
+            match word.to_ascii_uppercase().as_str() {
+                "BYSCORE"    => by_score = true,
+                "BYLEX"      => by_lex = true,
+                "REV"        => reverse = true,
+                "WITHSCORES" => with_scores = true,
+                "LIMIT"      => {
+                    let mut decode = || words.next().and_then(|n| n.parse::<i64>().ok());
+                    limit = Some((decode().ok_or("syntax error")?, decode().ok_or("syntax error")?));
+                },
+                _otherwise   => return Err("syntax error".to_string()),
+            }
+        }
+
+        let (min, max) = if reverse { (stop, start) } else { (start, stop) };
+        let by = match (by_score, by_lex) {
+            (true, true)   => return Err("syntax error".to_string()),
+            (true, false)  => RangeBy::Score(ScoreRange::parse(min, max).ok_or("min or max is not a float")?),
+            (false, true)  => RangeBy::Lex(LexRange::parse(min, max).ok_or("min or max not valid string range item")?),
+            (false, false) => RangeBy::Rank(
+                start.parse().map_err(|_| "value is not an integer or out of range")?,
+                stop.parse().map_err(|_| "value is not an integer or out of range")?,
+            ),
+        };
+
+        match (&by, limit, with_scores) {
+            (RangeBy::Rank(..), Some(_), _) =>
+                Err("syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string()),
+            (RangeBy::Lex(_), _, true) =>
+                Err("syntax error, WITHSCORES not supported in combination with BYLEX".to_string()),
+            _otherwise =>
+                Ok((Self { by, reverse, limit }, with_scores)),
+        }
+    }
+}
+
+/* Redis rank semantics: negative indices count from the end, both ends are
+   inclusive and the stop is clamped. None if nothing is left. */
+fn rank_span(start: i64, stop: i64, length: usize) -> Option<(usize, usize)> {
+    let length = length as i64;
+    let start = if start < 0 { cmp::max(start + length, 0) } else { start };
+    let stop  = if stop < 0 { stop + length } else { cmp::min(stop, length - 1) };
+    (start <= stop && start < length).then_some((start as usize, stop as usize))
 }
 
 pub struct MemberEntry {
     rank: usize,
+    reverse_rank: usize,
     score: f64,
-    member: String,
 }
 
 impl MemberEntry {
-    fn new(rank: usize, score: f64, member: &str) -> Self {
-        Self { rank, score, member: member.into() }
+    fn new(rank: usize, reverse_rank: usize, score: f64) -> Self {
+        Self { rank, reverse_rank, score }
     }
 }
 
@@ -49,20 +345,70 @@ pub enum MergePolicy {
 }
 
 
-#[derive(Clone, Debug, PartialEq)]
+impl MergePolicy {
+    /* Whether `new_score` may be merged in, given the member's current score. */
+    fn admits(&self, existing: Option<f64>, new_score: f64) -> bool {
+        match (self, existing) {
+            (MergePolicy::Require(Only::AddNew), existing)          => existing.is_none(),
+            (MergePolicy::Require(Only::UpdateExisting), existing)  => existing.is_some(),
+            (MergePolicy::UpdateExisting(_), None)                  => false,
+            (MergePolicy::UpdateExisting(when), Some(score)) |
+            (MergePolicy::AddOrUpdate(when), Some(score))           => when.admits(score, new_score),
+            (MergePolicy::AddOrUpdate(_), None)                     => true,
+            (MergePolicy::Default, _)                               => true,
+            (MergePolicy::Diverged(_), _)                           => false,
+        }
+    }
+
+    fn requires_existing(&self) -> bool {
+        matches!(self, MergePolicy::Require(Only::UpdateExisting) | MergePolicy::UpdateExisting(_))
+    }
+}
+
+/* What came out of merging a single member. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Merge {
+    Added,
+    Updated,
+    Unchanged,
+    Rejected,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Merged {
+    pub added:   usize,
+    pub updated: usize,
+}
+
+impl Merged {
+    fn tally(self, merge: Merge) -> Self {
+        match merge {
+            Merge::Added   => Self { added: self.added + 1, ..self },
+            Merge::Updated => Self { updated: self.updated + 1, ..self },
+            _otherwise     => self,
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.added + self.updated > 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Return {
     Added,                      /* Nothing */
     Changed,                    /* CH */
+    NewScore,                   /* INCR */
 }
 
 impl Return {
     fn default() -> Self { Return::Added }
 
     fn parse(word: &str) -> Option<Return> {
-        if matches!(word, "CH" | "ch") {
-            Some(Return::Changed)
-        } else {
-            None
+        match word {
+            "CH" | "ch"     => Some(Return::Changed),
+            "INCR" | "incr" => Some(Return::NewScore),
+            _otherwise      => None,
         }
     }
 }
@@ -80,24 +426,30 @@ impl Default for AddOptions {
 }
 
 impl AddOptions {
+    /* INCR trumps CH, which trumps the default. */
     fn select_return(p: &AddOptions, q: &AddOptions, merge: MergePolicy) -> Self {
-        if p.and_return == Return::Changed || q.and_return == Return::Changed {
-            Self { merge, and_return: Return::Changed }
-        } else {
-            Self { merge, and_return: Return::default() }
-        }
+        let and_return = if p.and_return > q.and_return { &p.and_return } else { &q.and_return };
+        Self { merge, and_return: and_return.clone() }
     }
 
-    fn merge_policy(&self) -> &MergePolicy {
+    pub fn merge_policy(&self) -> &MergePolicy {
         &self.merge
     }
 
-    fn return_default(merge: MergePolicy) -> Self {
-        Self { merge, and_return: Return::default() }
+    pub fn returns(&self) -> &Return {
+        &self.and_return
+    }
+
+    /* The reply to ZADD without INCR. */
+    pub fn count(&self, merged: &Merged) -> usize {
+        match &self.and_return {
+            Return::Changed => merged.added + merged.updated,
+            _otherwise      => merged.added,
+        }
     }
 
-    fn return_changed(merge: MergePolicy) -> Self {
-        Self { merge, and_return: Return::Changed }
+    fn return_default(merge: MergePolicy) -> Self {
+        Self { merge, and_return: Return::default() }
     }
 
     fn parse(word: &str) -> Option<Self> {
@@ -106,18 +458,34 @@ impl AddOptions {
         ).or_else(||
             Only::parse(word).map(|only| Self::return_default(MergePolicy::Require(only)))
         ).or_else(||
-            Return::parse(word).map(|_| Self::return_changed(MergePolicy::Default))
+            Return::parse(word).map(|and_return| Self { merge: MergePolicy::Default, and_return })
         )
     }
 
     fn combine(lhs: Self, rhs: &Self) -> Self {
-        match (lhs.merge_policy(), rhs.merge_policy()) {
+        let merge = match (lhs.merge_policy(), rhs.merge_policy()) {
+            (MergePolicy::Diverged(reason), _) | (_, MergePolicy::Diverged(reason)) =>
+                MergePolicy::Diverged(reason.clone()),
+            (MergePolicy::Default, policy) | (policy, MergePolicy::Default) =>
+                policy.clone(),
+            (p, q) if p == q =>
+                p.clone(),
             (MergePolicy::AddOrUpdate(when), MergePolicy::Require(Only::UpdateExisting)) |
             (MergePolicy::Require(Only::UpdateExisting), MergePolicy::AddOrUpdate(when)) =>
-                Self::select_return(&lhs, rhs, MergePolicy::UpdateExisting(when.clone())),
-            otherwise =>
-                Self::return_default(MergePolicy::Diverged(format!("bad options: {otherwise:?}"))),
-        }
+                MergePolicy::UpdateExisting(when.clone()),
+            (MergePolicy::UpdateExisting(when), MergePolicy::AddOrUpdate(other)) |
+            (MergePolicy::AddOrUpdate(other), MergePolicy::UpdateExisting(when)) if when == other =>
+                MergePolicy::UpdateExisting(when.clone()),
+            (update @ MergePolicy::UpdateExisting(_), MergePolicy::Require(Only::UpdateExisting)) |
+            (MergePolicy::Require(Only::UpdateExisting), update @ MergePolicy::UpdateExisting(_)) =>
+                update.clone(),
+            (MergePolicy::Require(Only::AddNew), xx) | (xx, MergePolicy::Require(Only::AddNew))
+                if xx.requires_existing() =>
+                MergePolicy::Diverged("XX and NX options at the same time are not compatible".to_string()),
+            _otherwise =>
+                MergePolicy::Diverged("GT, LT, and/or NX options at the same time are not compatible".to_string()),
+        };
+        Self::select_return(&lhs, rhs, merge)
     }
 }
 
@@ -168,55 +536,248 @@ impl When {
             _otherwise  => None,
         }
     }
+
+    fn admits(&self, score: f64, new_score: f64) -> bool {
+        match self {
+            When::GreaterThan => new_score > score,
+            When::LessThan    => new_score < score,
+        }
+    }
 }
 
 pub trait SortedSet {
-    fn add(&mut self, key: &str, entries: &[(f64, &str)], options: AddOptions) -> usize;
-    fn range_by_rank(&self, key: &str, start: usize, stop: usize) -> Vec<MemberEntry>;
-    fn range_by_score(&self, key: &str, start: f64, stop: f64) -> Vec<MemberEntry>;
-    fn member_stats(&self, key: &str, member: &str) -> Option<MemberEntry>;
+    fn add(&mut self, key: &str, entries: &[(f64, &str)], policy: &MergePolicy) -> Typed<Merged>;
+    fn increment(&mut self, key: &str, increment: f64, member: &str, policy: &MergePolicy) -> Typed<Option<f64>>;
+    fn range(&self, key: &str, query: &RangeQuery) -> Typed<Vec<(f64, String)>>;
+    fn store(&mut self, destination: &str, entries: &[(f64, String)]) -> usize;
+    fn member_stats(&self, key: &str, member: &str) -> Typed<Option<MemberEntry>>;
+    fn scores(&self, key: &str, members: &[String]) -> Typed<Vec<Option<f64>>>;
+    fn remove(&mut self, key: &str, members: &[String]) -> Typed<usize>;
+    fn cardinality(&self, key: &str) -> Typed<usize>;
+    fn count(&self, key: &str, range: &ScoreRange) -> Typed<usize>;
+    fn pop(&mut self, key: &str, from: &PopFrom, count: usize) -> Typed<Vec<(f64, String)>>;
+    fn remove_range_by_rank(&mut self, key: &str, start: i64, stop: i64) -> Typed<usize>;
+    fn remove_range_by_score(&mut self, key: &str, range: &ScoreRange) -> Typed<usize>;
+    fn remove_range_by_lex(&mut self, key: &str, range: &LexRange) -> Typed<usize>;
+    fn random_members(&self, key: &str, count: i64) -> Typed<Vec<(f64, String)>>;
+    fn combine(&self, combination: &Combination) -> Typed<Vec<(f64, String)>>;
+    fn intersection_cardinality(&self, keys: &[String], limit: usize) -> Typed<usize>;
+    fn scan(&self, key: &str, cursor: u64, pattern: Option<&str>, count: usize) -> Typed<cursors::Page<(f64, String)>>;
 }
 
 impl SortedSet for core::State {
-    fn add(&mut self, key: &str, entries: &[(f64, &str)], _options: AddOptions) -> usize {
-        let mut count = 0;
-        self.sorted_sets
-            .entry(key.into()).and_modify(|xs|
-                entries.iter().copied().for_each(|(score, member)| {
-                    xs.merge(score, member);
-                    count += 1;
-                })
-             ).or_insert_with(|| {
-                let mut xs = OrderedScores::new();
-                entries.iter().copied().for_each(|(score, member)| {
-                    xs.merge(score, member);
-                    count += 1;
-                });
-                xs
-             });
-        count
+    fn add(&mut self, key: &str, entries: &[(f64, &str)], policy: &MergePolicy) -> Typed<Merged> {
+        let xs = self.typed_or_default::<OrderedScores>(key)?;
+        let merged = entries.iter().copied().fold(Merged::default(), |merged, (score, member)|
+            merged.tally(xs.merge(score, member, policy))
+        );
+        /* NX or XX may well have kept everything out. */
+        self.remove_if_empty(key);
+        Ok(merged)
     }
 
-    fn range_by_rank(&self, key: &str, start: usize, stop: usize) -> Vec<MemberEntry> {
-        self.sorted_sets.get(key)
-            .map_or(vec![], |xs|
-                xs.range_by_rank(start, stop).map(|(rank, (score, member))| {
-                    MemberEntry::new(rank, score, &member)
-                }).collect()
-            )
+    fn increment(&mut self, key: &str, increment: f64, member: &str, policy: &MergePolicy) -> Typed<Option<f64>> {
+        let new_score = self.typed_or_default::<OrderedScores>(key)?.increment(increment, member, policy);
+        self.remove_if_empty(key);
+        Ok(new_score)
     }
 
-    fn range_by_score(&self, key: &str, start: f64, stop: f64) -> Vec<MemberEntry> {
-        self.sorted_sets.get(key)
-            .map_or(vec![], |xs|
-                xs.range_by_score(start, stop).map(|(rank, (score, member))| {
-                    MemberEntry::new(rank, score, &member)
-                }).collect()
-            )
+    fn range(&self, key: &str, query: &RangeQuery) -> Typed<Vec<(f64, String)>> {
+        Ok(self.typed::<OrderedScores>(key)?.map_or(vec![], |xs| xs.query(query)))
+    }
+
+    /* Replaces whatever was at `destination`, of whatever type; nothing to
+       store deletes it. */
+    fn store(&mut self, destination: &str, entries: &[(f64, String)]) -> usize {
+        let mut xs = OrderedScores::new();
+        for (score, member) in entries {
+            xs.merge(*score, member, &MergePolicy::Default);
+        }
+        let stored = xs.len();
+        if xs.is_empty() {
+            self.remove_key(destination);
+        } else {
+            self.insert(destination, xs);
+        }
+        stored
     }
 
-    fn member_stats(&self, key: &str, member: &str) -> Option<MemberEntry> {
-        self.sorted_sets.get(key)?.member_stats(member)
+    fn member_stats(&self, key: &str, member: &str) -> Typed<Option<MemberEntry>> {
+        Ok(self.typed::<OrderedScores>(key)?.and_then(|xs| xs.member_stats(member)))
+    }
+
+    fn scores(&self, key: &str, members: &[String]) -> Typed<Vec<Option<f64>>> {
+        let xs = self.typed::<OrderedScores>(key)?;
+        Ok(members.iter()
+            .map(|member| xs.and_then(|xs| xs.score(member)))
+            .collect())
+    }
+
+    fn remove(&mut self, key: &str, members: &[String]) -> Typed<usize> {
+        self.update(key, |xs: &mut OrderedScores| xs.remove_all(members))
+    }
+
+    fn cardinality(&self, key: &str) -> Typed<usize> {
+        Ok(self.typed::<OrderedScores>(key)?.map_or(0, OrderedScores::len))
+    }
+
+    fn count(&self, key: &str, range: &ScoreRange) -> Typed<usize> {
+        Ok(self.typed::<OrderedScores>(key)?.map_or(0, |xs| xs.scored_within(range).count()))
+    }
+
+    fn pop(&mut self, key: &str, from: &PopFrom, count: usize) -> Typed<Vec<(f64, String)>> {
+        self.update(key, |xs: &mut OrderedScores| xs.pop(from, count))
+    }
+
+    fn remove_range_by_rank(&mut self, key: &str, start: i64, stop: i64) -> Typed<usize> {
+        self.update(key, |xs: &mut OrderedScores| {
+            let doomed = xs.ranked_window(start, stop, false)
+                .map(|(_, member)| member.clone())
+                .collect::<Vec<_>>();
+            xs.remove_all(&doomed)
+        })
+    }
+
+    fn remove_range_by_score(&mut self, key: &str, range: &ScoreRange) -> Typed<usize> {
+        self.update(key, |xs: &mut OrderedScores| {
+            let doomed = xs.scored_within(range)
+                .map(|(_, member)| member.clone())
+                .collect::<Vec<_>>();
+            xs.remove_all(&doomed)
+        })
+    }
+
+    fn remove_range_by_lex(&mut self, key: &str, range: &LexRange) -> Typed<usize> {
+        self.update(key, |xs: &mut OrderedScores| {
+            let doomed = xs.entries()
+                .filter(|(_, member)| range.contains(member))
+                .map(|(_, member)| member.clone())
+                .collect::<Vec<_>>();
+            xs.remove_all(&doomed)
+        })
+    }
+
+    fn scan(&self, key: &str, cursor: u64, pattern: Option<&str>, count: usize) -> Typed<cursors::Page<(f64, String)>> {
+        let Some(xs) = self.typed::<OrderedScores>(key)? else {
+            return Ok(cursors::Page { cursor: 0, items: vec![] })
+        };
+        let glob = pattern.map(globs::Glob::new);
+        let page = xs.positions.page(cursor, count, |member| glob.as_ref().is_none_or(|g| g.matches(member)));
+        Ok(cursors::Page {
+            cursor: page.cursor,
+            items:  page.items.into_iter()
+                .filter_map(|member| Some((xs.score(member)?, member.clone())))
+                .collect(),
+        })
+    }
+
+    /* A positive count picks distinct members, a negative one allows the
+       same member to be picked more than once. Members are picked by rank,
+       so that each costs a lookup in the skip list rather than a copy of
+       the whole set. */
+    fn random_members(&self, key: &str, count: i64) -> Typed<Vec<(f64, String)>> {
+        let Some(xs) = self.typed::<OrderedScores>(key)?.filter(|xs| !xs.is_empty()) else { return Ok(vec![]) };
+        let mut rng = rand::thread_rng();
+        let ranks = if count < 0 {
+            (0..count.unsigned_abs())
+                .map(|_| rng.gen_range(0..xs.len()))
+                .collect::<Vec<_>>()
+        } else {
+            let amount = (count as usize).min(xs.len());
+            seq::index::sample(&mut rng, xs.len(), amount).into_vec()
+        };
+        Ok(ranks.into_iter()
+            .filter_map(|rank| xs.ranked.get(rank))
+            .map(|(score, member)| (score, member.clone()))
+            .collect())
+    }
+
+    /* Missing keys count as empty sets. */
+    fn combine(&self, combination: &Combination) -> Typed<Vec<(f64, String)>> {
+        let empty = OrderedScores::new();
+        let inputs = combination.keys.iter()
+            .map(|key| Ok(self.typed::<OrderedScores>(key)?.unwrap_or(&empty)))
+            .collect::<Typed<Vec<_>>>()?;
+        Ok(OrderedScores::combine(&inputs, combination)
+            .entries()
+            .map(|(score, member)| (score, member.clone()))
+            .collect())
+    }
+
+    /* A limit of 0 means no limit. */
+    fn intersection_cardinality(&self, keys: &[String], limit: usize) -> Typed<usize> {
+        let inputs = keys.iter()
+            .map(|key| self.typed::<OrderedScores>(key))
+            .collect::<Typed<Vec<_>>>()?
+            .into_iter()
+            .collect::<Option<Vec<_>>>();
+        let Some((first, rest)) = inputs.as_deref().and_then(<[_]>::split_first) else { return Ok(0) };
+        let shared = first.entries().filter(|(_, member)|
+            rest.iter().all(|xs| xs.score(member).is_some())
+        );
+        Ok(if limit > 0 { shared.take(limit).count() } else { shared.count() })
+    }
+}
+
+/* The flat `member score member score ...` shape shared by several replies. */
+fn scored_members(entries: &[(f64, String)], with_scores: bool) -> resp::Message {
+    resp::Message::make_bulk_array(
+        &entries.iter().flat_map(|(score, member)|
+            if with_scores {
+                vec![member.clone(), score.to_string()]
+            } else {
+                vec![member.clone()]
+            }
+        ).collect::<Vec<_>>()
+    )
+}
+
+/* The event itself, plus `del` if that took the last member with it. */
+fn notify_removal(state: &core::StateContext, event: &str, key: &str, deleted: bool) -> io::Result<()> {
+    state.notify(EventClass::SortedSet, event, key)?;
+    if deleted {
+        state.notify(EventClass::Generic, "del", key)?;
+    }
+    Ok(())
+}
+
+fn blocking_pop(
+    state:   &core::StateContext,
+    keys:    &[String],
+    from:    &PopFrom,
+    timeout: f64,
+) -> Result<resp::Message, io::Error> {
+    let deadline = (timeout > 0f64).then(|| time::Instant::now() + time::Duration::from_secs_f64(timeout));
+    loop {
+        let seen = state.write_count()?;
+        let ready = {
+            let data = state.begin_reading()?;
+            keys.iter()
+                .find_map(|key| match data.cardinality(key) {
+                    Ok(0)      => None,
+                    Ok(_)      => Some(Ok(key.clone())),
+                    Err(wrong) => Some(Err(wrong)),
+                })
+                .transpose()?
+        };
+
+        if let Some(key) = ready {
+            /* Logged as the plain pop it turned into, so that replaying the
+               log never blocks. */
+            let message = resp::Message::make_bulk_array(&[
+                from.command_name().to_string(), key.clone(), "1".to_string(),
+            ]);
+            let pop = SortedSetApi::Pop { key: key.clone(), from: from.clone(), count: Some(1) };
+            let (popped, deleted) = state.try_apply_transaction(&core::CommandContext::new(pop, &message), |data| -> Typed<_> {
+                Ok((data.pop(&key, from, 1)?, !data.contains_key(&key)))
+            })??;
+            if let Some((score, member)) = popped.first() {
+                notify_removal(state, from.event_name(), &key, deleted)?;
+                break Ok(resp::Message::make_bulk_array(&[key, member.clone(), score.to_string()]))
+            }
+        } else if !state.await_write(seen, deadline)? {
+            break Ok(resp::Message::Nil)
+        }
     }
 }
 
@@ -226,38 +787,190 @@ pub fn apply(
 ) -> Result<resp::Message, io::Error> {
     match &*command {
         SortedSetApi::Add { key, entries, options } =>
-            state.apply_transaction(&command, |data| {
-                /* Why is this necessary? */
-                let xs = entries.iter().map(|(a, b)| (*a, b.as_str())).collect::<Vec<(f64, &str)>>();
-                resp::Message::Integer(
-                    data.add(key, &xs, options.clone()) as i64
-                )
-            }),
-        SortedSetApi::RangeByRank(key, start, stop) =>
-            Ok(resp::Message::make_bulk_array(
-                state.begin_reading()?.range_by_rank(key, *start, *stop)
-                     .iter().map(|x| x.member.clone()).collect::<Vec<_>>()
-                     .as_slice()
-            )),
-        SortedSetApi::RangeByScore(key, start, stop) => 
-            Ok(resp::Message::make_bulk_array(
-                state.begin_reading()?.range_by_score(key, *start, *stop)
-                    .iter().map(|x| x.member.clone()).collect::<Vec<_>>()
-                    .as_slice()
+            match (options.merge_policy(), options.returns(), entries.as_slice()) {
+                (MergePolicy::Diverged(reason), _, _) =>
+                    Ok(resp::Message::Error {
+                        prefix: resp::ErrorPrefix::Err,
+                        message: format!("syntax error, {reason}"),
+                    }),
+                (policy, Return::NewScore, [(increment, member)]) => {
+                    let new_score = state.try_apply_transaction(&command, |data|
+                        data.increment(key, *increment, member, policy)
+                    )??;
+                    if new_score.is_some() {
+                        state.notify(EventClass::SortedSet, "zincr", key)?;
+                    }
+                    Ok(new_score.map_or(resp::Message::Nil, |score| resp::Message::BulkString(score.to_string())))
+                },
+                (_, Return::NewScore, _) =>
+                    Ok(resp::Message::Error {
+                        prefix: resp::ErrorPrefix::Err,
+                        message: "INCR option supports a single increment-element pair".to_string(),
+                    }),
+                (policy, _, entries) => {
+                    let merged = state.try_apply_transaction(&command, |data| {
+                        /* Why is this necessary? */
+                        let xs = entries.iter().map(|(a, b)| (*a, b.as_str())).collect::<Vec<(f64, &str)>>();
+                        data.add(key, &xs, policy)
+                    })??;
+                    if merged.is_modified() {
+                        state.notify(EventClass::SortedSet, "zadd", key)?;
+                    }
+                    Ok(resp::Message::Integer(options.count(&merged) as i64))
+                },
+            },
+        SortedSetApi::Range { key, query, with_scores } =>
+            Ok(scored_members(
+                &state.begin_reading()?.range(key, query)?,
+                *with_scores
             )),
+        SortedSetApi::RangeStore { destination, key, query } => {
+            let (stored, existed) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                let existed = data.contains_key(destination);
+                let entries = data.range(key, query)?;
+                Ok((data.store(destination, &entries), existed))
+            })??;
+            if stored > 0 {
+                state.notify(EventClass::SortedSet, "zrangestore", destination)?;
+            } else if existed {
+                state.notify(EventClass::Generic, "del", destination)?;
+            }
+            Ok(resp::Message::Integer(stored as i64))
+        },
         SortedSetApi::Rank(key, member) =>
-            if let Some(stat) = state.begin_reading()?.member_stats(key, member) {
+            if let Some(stat) = state.begin_reading()?.member_stats(key, member)? {
                 Ok(resp::Message::Integer(stat.rank as i64))
             } else {
                 Ok(resp::Message::Nil)
             },
+        SortedSetApi::ReverseRank(key, member) =>
+            if let Some(stat) = state.begin_reading()?.member_stats(key, member)? {
+                Ok(resp::Message::Integer(stat.reverse_rank as i64))
+            } else {
+                Ok(resp::Message::Nil)
+            },
         SortedSetApi::Score(key, member) =>
-            if let Some(stat) = state.begin_reading()?.member_stats(key, member) {
+            if let Some(stat) = state.begin_reading()?.member_stats(key, member)? {
                 Ok(resp::Message::BulkString(stat.score.to_string()))
             } else {
                 Ok(resp::Message::Nil)
             },
-}
+        SortedSetApi::Scores(key, members) =>
+            Ok(resp::Message::make_array(
+                state.begin_reading()?.scores(key, members)?.into_iter().map(|score|
+                    score.map_or(resp::Message::Nil, |score| resp::Message::BulkString(score.to_string()))
+                ).collect()
+            )),
+        SortedSetApi::Remove(key, members) => {
+            let (removed, deleted) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                Ok((data.remove(key, members)?, !data.contains_key(key)))
+            })??;
+            if removed > 0 {
+                notify_removal(state, "zrem", key, deleted)?;
+            }
+            Ok(resp::Message::Integer(removed as i64))
+        },
+        SortedSetApi::IncrementBy(key, increment, member) => {
+            let new_score = state.try_apply_transaction(&command, |data|
+                data.increment(key, *increment, member, &MergePolicy::Default)
+            )??;
+            if let Some(score) = new_score {
+                state.notify(EventClass::SortedSet, "zincr", key)?;
+                Ok(resp::Message::BulkString(score.to_string()))
+            } else {
+                Ok(resp::Message::Error {
+                    prefix: resp::ErrorPrefix::Err,
+                    message: "resulting score is not a number (NaN)".to_string(),
+                })
+            }
+        },
+        SortedSetApi::Cardinality(key) =>
+            Ok(resp::Message::Integer(
+                state.begin_reading()?.cardinality(key)? as i64
+            )),
+        SortedSetApi::Count(key, range) =>
+            Ok(resp::Message::Integer(
+                state.begin_reading()?.count(key, range)? as i64
+            )),
+        SortedSetApi::Pop { key, from, count } => {
+            let (popped, deleted) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                Ok((data.pop(key, from, count.unwrap_or(1))?, !data.contains_key(key)))
+            })??;
+            if !popped.is_empty() {
+                notify_removal(state, from.event_name(), key, deleted)?;
+            }
+            Ok(scored_members(&popped, true))
+        },
+        SortedSetApi::BlockingPop { keys, from, timeout } =>
+            blocking_pop(state, keys, from, *timeout),
+        SortedSetApi::RemoveRangeByRank(key, start, stop) => {
+            let (removed, deleted) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                Ok((data.remove_range_by_rank(key, *start, *stop)?, !data.contains_key(key)))
+            })??;
+            if removed > 0 {
+                notify_removal(state, "zremrangebyrank", key, deleted)?;
+            }
+            Ok(resp::Message::Integer(removed as i64))
+        },
+        SortedSetApi::RemoveRangeByScore(key, range) => {
+            let (removed, deleted) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                Ok((data.remove_range_by_score(key, range)?, !data.contains_key(key)))
+            })??;
+            if removed > 0 {
+                notify_removal(state, "zremrangebyscore", key, deleted)?;
+            }
+            Ok(resp::Message::Integer(removed as i64))
+        },
+        SortedSetApi::RemoveRangeByLex(key, range) => {
+            let (removed, deleted) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                Ok((data.remove_range_by_lex(key, range)?, !data.contains_key(key)))
+            })??;
+            if removed > 0 {
+                notify_removal(state, "zremrangebylex", key, deleted)?;
+            }
+            Ok(resp::Message::Integer(removed as i64))
+        },
+        SortedSetApi::Combine { combination, with_scores } =>
+            Ok(scored_members(
+                &state.begin_reading()?.combine(combination)?,
+                *with_scores
+            )),
+        SortedSetApi::CombineStore { destination, combination } => {
+            let (stored, existed) = state.try_apply_transaction(&command, |data| -> Typed<_> {
+                let existed = data.contains_key(destination);
+                let entries = data.combine(combination)?;
+                Ok((data.store(destination, &entries), existed))
+            })??;
+            if stored > 0 {
+                state.notify(EventClass::SortedSet, combination.operation.event_name(), destination)?;
+            } else if existed {
+                state.notify(EventClass::Generic, "del", destination)?;
+            }
+            Ok(resp::Message::Integer(stored as i64))
+        },
+        SortedSetApi::IntersectionCardinality { keys, limit } =>
+            Ok(resp::Message::Integer(
+                state.begin_reading()?.intersection_cardinality(keys, *limit)? as i64
+            )),
+        SortedSetApi::RandomMember { key, count: None, .. } =>
+            Ok(state.begin_reading()?.random_members(key, 1)?.first().map_or(
+                resp::Message::Nil,
+                |(_, member)| resp::Message::BulkString(member.clone())
+            )),
+        SortedSetApi::RandomMember { key, count: Some(count), with_scores } =>
+            Ok(scored_members(
+                &state.begin_reading()?.random_members(key, *count)?,
+                *with_scores
+            )),
+        SortedSetApi::Scan { key, cursor, pattern, count } => {
+            let page = state.begin_reading()?
+                .scan(key, *cursor, pattern.as_deref(), count.unwrap_or(cursors::DEFAULT_COUNT))?;
+            Ok(resp::Message::make_array(vec![
+                resp::Message::BulkString(page.cursor.to_string()),
+                scored_members(&page.items, true),
+            ]))
+        },
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -267,7 +980,7 @@ impl Ord for Score {
 }
 impl PartialOrd for Score {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+        Some(self.cmp(other))
     }
 }
 impl PartialEq for Score {
@@ -275,78 +988,232 @@ impl PartialEq for Score {
 }
 impl Eq for Score {}
 
+/* What OrderedScores looked like in snapshots before format version 6:
+   the pair of maps it used to be made of. Read only, to migrate. */
 #[derive(Deserialize, Serialize)]
-pub struct OrderedScores {
+pub struct ScoreTables {
     member_to_score:  collections::HashMap<String, Score>,
     score_to_members: collections::BTreeMap<Score, collections::BTreeSet<String>>,
 }
 
-use collections::hash_map::Entry as HashEntry;
-use collections::btree_map::Entry as BTreeEntry;
+/* In a snapshot, only each member's score: the skip list and positions
+   for ZSCAN are built up again from those. */
+#[derive(Clone, Default, Deserialize)]
+#[serde(from = "collections::HashMap<String, f64>")]
+pub struct OrderedScores {
+    member_to_score: collections::HashMap<String, f64>,
+    ranked:          skiplist::SkipList,
+    positions:       cursors::Index,
+}
+
+impl From<collections::HashMap<String, f64>> for OrderedScores {
+    fn from(member_to_score: collections::HashMap<String, f64>) -> Self {
+        let mut xs = OrderedScores::new();
+        for (member, score) in member_to_score {
+            xs.relocate(&member, score);
+        }
+        xs
+    }
+}
+
+impl From<ScoreTables> for OrderedScores {
+    fn from(tables: ScoreTables) -> Self {
+        tables.member_to_score.into_iter()
+            .map(|(member, Score(score))| (member, score))
+            .collect::<collections::HashMap<_, _>>()
+            .into()
+    }
+}
+
+impl Serialize for OrderedScores {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.member_to_score.serialize(serializer)
+    }
+}
 
 impl OrderedScores {
     fn new() -> Self {
-        Self {
-            member_to_score: collections::HashMap::new(),
-            score_to_members: collections::BTreeMap::new(),
-        }
+        Self::default()
     }
 
-    /* This should probably have the rank included. */
-    fn range_by_score(&self, start: f64, stop: f64) -> impl Iterator<Item = (usize, (f64, String))> + '_ {
-        /* Make sure start < stop. */
-        self.score_to_members
-            .range(Score(start) ..= Score(stop))
-            .flat_map(|(Score(score), members)|
-                members.iter().map(|member| (*score, member.clone()))
-             )
-            .enumerate()
+    pub fn is_empty(&self) -> bool {
+        self.member_to_score.is_empty()
     }
 
-    fn range_by_rank(&self, start: usize, stop: usize) -> impl Iterator<Item = (usize, (f64, String))> + '_ {
-        self.score_to_members
-            .iter()
-            .flat_map(|(Score(score), members)|
-                members.iter().map(|member| (*score, member.clone()))
-             )
-            .enumerate()
-            .skip(start).take(stop - start)
+    pub fn len(&self) -> usize {
+        self.member_to_score.len()
     }
 
-    fn member_stats(&self, member: &str) -> Option<MemberEntry> {
-        let Score(score) = self.member_to_score.get(member)?;
-        self.range_by_score(f64::MIN, *score)
-            .find_map(|(rank, (score, subject))| 
-                (member == subject).then(|| MemberEntry::new(rank, score, member))
-            )
-    }
-
-    /* Add parameter to control how or if a new score is incorporated. */    
-    fn merge(&mut self, new_score: f64, member: &str) {
-        match self.member_to_score.entry(member.into()) {
-            HashEntry::Occupied(mut member_score) => {
-                let score = member_score.get().clone();
-                if let BTreeEntry::Occupied(mut score_entry) = self.score_to_members.entry(score) {
-                    let members = score_entry.get_mut();
-                    if members.remove(member) && members.is_empty() {
-                        score_entry.remove_entry();
+    fn score(&self, member: &str) -> Option<f64> {
+        self.member_to_score.get(member).copied()
+    }
+
+    /* Everything, lowest score first; equal scores in member order. */
+    pub fn entries(&self) -> skiplist::Iter<'_> {
+        self.ranked.iter()
+    }
+
+    fn scored_within(&self, range: &ScoreRange) -> skiplist::Iter<'_> {
+        let start = self.ranked.position(|score, _| range.precedes(score));
+        let end = self.ranked.position(|score, _| !range.exceeded_by(score));
+        self.ranked.range(start, end)
+    }
+
+    fn remove(&mut self, member: &str) -> Option<f64> {
+        let score = self.member_to_score.remove(member)?;
+        self.ranked.remove(score, member);
+        self.positions.remove(member);
+        Some(score)
+    }
+
+    fn combine(inputs: &[&OrderedScores], combination: &Combination) -> OrderedScores {
+        fn weighted(score: f64, weight: f64) -> f64 {
+            /* 0 * inf is 0 and not NaN. */
+            Some(score * weight).filter(|x| !x.is_nan()).unwrap_or(0f64)
+        }
+
+        let mut combined = OrderedScores::new();
+        let Some((first, rest)) = inputs.split_first() else { return combined };
+        match combination.operation {
+            SetOperation::Union => {
+                let mut scores = collections::HashMap::<&String, f64>::new();
+                for (xs, weight) in inputs.iter().zip(&combination.weights) {
+                    for (score, member) in xs.entries() {
+                        let score = weighted(score, *weight);
+                        scores.entry(member)
+                            .and_modify(|acc| *acc = combination.aggregate.apply(*acc, score))
+                            .or_insert(score);
                     }
-                    member_score.insert(Score(new_score));
-                    self.score_to_members.entry(Score(new_score))
-                        .and_modify(|e| { e.insert(member.into()); })
-                        .or_insert_with(|| { collections::BTreeSet::from([ member.into() ]) });    
-                } else {
-                    panic!("member_to_score <=> score_to_member invariant broken; 
-                            member has the score, but scores does not have that member")
                 }
+                for (member, score) in scores {
+                    combined.relocate(member, score);
+                }
+            },
+            SetOperation::Intersection =>
+                for (score, member) in first.entries() {
+                    let others = rest.iter().zip(&combination.weights[1..])
+                        .map(|(xs, weight)| xs.score(member).map(|score| weighted(score, *weight)))
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(others) = others {
+                        let score = others.into_iter().fold(
+                            weighted(score, combination.weights[0]),
+                            |acc, score| combination.aggregate.apply(acc, score)
+                        );
+                        combined.relocate(member, score);
+                    }
+                },
+            SetOperation::Difference =>
+                for (score, member) in first.entries() {
+                    if rest.iter().all(|xs| xs.score(member).is_none()) {
+                        combined.relocate(member, score);
+                    }
+                },
+        }
+        combined
+    }
+
+    fn remove_all(&mut self, members: &[String]) -> usize {
+        members.iter().filter_map(|member| self.remove(member)).count()
+    }
+
+    fn pop(&mut self, from: &PopFrom, count: usize) -> Vec<(f64, String)> {
+        let popped = match from {
+            PopFrom::Min => self.entries().take(count).map(|(score, member)| (score, member.clone())).collect::<Vec<_>>(),
+            PopFrom::Max => self.entries().rev().take(count).map(|(score, member)| (score, member.clone())).collect(),
+        };
+        for (_, member) in &popped {
+            self.remove(member);
+        }
+        popped
+    }
+
+    /* rank_span as the arguments to skip and take. */
+    fn rank_window(&self, start: i64, stop: i64) -> (usize, usize) {
+        rank_span(start, stop, self.len())
+            .map_or((0, 0), |(start, stop)| (start, stop - start + 1))
+    }
+
+    /* Ranks counted from the lowest score, or from the highest. */
+    fn ranked_window(&self, start: i64, stop: i64, reverse: bool) -> skiplist::Iter<'_> {
+        let (skip, take) = self.rank_window(start, stop);
+        if reverse {
+            let end = self.len() - skip;
+            self.ranked.range(end - take, end)
+        } else {
+            self.ranked.range(skip, skip + take)
+        }
+    }
+
+    fn query(&self, query: &RangeQuery) -> Vec<(f64, String)> {
+        let selected: Box<dyn Iterator<Item = (f64, &String)>> = match (&query.by, query.reverse) {
+            (RangeBy::Rank(start, stop), false) => Box::new(self.ranked_window(*start, *stop, false)),
+            (RangeBy::Rank(start, stop), true)  => Box::new(self.ranked_window(*start, *stop, true).rev()),
+            (RangeBy::Score(range), false)      => Box::new(self.scored_within(range)),
+            (RangeBy::Score(range), true)       => Box::new(self.scored_within(range).rev()),
+            (RangeBy::Lex(range), false)        => Box::new(self.entries().filter(|(_, m)| range.contains(m))),
+            (RangeBy::Lex(range), true)         => Box::new(self.entries().rev().filter(|(_, m)| range.contains(m))),
+        };
+
+        /* A negative offset selects nothing, a negative count everything. */
+        let (offset, count) = match query.limit {
+            Some((offset, _)) if offset < 0 => (0, 0),
+            Some((offset, count)) if count < 0 => (offset as usize, usize::MAX),
+            Some((offset, count)) => (offset as usize, count as usize),
+            None => (0, usize::MAX),
+        };
+        selected.skip(offset).take(count)
+            .map(|(score, member)| (score, member.clone()))
+            .collect()
+    }
+
+    fn member_stats(&self, member: &str) -> Option<MemberEntry> {
+        let score = *self.member_to_score.get(member)?;
+        let rank = self.ranked.rank(score, member)?;
+        Some(MemberEntry::new(rank, self.len() - 1 - rank, score))
+    }
+
+    fn merge(&mut self, new_score: f64, member: &str, policy: &MergePolicy) -> Merge {
+        let existing = self.score(member);
+        if !policy.admits(existing, new_score) {
+            Merge::Rejected
+        } else {
+            match existing {
+                Some(score) if score == new_score =>
+                    Merge::Unchanged,
+                Some(_) => {
+                    self.relocate(member, new_score);
+                    Merge::Updated
+                },
+                None => {
+                    self.relocate(member, new_score);
+                    Merge::Added
+                },
             }
-            collections::hash_map::Entry::Vacant(e) => {
-                e.insert(Score(new_score));
-                self.score_to_members.entry(Score(new_score))
-                    .and_modify(|e| { e.insert(member.into()); })
-                    .or_insert_with(|| { collections::BTreeSet::from([ member.into() ]) });    
+        }
+    }
+
+    /* A NaN sum, say inf + -inf, is refused just like a policy violation. */
+    fn increment(&mut self, increment: f64, member: &str, policy: &MergePolicy) -> Option<f64> {
+        let score = self.score(member).unwrap_or(0f64);
+        let new_score = score + increment;
+        if new_score.is_nan() || self.merge(new_score, member, policy) == Merge::Rejected {
+            None
+        } else {
+            Some(new_score)
+        }
+    }
+
+    /* Keeps member_to_score, the skip list and positions in step. */
+    fn relocate(&mut self, member: &str, new_score: f64) {
+        if let Some(score) = self.member_to_score.insert(member.into(), new_score) {
+            if !self.ranked.remove(score, member) {
+                panic!("member_to_score <=> ranked invariant broken;
+                        member has the score, but the skip list does not have that member")
             }
+        } else {
+            self.positions.insert(member);
         }
+        self.ranked.insert(new_score, member);
     }
 }
 
@@ -354,73 +1221,103 @@ impl OrderedScores {
 mod tests {
     use super::*;
 
+    fn range_by_rank(d: &OrderedScores, start: i64, stop: i64) -> Vec<(usize, (f64, String))> {
+        let (query, _) = RangeQuery::parse(&start.to_string(), &stop.to_string(), &[]).unwrap();
+        d.query(&query).into_iter().enumerate().map(|(i, x)| (i + start as usize, x)).collect()
+    }
+
+    fn range_by_score(d: &OrderedScores, min: f64, max: f64) -> Vec<(usize, (f64, String))> {
+        let range = ScoreRange::parse(&min.to_string(), &max.to_string()).unwrap();
+        d.scored_within(&range).map(|(score, member)| (score, member.clone())).enumerate().collect()
+    }
+
+    fn members_at(d: &OrderedScores, score: f64) -> collections::BTreeSet<String> {
+        d.entries().filter(|(s, _)| *s == score).map(|(_, member)| member.clone()).collect()
+    }
+
+    fn distinct_scores(d: &OrderedScores) -> usize {
+        d.entries().map(|(score, _)| Score(score)).collect::<collections::BTreeSet<_>>().len()
+    }
+
+    #[test]
+    fn reconstruction() {
+        let mut d = OrderedScores::new();
+        d.merge(1.5, "a", &MergePolicy::Default);
+        d.merge(f64::NEG_INFINITY, "b", &MergePolicy::Default);
+        assert_eq!(
+            core::domain::keyspace::Value::SortedSet(Box::new(d)).reconstruction("k"),
+            vec![resp::Message::make_bulk_array(
+                &["ZADD", "k", "-inf", "b", "1.5", "a"].map(String::from)
+            )]
+        );
+    }
+
     #[test]
     fn or_this() {
         let mut d = OrderedScores::new();
 
-        d.merge(1f64, "user:1");
-        assert_eq!(d.member_to_score.get("user:1").unwrap(), &Score(1f64));
+        d.merge(1f64, "user:1", &MergePolicy::Default);
+        assert_eq!(d.member_to_score.get("user:1").unwrap(), &1f64);
         assert_eq!(
-            d.score_to_members.get(&Score(1f64)).unwrap(), &collections::BTreeSet::from(["user:1".to_string()])
+            &members_at(&d, 1f64), &collections::BTreeSet::from(["user:1".to_string()])
         );
         assert_eq!(d.member_to_score.len(), 1);
-        assert_eq!(d.score_to_members.len(), 1);
+        assert_eq!(distinct_scores(&d), 1);
 
-        d.merge(2f64, "user:1");
-        assert_eq!(d.member_to_score.get("user:1").unwrap(), &Score(2f64));
+        d.merge(2f64, "user:1", &MergePolicy::Default);
+        assert_eq!(d.member_to_score.get("user:1").unwrap(), &2f64);
         assert_eq!(
-            d.score_to_members.get(&Score(2f64)).unwrap(), 
+            &members_at(&d, 2f64), 
             &collections::BTreeSet::from(["user:1".to_string()])
         );
         assert_eq!(d.member_to_score.len(), 1);
-        assert_eq!(d.score_to_members.len(), 1);
+        assert_eq!(distinct_scores(&d), 1);
 
-        d.merge(1f64, "user:2");
-        assert_eq!(d.member_to_score.get("user:2").unwrap(), &Score(1f64));
-        assert_eq!(d.score_to_members.get(
-            &Score(1f64)).unwrap(), &collections::BTreeSet::from(["user:2".to_string()])
+        d.merge(1f64, "user:2", &MergePolicy::Default);
+        assert_eq!(d.member_to_score.get("user:2").unwrap(), &1f64);
+        assert_eq!(&members_at(&d, 1f64), &collections::BTreeSet::from(["user:2".to_string()])
         );
         assert_eq!(d.member_to_score.len(), 2);
-        assert_eq!(d.score_to_members.len(), 2);
+        assert_eq!(distinct_scores(&d), 2);
 
         assert_eq!(
-            d.range_by_score(0f64, 100f64).collect::<Vec<_>>(), 
+            range_by_score(&d, 0f64, 100f64), 
             vec![ (0, (1f64, "user:2".to_string())), (1, (2f64, "user:1".to_string())) ]
         );
 
         assert_eq!(
-            d.range_by_rank(0, 100).collect::<Vec<_>>(), 
+            range_by_rank(&d, 0, 100), 
             vec![ (0, (1f64, "user:2".to_string())), (1, (2f64, "user:1".to_string())) ]
         );
 
-        d.merge(2f64, "user:2");
-        assert_eq!(d.member_to_score.get("user:2").unwrap(), &Score(2f64));
+        d.merge(2f64, "user:2", &MergePolicy::Default);
+        assert_eq!(d.member_to_score.get("user:2").unwrap(), &2f64);
         assert_eq!(
-            d.score_to_members.get(&Score(2f64)).unwrap(),
+            &members_at(&d, 2f64),
             &collections::BTreeSet::from([ "user:2".to_string(), "user:1".to_string() ]));
         assert_eq!(d.member_to_score.len(), 2);
-        assert_eq!(d.score_to_members.len(), 1);
+        assert_eq!(distinct_scores(&d), 1);
 
         assert_eq!(
-            d.range_by_score(0f64, 100f64).collect::<Vec<_>>(), 
+            range_by_score(&d, 0f64, 100f64), 
             vec![ (0, (2f64, "user:1".to_string())), (1, (2f64, "user:2".to_string())) ]
         );
 
         assert_eq!(
-            d.range_by_rank(0, 100).collect::<Vec<_>>(), 
+            range_by_rank(&d, 0, 100), 
             vec![ (0, (2f64, "user:1".to_string())), (1, (2f64, "user:2".to_string())) ]
         );
 
-        d.merge(3f64, "user:3");
-        assert_eq!(d.member_to_score.get("user:3").unwrap(), &Score(3f64));
+        d.merge(3f64, "user:3", &MergePolicy::Default);
+        assert_eq!(d.member_to_score.get("user:3").unwrap(), &3f64);
         assert_eq!(
-            d.score_to_members.get(&Score(3f64)).unwrap(), 
+            &members_at(&d, 3f64), 
             &collections::BTreeSet::from([ "user:3".to_string() ]));
         assert_eq!(d.member_to_score.len(), 3);
-        assert_eq!(d.score_to_members.len(), 2);
+        assert_eq!(distinct_scores(&d), 2);
 
         assert_eq!(
-            d.range_by_score(0f64, 100f64).collect::<Vec<_>>(), 
+            range_by_score(&d, 0f64, 100f64), 
             vec![ 
                 (0, (2f64, "user:1".to_string())),
                 (1, (2f64, "user:2".to_string())),
@@ -429,7 +1326,7 @@ mod tests {
         );
 
         assert_eq!(
-            d.range_by_rank(0, 100).collect::<Vec<_>>(), 
+            range_by_rank(&d, 0, 100), 
             vec![ 
                 (0, (2f64, "user:1".to_string())),
                 (1, (2f64, "user:2".to_string())),
@@ -438,7 +1335,7 @@ mod tests {
         );
 
         assert_eq!(
-            d.range_by_rank(1, 100).collect::<Vec<_>>(), 
+            range_by_rank(&d, 1, 100), 
             vec![
                 (1, (2f64, "user:2".to_string())),
                 (2, (3f64, "user:3".to_string())),
@@ -450,4 +1347,219 @@ mod tests {
         assert_eq!(d.member_stats("user:3").unwrap().rank, 2);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn merge_policies() {
+        let mut d = OrderedScores::new();
+        let nx = MergePolicy::Require(Only::AddNew);
+        let xx = MergePolicy::Require(Only::UpdateExisting);
+
+        assert_eq!(d.merge(1f64, "user:1", &xx), Merge::Rejected);
+        assert_eq!(d.merge(1f64, "user:1", &nx), Merge::Added);
+        assert_eq!(d.merge(2f64, "user:1", &nx), Merge::Rejected);
+        assert_eq!(d.merge(1f64, "user:1", &xx), Merge::Unchanged);
+        assert_eq!(d.merge(2f64, "user:1", &xx), Merge::Updated);
+
+        let gt = MergePolicy::AddOrUpdate(When::GreaterThan);
+        assert_eq!(d.merge(1f64, "user:1", &gt), Merge::Rejected);
+        assert_eq!(d.merge(3f64, "user:1", &gt), Merge::Updated);
+        assert_eq!(d.merge(1f64, "user:2", &gt), Merge::Added);
+
+        let xx_lt = MergePolicy::UpdateExisting(When::LessThan);
+        assert_eq!(d.merge(0f64, "user:3", &xx_lt), Merge::Rejected);
+        assert_eq!(d.merge(0f64, "user:2", &xx_lt), Merge::Updated);
+
+        assert_eq!(d.increment(2f64, "user:2", &MergePolicy::Default), Some(2f64));
+        assert_eq!(d.increment(-1f64, "user:2", &gt), None);
+        assert_eq!(d.increment(1f64, "user:4", &xx), None);
+        assert_eq!(d.member_stats("user:2").unwrap().score, 2f64);
+        assert_eq!(d.member_to_score.len(), 2);
+        assert_eq!(distinct_scores(&d), 2);
+    }
+
+    #[test]
+    fn add_options() {
+        let parse = |words: &[&str]| {
+            let mut parser = AddArgsParser::default();
+            parser.parse_into(words);
+            parser
+        };
+
+        let parser = parse(&["XX", "GT", "CH", "1", "a"]);
+        assert_eq!(parser.options.merge_policy(), &MergePolicy::UpdateExisting(When::GreaterThan));
+        assert_eq!(parser.options.returns(), &Return::Changed);
+        assert_eq!(parser.entries, vec!["1".to_string(), "a".to_string()]);
+
+        let parser = parse(&["NX", "INCR", "CH", "1", "a"]);
+        assert_eq!(parser.options.merge_policy(), &MergePolicy::Require(Only::AddNew));
+        assert_eq!(parser.options.returns(), &Return::NewScore);
+
+        assert_eq!(parse(&["1", "a"]).options, AddOptions::default());
+        assert!(matches!(parse(&["NX", "XX", "1", "a"]).options.merge_policy(), MergePolicy::Diverged(_)));
+        assert!(matches!(parse(&["GT", "LT", "1", "a"]).options.merge_policy(), MergePolicy::Diverged(_)));
+        assert!(matches!(parse(&["NX", "GT", "1", "a"]).options.merge_policy(), MergePolicy::Diverged(_)));
+
+        let merged = Merged { added: 1, updated: 2 };
+        assert_eq!(parse(&["1", "a"]).options.count(&merged), 1);
+        assert_eq!(parse(&["CH", "1", "a"]).options.count(&merged), 3);
+    }
+
+    #[test]
+    fn removal() {
+        let mut d = OrderedScores::new();
+        for (score, member) in [(1f64, "a"), (2f64, "b"), (2f64, "c"), (3f64, "d"), (4f64, "e")] {
+            d.merge(score, member, &MergePolicy::Default);
+        }
+
+        let within = |d: &OrderedScores, min: &str, max: &str| {
+            d.scored_within(&ScoreRange::parse(min, max).unwrap())
+             .map(|(_, member)| member.clone())
+             .collect::<Vec<_>>()
+        };
+        assert_eq!(within(&d, "2", "3"), vec!["b", "c", "d"]);
+        assert_eq!(within(&d, "(2", "+inf"), vec!["d", "e"]);
+        assert_eq!(within(&d, "-inf", "(2"), vec!["a"]);
+        assert_eq!(within(&d, "(2", "(2"), Vec::<String>::new());
+        assert_eq!(within(&d, "3", "2"), Vec::<String>::new());
+        assert!(ScoreRange::parse("nan", "1").is_none());
+
+        assert_eq!(d.remove("c"), Some(2f64));
+        assert_eq!(d.remove("c"), None);
+        assert_eq!(&members_at(&d, 2f64), &collections::BTreeSet::from(["b".to_string()]));
+
+        assert_eq!(d.pop(&PopFrom::Max, 2), vec![(4f64, "e".to_string()), (3f64, "d".to_string())]);
+        assert_eq!(d.pop(&PopFrom::Min, 1), vec![(1f64, "a".to_string())]);
+        assert_eq!(d.pop(&PopFrom::Min, 5), vec![(2f64, "b".to_string())]);
+        assert!(d.is_empty());
+        assert!(d.ranked.is_empty());
+    }
+
+    #[test]
+    fn rank_spans() {
+        assert_eq!(rank_span(0, -1, 5), Some((0, 4)));
+        assert_eq!(rank_span(-2, -1, 5), Some((3, 4)));
+        assert_eq!(rank_span(1, 100, 5), Some((1, 4)));
+        assert_eq!(rank_span(-100, 1, 5), Some((0, 1)));
+        assert_eq!(rank_span(3, 1, 5), None);
+        assert_eq!(rank_span(5, 10, 5), None);
+        assert_eq!(rank_span(0, -1, 0), None);
+    }
+
+    #[test]
+    fn lex_ranges() {
+        let range = LexRange::parse("[b", "(d").unwrap();
+        assert!(!range.contains("a"));
+        assert!(range.contains("b"));
+        assert!(range.contains("cat"));
+        assert!(!range.contains("d"));
+        assert!(LexRange::parse("-", "+").unwrap().contains("anything"));
+        assert!(!LexRange::parse("+", "+").unwrap().contains("anything"));
+        assert!(LexRange::parse("b", "[d").is_none());
+    }
+
+    #[test]
+    fn range_queries() {
+        let mut d = OrderedScores::new();
+        for (score, member) in [(1f64, "a"), (2f64, "b"), (3f64, "c"), (4f64, "d"), (5f64, "e")] {
+            d.merge(score, member, &MergePolicy::Default);
+        }
+        let members = |start: &str, stop: &str, options: &[&str]| {
+            let (query, _) = RangeQuery::parse(start, stop, options).unwrap();
+            d.query(&query).into_iter().map(|(_, member)| member).collect::<Vec<_>>()
+        };
+
+        assert_eq!(members("0", "-1", &[]), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(members("-2", "-1", &[]), vec!["d", "e"]);
+        assert_eq!(members("0", "1", &["REV"]), vec!["e", "d"]);
+        assert_eq!(members("(1", "3", &["BYSCORE"]), vec!["b", "c"]);
+        assert_eq!(members("+inf", "(3", &["BYSCORE", "REV"]), vec!["e", "d"]);
+        assert_eq!(members("-inf", "+inf", &["byscore", "limit", "1", "2"]), vec!["b", "c"]);
+        assert_eq!(members("-inf", "+inf", &["BYSCORE", "LIMIT", "3", "-1"]), vec!["d", "e"]);
+        assert_eq!(members("-inf", "+inf", &["BYSCORE", "LIMIT", "-1", "1"]), Vec::<String>::new());
+        assert_eq!(members("[b", "(d", &["BYLEX"]), vec!["b", "c"]);
+        assert_eq!(members("+", "[d", &["BYLEX", "REV"]), vec!["e", "d"]);
+
+        assert!(RangeQuery::parse("0", "1", &["LIMIT", "0", "1"]).is_err());
+        assert!(RangeQuery::parse("-", "+", &["BYLEX", "WITHSCORES"]).is_err());
+        assert!(RangeQuery::parse("0", "1", &["BYSCORE", "BYLEX"]).is_err());
+        assert!(RangeQuery::parse("0", "1", &["BYSCORE", "LIMIT", "0"]).is_err());
+        assert!(RangeQuery::parse("0", "1", &["WITHSCORES"]).unwrap().1);
+    }
+
+    #[test]
+    fn combinations() {
+        let make = |entries: &[(f64, &str)]| {
+            let mut d = OrderedScores::new();
+            for (score, member) in entries {
+                d.merge(*score, member, &MergePolicy::Default);
+            }
+            d
+        };
+        let x = make(&[(1f64, "a"), (2f64, "b"), (3f64, "c")]);
+        let y = make(&[(10f64, "b"), (20f64, "c"), (30f64, "d")]);
+        let combine = |operation, options: &[&str]| {
+            let args = [&["2", "x", "y"], options].concat();
+            let (combination, _) = Combination::parse(operation, &args).unwrap();
+            OrderedScores::combine(&[&x, &y], &combination)
+                .entries()
+                .map(|(score, member)| (score, member.clone()))
+                .collect::<Vec<_>>()
+        };
+        let entries = |xs: &[(f64, &str)]| xs.iter().map(|(s, m)| (*s, m.to_string())).collect::<Vec<_>>();
+
+        assert_eq!(
+            combine(SetOperation::Union, &[]),
+            entries(&[(1f64, "a"), (12f64, "b"), (23f64, "c"), (30f64, "d")])
+        );
+        assert_eq!(
+            combine(SetOperation::Union, &["WEIGHTS", "2", "0.5", "AGGREGATE", "MAX"]),
+            entries(&[(2f64, "a"), (5f64, "b"), (10f64, "c"), (15f64, "d")])
+        );
+        assert_eq!(
+            combine(SetOperation::Intersection, &["aggregate", "min"]),
+            entries(&[(2f64, "b"), (3f64, "c")])
+        );
+        assert_eq!(
+            combine(SetOperation::Intersection, &["WEIGHTS", "0", "-1"]),
+            entries(&[(-20f64, "c"), (-10f64, "b")])
+        );
+        assert_eq!(combine(SetOperation::Difference, &[]), entries(&[(1f64, "a")]));
+
+        assert!(Combination::parse(SetOperation::Union, &["0"]).is_err());
+        assert!(Combination::parse(SetOperation::Union, &["2", "x"]).is_err());
+        assert!(Combination::parse(SetOperation::Union, &["2", "x", "y", "WEIGHTS", "1"]).is_err());
+        assert!(Combination::parse(SetOperation::Union, &["1", "x", "AGGREGATE", "AVG"]).is_err());
+        assert!(Combination::parse(SetOperation::Difference, &["1", "x", "WEIGHTS", "1"]).is_err());
+        assert!(Combination::parse(SetOperation::Difference, &["1", "x", "WITHSCORES"]).unwrap().1);
+    }
+
+    #[test]
+    fn ranks_and_snapshot_layout() {
+        let mut d = OrderedScores::new();
+        for i in 0..100 {
+            d.merge(i as f64 % 10f64, &format!("m{i:02}"), &MergePolicy::Default);
+        }
+        let stats = d.member_stats("m10").unwrap();
+        assert_eq!((stats.rank, stats.reverse_rank, stats.score), (1, 98, 0f64));
+        assert_eq!(d.member_stats("m99").unwrap().reverse_rank, 0);
+        assert!(d.member_stats("m100").is_none());
+
+        let image = bincode::serialize(&d).unwrap();
+        let scores: collections::HashMap<String, f64> = bincode::deserialize(&image).unwrap();
+        assert_eq!(scores.len(), 100);
+        assert_eq!(scores.get("m10"), Some(&0f64));
+
+        let restored: OrderedScores = bincode::deserialize(&image).unwrap();
+        assert!(restored.entries().eq(d.entries()));
+        assert_eq!(restored.member_stats("m10").unwrap().rank, 1);
+
+        /* As written before format version 6. */
+        let mut tables = ScoreTables { member_to_score: Default::default(), score_to_members: Default::default() };
+        for (score, member) in d.entries() {
+            tables.member_to_score.insert(member.clone(), Score(score));
+            tables.score_to_members.entry(Score(score)).or_default().insert(member.clone());
+        }
+        let migrated: ScoreTables = bincode::deserialize(&bincode::serialize(&tables).unwrap()).unwrap();
+        assert!(OrderedScores::from(migrated).entries().eq(d.entries()));
+        assert!(image.len() < bincode::serialize(&tables).unwrap().len() * 2 / 3);
+    }
+}
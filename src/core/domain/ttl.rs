@@ -1,17 +1,23 @@
 use std::ops::{Deref, DerefMut};
-use std::collections;
 use std::time;
 use serde::{Deserialize, Serialize};
 
+use crate::core::clock::Clock;
+
 pub trait Expungeable {
     fn expunge(&mut self, id: &str);
 }
 
-#[derive(Deserialize, Serialize)]
+/* Every deadline in `ttls` is in `expires` too, and nothing else is: keys
+   may share a deadline, so each entry carries its key. Both are persistent,
+   so that a copy to save in the background shares them. */
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Lifetimes<Underlying: Expungeable + Serialize> {
-    expires:    collections::BTreeMap<time::SystemTime, String>,
-    ttls:       collections::HashMap<String, time::SystemTime>,
+    expires:    imbl::OrdSet<(time::SystemTime, String)>,
+    ttls:       imbl::HashMap<String, time::SystemTime>,
     underlying: Underlying,
+    #[serde(skip)]
+    expired:    Vec<String>,
 }
 
 impl <A: Expungeable + Serialize> Deref for Lifetimes<A> {
@@ -30,23 +36,63 @@ impl <A: Expungeable + Serialize> DerefMut for Lifetimes<A> {
 impl <Underlying: Expungeable + Serialize> Lifetimes<Underlying> {
     pub fn new(underlying: Underlying) -> Self {
         Self {
-            expires: collections::BTreeMap::new(),
-            ttls:    collections::HashMap::new(),
+            expires: imbl::OrdSet::new(),
+            ttls:    imbl::HashMap::new(),
             underlying,
+            expired: Vec::new(),
+        }
+    }
+
+    /* The same deadlines over some other representation of the data. */
+    pub fn map<B, F>(self, convert: F) -> Lifetimes<B>
+    where
+        B: Expungeable + Serialize,
+        F: FnOnce(Underlying) -> B,
+    {
+        Lifetimes {
+            expires:    self.expires,
+            ttls:       self.ttls,
+            underlying: convert(self.underlying),
+            expired:    self.expired,
         }
     }
 
-    pub fn expunge_expired(&mut self, now: &time::SystemTime) {
-        while let Some((expires, key)) = self.expires.pop_first() {
-            let expires_at = self.ttls.get(&key).unwrap_or(&expires);
-            if expires_at < now {
-                println!("expunge_expired: key={key:?}");
-                self.underlying.expunge(&key);
-            } else {
-                self.expires.insert(*expires_at, key);
-                break;
+    /* Keys expunged since the last call, for keyspace notifications. */
+    pub fn take_expired(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.expired)
+    }
+
+    pub fn expunge_expired(&mut self, clock: &dyn Clock) {
+        self.expunge_expired_until(clock, None);
+    }
+
+    /* Deadlines are kept in order, so there is no need to sample: what is
+       due comes first. Gives up once the clock passes `budget`, and says
+       whether it got through everything that was due. */
+    pub fn expunge_expired_until(&mut self, clock: &dyn Clock, budget: Option<time::Instant>) -> bool {
+        const CHECK_BUDGET_EVERY: usize = 16;
+        let now = clock.now();
+        let mut visited = 0;
+        while self.next_deadline().is_some_and(|expires| expires < now) {
+            visited += 1;
+            if visited % CHECK_BUDGET_EVERY == 0 && budget.is_some_and(|budget| clock.instant() >= budget) {
+                return false
             }
+            let Some((_, key)) = self.expires.remove_min() else { break };
+            println!("expunge_expired: key={key:?}");
+            self.ttls.remove(&key);
+            self.underlying.expunge(&key);
+            self.expired.push(key);
         }
+        true
+    }
+
+    pub fn next_deadline(&self) -> Option<time::SystemTime> {
+        self.expires.get_min().map(|(at, _)| *at)
+    }
+
+    pub fn expiring_count(&self) -> usize {
+        self.ttls.len()
     }
 
     pub fn register_ttl(
@@ -55,11 +101,59 @@ impl <Underlying: Expungeable + Serialize> Lifetimes<Underlying> {
         now: time::SystemTime, 
         ttl: time::Duration
     ) {
-        let at = now + ttl;
-        self.ttls.entry(key.to_string())
-            .and_modify(|expires_at| *expires_at = at)
-            .or_insert(at);
-        self.expires.insert(at, key.to_string());
+        self.expire_at(key, now + ttl);
+    }
+
+    pub fn expire_at(&mut self, key: &str, at: time::SystemTime) {
+        if let Some(previous) = self.ttls.insert(key.to_string(), at) {
+            self.expires.remove(&(previous, key.to_string()));
+        }
+        self.expires.insert((at, key.to_string()));
+    }
+
+    pub fn deadline(&self, key: &str) -> Option<time::SystemTime> {
+        self.ttls.get(key).copied()
+    }
+
+    pub fn clear_ttl(&mut self, key: &str) {
+        if let Some(at) = self.ttls.remove(key) {
+            self.expires.remove(&(at, key.to_string()));
+        }
+    }
+
+    /* RENAME takes the deadline along, and drops whatever `to` had. */
+    pub fn move_ttl(&mut self, from: &str, to: &str) {
+        if from != to {
+            let at = self.ttls.get(from).copied();
+            self.clear_ttl(from);
+            self.clear_ttl(to);
+            if let Some(at) = at {
+                self.expire_at(to, at);
+            }
+        }
+    }
+
+    /* COPY gives `to` the same deadline as `from`. */
+    pub fn copy_ttl(&mut self, from: &str, to: &str) {
+        if from != to {
+            self.clear_ttl(to);
+            if let Some(&at) = self.ttls.get(from) {
+                self.expire_at(to, at);
+            }
+        }
+    }
+
+    pub fn clear_ttls(&mut self) {
+        self.ttls.clear();
+        self.expires.clear();
+    }
+
+    /* Snapshots from before deadlines could be shared may have lost some
+       from `expires`, or kept stale ones; `ttls` is the one to trust. */
+    pub fn reindex(&mut self) {
+        self.expires = self.ttls.iter()
+            .map(|(key, &at)| (at, key.clone()))
+            .collect();
     }
 
     pub fn ttl_remaining(
@@ -73,27 +167,33 @@ impl <Underlying: Expungeable + Serialize> Lifetimes<Underlying> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections;
     use std::io;
     use super::*;
     use crate::core;
     use crate::core::domain::keyvalues::*;
     use crate::core::tx_log;
+    use crate::core::clock::ManualClock;
     use crate::core::domain::ttl;
+    use crate::core::domain::sorted_sets::*;
+    use crate::generic::{Expiry, Generic};
+    use arbitrary::{Arbitrary, Unstructured};
+    use rand::Rng;
 
     fn make_domain() -> Result<core::State, io::Error> {
-        Ok(tx_log::LoggedTransactions::new(
+        tx_log::LoggedTransactions::new(
             ttl::Lifetimes::new(core::Datasets::new())
-        )?)
+        )
     }
 
     #[test]
     fn register_ttl() {
         let mut st = make_domain().unwrap();
         let now = time::SystemTime::now();
-        assert_eq!(st.ttl_remaining(&"key".to_string(), &now), None);
-        st.register_ttl(&"key".to_string(), now, time::Duration::from_secs(1));
+        assert_eq!(st.ttl_remaining("key", &now), None);
+        st.register_ttl("key", now, time::Duration::from_secs(1));
         assert_eq!(
-            st.ttl_remaining(&"key".to_string(), &now), 
+            st.ttl_remaining("key", &now), 
             Some(time::Duration::from_secs(1))
         );
     }
@@ -101,12 +201,179 @@ mod tests {
     #[test]
     fn expires_the_right_one() {
         let mut st = make_domain().unwrap();
-        let now = time::SystemTime::now();
+        let clock = ManualClock::new(time::SystemTime::now());
         st.set("key", "value");
-        st.register_ttl(&"key".to_string(), now, time::Duration::from_secs(0));
-        assert_eq!(st.get("key").ok(), Some("value".to_string()));
+        st.register_ttl("key", clock.now(), time::Duration::from_secs(1));
         st.set("key2", "value");
-        assert_eq!(st.get("key").ok(), None);
-        assert_eq!(st.get("key2").ok(), Some("value".to_string()));
+        st.expunge_expired(&clock);
+        assert_eq!(st.get("key"), Ok(Some("value".to_string())));
+        clock.advance(time::Duration::from_millis(1001));
+        st.expunge_expired(&clock);
+        assert_eq!(st.get("key"), Ok(None));
+        assert_eq!(st.get("key2"), Ok(Some("value".to_string())));
+        assert_eq!(st.take_expired(), vec!["key".to_string()]);
+        assert!(st.take_expired().is_empty());
+    }
+
+    #[test]
+    fn expunges_only_live_deadlines() {
+        let mut st = make_domain().unwrap();
+        let clock = ManualClock::new(time::SystemTime::now());
+        let now = clock.now();
+        let past = now - time::Duration::from_secs(10);
+        for key in ["a", "b", "c"] {
+            st.set(key, "value");
+        }
+        st.register_ttl("a", past, time::Duration::from_secs(1));
+        st.register_ttl("a", now, time::Duration::from_secs(100));
+        st.register_ttl("b", past, time::Duration::from_secs(2));
+        st.clear_ttl("b");
+        st.register_ttl("b", past, time::Duration::from_secs(3));
+        st.register_ttl("c", past, time::Duration::from_secs(4));
+        assert_eq!(st.next_deadline(), Some(past + time::Duration::from_secs(3)));
+
+        assert!(st.expunge_expired_until(&clock, Some(clock.instant())));
+        let mut expired = st.take_expired();
+        expired.sort();
+        assert_eq!(expired, vec!["b".to_string(), "c".to_string()]);
+        assert_eq!(st.get("a"), Ok(Some("value".to_string())));
+        assert_eq!(st.deadline("c"), None);
+        assert_eq!(st.expiring_count(), 1);
+        assert_eq!(st.next_deadline(), Some(now + time::Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn expunging_within_a_budget() {
+        let mut st = make_domain().unwrap();
+        let clock = ManualClock::new(time::SystemTime::now());
+        let past = clock.now() - time::Duration::from_secs(10);
+        for i in 0..100 {
+            st.set(&format!("key:{i}"), "value");
+        }
+        for i in 0..100 {
+            st.register_ttl(&format!("key:{i}"), past, time::Duration::from_millis(i));
+        }
+        assert!(!st.expunge_expired_until(&clock, Some(clock.instant())));
+        assert_eq!(st.take_expired().len(), 15);
+        assert!(st.expunge_expired_until(&clock, None));
+        assert_eq!(st.take_expired().len(), 85);
+        assert_eq!(st.keys().count(), 0);
     }
-}
\ No newline at end of file
+
+    #[derive(Arbitrary, Debug)]
+    enum Operation {
+        Set(u8),
+        Add(u8, u8),
+        Pop(u8),
+        Delete(u8),
+        Rename(u8, u8),
+        Copy(u8, u8, bool),
+        Expire(u8, u8),
+        Persist(u8),
+        Expunge(u8),
+    }
+
+    #[derive(Clone, Debug)]
+    enum Modelled {
+        String,
+        SortedSet(collections::BTreeSet<String>),
+    }
+
+    /* Random operations on a handful of keys, so that they keep running
+       into each other, against a model of what deadlines there should be. */
+    #[test]
+    fn deadlines_follow_their_keys() {
+        use crate::commands::ExpiryCondition::Always;
+
+        let key = |k: u8| format!("k{}", k % 3);
+
+        for _ in 0..200 {
+            let mut random_bytes = [0u8; 256];
+            rand::thread_rng().fill(&mut random_bytes[..]);
+            let mut unstructured = Unstructured::new(&random_bytes);
+            let mut operations = vec![];
+            while !unstructured.is_empty() {
+                operations.push(unstructured.arbitrary::<Operation>().unwrap());
+            }
+
+            let clock = ManualClock::new(time::SystemTime::now());
+            let at = |secs: u8| clock.now() + time::Duration::from_secs(secs as u64);
+            let mut st = make_domain().unwrap();
+            let mut model = collections::HashMap::<String, (Modelled, Option<time::SystemTime>)>::new();
+            for operation in &operations {
+                match *operation {
+                    Operation::Set(k) => {
+                        st.set(&key(k), "value");
+                        model.insert(key(k), (Modelled::String, None));
+                    },
+                    Operation::Add(k, member) => {
+                        let added = st.add(&key(k), &[(1.0, &member.to_string())], &MergePolicy::Default);
+                        match model.entry(key(k)).or_insert((Modelled::SortedSet(Default::default()), None)) {
+                            (Modelled::SortedSet(members), _) => { members.insert(member.to_string()); },
+                            (Modelled::String, _)             => assert!(added.is_err()),
+                        }
+                    },
+                    Operation::Pop(k) => {
+                        let popped = st.pop(&key(k), &PopFrom::Min, 1);
+                        if let Some((Modelled::SortedSet(members), _)) = model.get_mut(&key(k)) {
+                            /* Equal scores go in lexical order. */
+                            let first = members.pop_first();
+                            assert_eq!(popped.unwrap().into_iter().map(|(_, member)| member).next(), first);
+                            if members.is_empty() {
+                                model.remove(&key(k));
+                            }
+                        }
+                    },
+                    Operation::Delete(k) => {
+                        st.delete_keys(&[key(k)]);
+                        model.remove(&key(k));
+                    },
+                    Operation::Rename(from, to) => {
+                        st.rename(&key(from), &key(to), false);
+                        if let Some(entry) = model.remove(&key(from)) {
+                            model.insert(key(to), entry);
+                        }
+                    },
+                    Operation::Copy(from, to, replace) => {
+                        st.copy(&key(from), &key(to), replace);
+                        let admitted = key(from) != key(to) && (replace || !model.contains_key(&key(to)));
+                        if let Some(entry) = model.get(&key(from)).cloned().filter(|_| admitted) {
+                            model.insert(key(to), entry);
+                        }
+                    },
+                    Operation::Expire(k, secs) => {
+                        let expiry = st.expire(&key(k), at(secs % 8), &Always, clock.now());
+                        match model.get_mut(&key(k)) {
+                            None                            => assert_eq!(expiry, Expiry::Ignored),
+                            Some(_) if secs % 8 == 0        => { model.remove(&key(k)); },
+                            Some((_, deadline))             => *deadline = Some(at(secs % 8)),
+                        }
+                    },
+                    Operation::Persist(k) => {
+                        st.persist(&key(k));
+                        if let Some((_, deadline)) = model.get_mut(&key(k)) {
+                            *deadline = None;
+                        }
+                    },
+                    Operation::Expunge(secs) => {
+                        clock.advance(time::Duration::from_secs((secs % 3) as u64));
+                        st.expunge_expired(&clock);
+                        model.retain(|_, (_, deadline)| deadline.is_none_or(|deadline| deadline >= clock.now()));
+                    },
+                }
+
+                let mut keys = st.keys().cloned().collect::<Vec<_>>();
+                let mut expected = model.keys().cloned().collect::<Vec<_>>();
+                keys.sort();
+                expected.sort();
+                assert_eq!(keys, expected, "after {operations:?}");
+                for (k, (_, deadline)) in &model {
+                    assert_eq!(st.deadline(k), *deadline, "{k} after {operations:?}");
+                }
+                assert!(st.ttls.keys().all(|k| model.contains_key(k)), "after {operations:?}");
+                assert_eq!(st.expires.len(), st.ttls.len());
+                assert!(st.expires.iter().all(|(at, k)| st.ttls.get(k) == Some(at)));
+            }
+        }
+    }
+}
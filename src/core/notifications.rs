@@ -0,0 +1,147 @@
+use std::collections;
+use std::fmt;
+use std::str;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventClass {
+    Generic,                    /* g */
+    String,                     /* $ */
+    List,                       /* l */
+    Set,                        /* s */
+    Hash,                       /* h */
+    SortedSet,                  /* z */
+    Expired,                    /* x */
+    Evicted,                    /* e */
+    Stream,                     /* t */
+    Module,                     /* d */
+    KeyMiss,                    /* m */
+    New,                        /* n */
+}
+
+impl EventClass {
+    /* Everything `A` stands for; m and n have to be asked for explicitly. */
+    const ALL: [EventClass; 10] = [
+        EventClass::Generic, EventClass::String, EventClass::List, EventClass::Set,
+        EventClass::Hash, EventClass::SortedSet, EventClass::Expired, EventClass::Evicted,
+        EventClass::Stream, EventClass::Module,
+    ];
+
+    fn letter(&self) -> char {
+        match self {
+            EventClass::Generic   => 'g',
+            EventClass::String    => '$',
+            EventClass::List      => 'l',
+            EventClass::Set       => 's',
+            EventClass::Hash      => 'h',
+            EventClass::SortedSet => 'z',
+            EventClass::Expired   => 'x',
+            EventClass::Evicted   => 'e',
+            EventClass::Stream    => 't',
+            EventClass::Module    => 'd',
+            EventClass::KeyMiss   => 'm',
+            EventClass::New       => 'n',
+        }
+    }
+
+    fn parse(letter: char) -> Option<EventClass> {
+        Self::ALL.iter()
+            .chain([EventClass::KeyMiss, EventClass::New].iter())
+            .find(|class| class.letter() == letter)
+            .copied()
+    }
+}
+
+/* The `notify-keyspace-events` setting. Nothing is published unless at
+   least one of K (keyspace) or E (keyevent) is on as well as the class. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyspaceEvents {
+    keyspace: bool,
+    keyevent: bool,
+    classes:  collections::HashSet<EventClass>,
+}
+
+impl KeyspaceEvents {
+    pub fn publishes(&self, class: EventClass) -> bool {
+        (self.keyspace || self.keyevent) && self.classes.contains(&class)
+    }
+
+    pub fn keyspace_channel(&self, key: &str) -> Option<String> {
+        self.keyspace.then(|| format!("__keyspace@0__:{key}"))
+    }
+
+    pub fn keyevent_channel(&self, event: &str) -> Option<String> {
+        self.keyevent.then(|| format!("__keyevent@0__:{event}"))
+    }
+}
+
+impl str::FromStr for KeyspaceEvents {
+    type Err = String;
+
+    fn from_str(flags: &str) -> Result<Self, Self::Err> {
+        let mut events = Self::default();
+        for letter in flags.chars() {
+            match letter {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'A' => events.classes.extend(EventClass::ALL),
+                otherwise =>
+                    if let Some(class) = EventClass::parse(otherwise) {
+                        events.classes.insert(class);
+                    } else {
+                        return Err(format!("unknown keyspace event class `{otherwise}`"))
+                    },
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if EventClass::ALL.iter().all(|class| self.classes.contains(class)) {
+            write!(f, "A")?;
+        } else {
+            for class in EventClass::ALL.iter().filter(|class| self.classes.contains(class)) {
+                write!(f, "{}", class.letter())?;
+            }
+        }
+        if self.keyspace { write!(f, "K")?; }
+        if self.keyevent { write!(f, "E")?; }
+        for class in [EventClass::KeyMiss, EventClass::New] {
+            if self.classes.contains(&class) {
+                write!(f, "{}", class.letter())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_flags() {
+        let events = "Ex".parse::<KeyspaceEvents>().unwrap();
+        assert!(events.publishes(EventClass::Expired));
+        assert!(!events.publishes(EventClass::Generic));
+        assert_eq!(events.keyspace_channel("key"), None);
+        assert_eq!(events.keyevent_channel("expired"), Some("__keyevent@0__:expired".to_string()));
+
+        let events = "g$lzx".parse::<KeyspaceEvents>().unwrap();
+        assert!(!events.publishes(EventClass::String));
+
+        assert!("Kw".parse::<KeyspaceEvents>().is_err());
+        assert_eq!("".parse::<KeyspaceEvents>().unwrap(), KeyspaceEvents::default());
+    }
+
+    #[test]
+    fn canonical_image() {
+        let image = |flags: &str| flags.parse::<KeyspaceEvents>().unwrap().to_string();
+        assert_eq!(image("EKxg"), "gxKE");
+        assert_eq!(image("KEA"), "AKE");
+        assert_eq!(image("g$lshzxetdKE"), "AKE");
+        assert_eq!(image("nKmz"), "zKmn");
+        assert_eq!(image(""), "");
+    }
+}
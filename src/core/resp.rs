@@ -3,8 +3,9 @@ use std::str::FromStr;
 use std::io::Error;
 use std::fmt;
 use arbitrary::Arbitrary;
+use serde::{Deserialize, Serialize};
 
-#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[derive(Arbitrary, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ErrorPrefix {
     Empty, Err,
     Named(String),
@@ -39,7 +40,7 @@ impl From<ErrorPrefix> for String {
     }
 }
 
-#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[derive(Arbitrary, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Message {
     SimpleString(String),
     Error { prefix: ErrorPrefix, message: String },
@@ -1,9 +1,16 @@
 use std::fs;
 use std::path;
 use std::io;
+use std::io::prelude::*;
+use std::ffi;
+use std::cmp;
+use std::cell;
+use std::time;
 
 use serde::{Serialize, de::DeserializeOwned};
 
+use crate::core::{config, tx_log};
+
 #[derive(Clone)]
 pub struct SnapshotFile {
     path: path::PathBuf,
@@ -15,45 +22,504 @@ impl SnapshotFile {
         Self { path: path.to_path_buf(), index }
     }
 
-    pub fn put<S: Serialize>(&self, data: &S) -> io::Result<()> {
-        let file = fs::File::options().write(true).create_new(true).open(self.path.as_path());
-        let writer = io::BufWriter::new(file?);
-        bincode::serialize_into(writer, data).map_err(|e|
-            io::Error::new(io::ErrorKind::Other, e.to_string())
-        )
+    pub fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    /* Written aside and renamed into place once on disk, so that a crash
+       leaves either the whole snapshot or none of it. The header is filled
+       in last, when the length and checksum are known. */
+    pub fn put<S: Serialize>(&self, data: &S, revision: &tx_log::Revision, encoding: &Encoding) -> io::Result<()> {
+        let temporary = self.temporary_path();
+        let mut file = fs::File::options().write(true).create(true).truncate(true).open(&temporary)?;
+        file.write_all(&Header::default().to_bytes())?;
+        let mut writer = Checksummed::new(io::BufWriter::new(file));
+        compressed::values_over(encoding.compress_values_over, ||
+            encode(&mut writer, encoding.compression, data)
+        )?;
+        let header = Header {
+            revision:    usize::from(revision.clone()) as u64,
+            compression: encoding.compression,
+            ..writer.header()
+        };
+        let mut file = writer.inner.into_inner().map_err(|e| e.into_error())?;
+        file.rewind()?;
+        file.write_all(&header.to_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn temporary_path(&self) -> path::PathBuf {
+        let mut name = ffi::OsString::from(self.path.as_os_str());
+        name.push(".tmp");
+        name.into()
     }
 
     pub fn get<D>(&self) -> io::Result<D>
     where D: DeserializeOwned {   /* Wtf. */
+        self.get_or_migrate::<D, D>()
+    }
+
+    /* Snapshots without the magic prefix predate versioning and are read as
+       a Legacy, then converted. */
+    pub fn get_or_migrate<D, Legacy>(&self) -> io::Result<D>
+    where
+        D: DeserializeOwned,
+        Legacy: DeserializeOwned + Into<D>,
+    {
         let file = fs::File::options().read(true).open(self.path.as_path());
-        let reader = io::BufReader::new(file?);
-        bincode::deserialize_from(reader).map_err(|e|
-            io::Error::new(io::ErrorKind::Other, e.to_string())
+        let mut reader = io::BufReader::new(file?);
+        let mut prefix = [0u8; MAGIC.len()];
+        let versioned = reader.read_exact(&mut prefix).is_ok() && &prefix == MAGIC;
+        if versioned {
+            let mut version = [0u8; 4];
+            reader.read_exact(&mut version).map_err(|_| self.damaged("truncated header"))?;
+            match u32::from_le_bytes(version) {
+                FORMAT_VERSION => {
+                    let header = self.read_header(&mut reader)?;
+                    let start = reader.stream_position()?;
+                    self.verify(&mut reader, &header)?;
+                    reader.seek(io::SeekFrom::Start(start))?;
+                    decode(reader.take(header.length), header.compression)
+                },
+                otherwise => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: unknown snapshot format version {otherwise}", self.path.display()),
+                )),
+            }
+        } else {
+            reader.rewind()?;
+            deserialize::<Legacy, _>(reader).map(Into::into)
+        }
+    }
+
+    /* The revision it was taken at, without reading the rest; None for
+       snapshots from before there was a header. */
+    pub fn revision(&self) -> io::Result<Option<tx_log::Revision>> {
+        let mut reader = io::BufReader::new(fs::File::open(&self.path)?);
+        let mut prefix = [0u8; MAGIC.len() + 4];
+        reader.read_exact(&mut prefix).map_err(|_| self.damaged("truncated header"))?;
+        let version = u32::from_le_bytes(prefix[MAGIC.len()..].try_into().unwrap_or_default());
+        if prefix[..MAGIC.len()] != MAGIC[..] || version != FORMAT_VERSION {
+            return Ok(None)
+        }
+        let header = self.read_header(&mut reader)?;
+        Ok(Some(tx_log::Revision::from(header.revision as usize)))
+    }
+
+    /* Whether it reads back whole, going by the header and checksum alone,
+       with nothing deserialized. Those from before checksums cannot say. */
+    pub fn verify_whole(&self) -> io::Result<()> {
+        let mut reader = io::BufReader::new(fs::File::open(&self.path)?);
+        let mut prefix = [0u8; MAGIC.len() + 4];
+        reader.read_exact(&mut prefix).map_err(|_| self.damaged("truncated header"))?;
+        let version = u32::from_le_bytes(prefix[MAGIC.len()..].try_into().unwrap_or_default());
+        if prefix[..MAGIC.len()] != MAGIC[..] || version != FORMAT_VERSION {
+            return Err(self.damaged("no checksum to go by"))
+        }
+        let header = self.read_header(&mut reader)?;
+        self.verify(&mut reader, &header)
+    }
+
+    /* Right after the version. */
+    fn read_header<R: io::Read>(&self, reader: &mut R) -> io::Result<Header> {
+        let mut fields = [0u8; 24];
+        reader.read_exact(&mut fields).map_err(|_| self.damaged("truncated header"))?;
+        let compression = match u32::from_le_bytes(fields[20..].try_into().unwrap_or_default()) {
+            0          => config::Compression::No,
+            1          => config::Compression::Lz4,
+            2          => config::Compression::Zstd,
+            _otherwise => return Err(self.damaged("unknown compression")),
+        };
+        Ok(Header {
+            length:   u64::from_le_bytes(fields[..8].try_into().unwrap_or_default()),
+            checksum: u32::from_le_bytes(fields[8..12].try_into().unwrap_or_default()),
+            revision: u64::from_le_bytes(fields[12..20].try_into().unwrap_or_default()),
+            compression,
+        })
+    }
+
+    /* A whole pass over the payload before anything is deserialized: a
+       damaged length could otherwise ask for any amount of memory. */
+    fn verify<R: io::Read>(&self, reader: &mut R, expected: &Header) -> io::Result<()> {
+        let mut payload = Checksummed::new(reader.take(expected.length));
+        io::copy(&mut payload, &mut io::sink())?;
+        let found = payload.header();
+        if found.length != expected.length {
+            Err(self.damaged("truncated"))
+        } else if found.checksum != expected.checksum {
+            Err(self.damaged("checksum mismatch"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn damaged(&self, what: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {what}", self.path.display()))
+    }
+}
+
+const MAGIC: &[u8; 8] = b"PELICAN\0";
+
+/* After the version: the payload's length and CRC-32, the revision, and
+   how the payload is compressed; string values may be compressed on their
+   own too. One keyspace of typed values, rather than a map per type, and
+   sorted sets as their members' scores alone. */
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Default)]
+struct Header {
+    length:      u64,
+    checksum:    u32,
+    revision:    u64,
+    compression: config::Compression,
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let compression: u32 = match self.compression {
+            config::Compression::No   => 0,
+            config::Compression::Lz4  => 1,
+            config::Compression::Zstd => 2,
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        bytes.extend(self.length.to_le_bytes());
+        bytes.extend(self.checksum.to_le_bytes());
+        bytes.extend(self.revision.to_le_bytes());
+        bytes.extend(compression.to_le_bytes());
+        bytes
+    }
+}
+
+/* How a snapshot is written: compressed as a whole, and string values of
+   at least some length on their own as well, unless that is 0. */
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Encoding {
+    pub compression:          config::Compression,
+    pub compress_values_over: usize,
+}
+
+/* Counts and checksums whatever passes through, either way. */
+struct Checksummed<A> {
+    inner:  A,
+    hasher: crc32fast::Hasher,
+    length: u64,
+}
+
+impl<A> Checksummed<A> {
+    fn new(inner: A) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new(), length: 0 }
+    }
+
+    fn header(&self) -> Header {
+        Header { length: self.length, checksum: self.hasher.clone().finalize(), ..Header::default() }
+    }
+
+    fn count(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.length += bytes.len() as u64;
+    }
+}
+
+impl<W: io::Write> io::Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: io::Read> io::Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn deserialize<D: DeserializeOwned, R: io::Read>(reader: R) -> io::Result<D> {
+    bincode::deserialize_from(reader).map_err(|e|
+        io::Error::other(e.to_string())
+    )
+}
+
+/* Through the compressor as it is serialized, so that there is never a
+   second copy of the data in memory. */
+fn encode<S: Serialize, W: io::Write>(writer: W, compression: config::Compression, data: &S) -> io::Result<()> {
+    fn serialize<S: Serialize, W: io::Write>(writer: W, data: &S) -> io::Result<()> {
+        bincode::serialize_into(writer, data).map_err(|e|
+            io::Error::other(e.to_string())
+        )
+    }
+
+    match compression {
+        config::Compression::No =>
+            serialize(writer, data),
+        config::Compression::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(writer);
+            serialize(&mut encoder, data)?;
+            encoder.finish().map(|_| ()).map_err(io::Error::other)
+        },
+        config::Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            serialize(&mut encoder, data)?;
+            encoder.finish().map(|_| ())
+        },
+    }
+}
+
+fn decode<D: DeserializeOwned, R: io::Read>(reader: R, compression: config::Compression) -> io::Result<D> {
+    match compression {
+        config::Compression::No   => deserialize(reader),
+        config::Compression::Lz4  => deserialize(lz4_flex::frame::FrameDecoder::new(reader)),
+        config::Compression::Zstd => deserialize(zstd::Decoder::new(reader)?),
+    }
+}
+
+/* For string values, as `#[serde(with = "snapshots::compressed")]`: those
+   long enough are compressed on their own while a snapshot is written.
+   Serde has no way to hand down how long that is, so it is set aside for
+   the thread doing the writing. */
+pub mod compressed {
+    use super::*;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    thread_local! {
+        static OVER: cell::Cell<usize> = const { cell::Cell::new(0) };
+    }
+
+    pub(super) fn values_over<A>(length: usize, write: impl FnOnce() -> A) -> A {
+        let previous = OVER.replace(length);
+        let written = write();
+        OVER.set(previous);
+        written
+    }
+
+    #[derive(Serialize)]
+    enum Written<'a> {
+        Plain(&'a str),
+        Lz4(Vec<u8>),
+    }
+
+    #[derive(Deserialize)]
+    enum Read {
+        Plain(String),
+        Lz4(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        let over = OVER.get();
+        if over > 0 && value.len() >= over {
+            let compressed = lz4_flex::compress_prepend_size(value.as_bytes());
+            if compressed.len() < value.len() {
+                return Written::Lz4(compressed).serialize(serializer)
+            }
+        }
+        Written::Plain(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        match Read::deserialize(deserializer)? {
+            Read::Plain(value) =>
+                Ok(value),
+            Read::Lz4(compressed) => {
+                let bytes = lz4_flex::decompress_size_prepended(&compressed).map_err(de::Error::custom)?;
+                String::from_utf8(bytes).map_err(de::Error::custom)
+            },
+        }
+    }
+}
+
+/* For SAVE, BGSAVE, LASTSAVE and INFO persistence. */
+pub struct SaveStatus {
+    /* When the save under way began. */
+    in_progress:    Option<time::Instant>,
+    last_save:      time::SystemTime,
+    last_revision:  tx_log::Revision,
+    last_succeeded: bool,
+    last_duration:  Option<time::Duration>,
+    last_attempt:   Option<time::Instant>,
+    saves:          u64,
+}
+
+/* How long a failed save holds off the next scheduled one. */
+const RETRY_AFTER: time::Duration = time::Duration::from_secs(5);
+
+impl SaveStatus {
+    /* Whatever was on disk at start counts as saved. */
+    pub fn new(at: time::SystemTime, revision: tx_log::Revision) -> Self {
+        Self {
+            in_progress:    None,
+            last_save:      at,
+            last_revision:  revision,
+            last_succeeded: true,
+            last_duration:  None,
+            last_attempt:   None,
+            saves:          0,
+        }
+    }
+
+    pub fn is_in_progress(&self) -> bool {
+        self.in_progress.is_some()
+    }
+
+    pub fn begin(&mut self, at: time::Instant) {
+        self.in_progress = Some(at);
+        self.last_attempt = Some(at);
+    }
+
+    /* Whether any of the rules calls for a save by now. */
+    pub fn is_due(&self, rules: &config::SaveRules, revision: &tx_log::Revision, now: (time::SystemTime, time::Instant)) -> bool {
+        let (at, instant) = now;
+        if self.is_in_progress() {
+            return false
+        }
+        if !self.last_succeeded && self.last_attempt.is_some_and(|attempt| instant.saturating_duration_since(attempt) < RETRY_AFTER) {
+            return false
+        }
+        let elapsed = at.duration_since(self.last_save).unwrap_or_default().as_secs();
+        let changes = revision.changes_since(&self.last_revision);
+        rules.0.iter().any(|&(seconds, count)| changes > 0 && elapsed >= seconds && changes >= count)
+    }
+
+    pub fn finish(&mut self, outcome: &io::Result<()>, revision: tx_log::Revision, now: (time::SystemTime, time::Instant)) {
+        let (at, instant) = now;
+        self.last_duration = self.in_progress.take().map(|began| instant.saturating_duration_since(began));
+        self.last_succeeded = outcome.is_ok();
+        if outcome.is_ok() {
+            self.last_save = at;
+            self.last_revision = revision;
+            self.saves += 1;
+        }
+    }
+
+    pub fn last_save(&self) -> time::SystemTime {
+        self.last_save
+    }
+
+    pub fn info(&self, revision: &tx_log::Revision, now: time::Instant) -> String {
+        let seconds = |duration: Option<time::Duration>| duration.map_or(-1, |d| d.as_secs() as i64);
+        format!(
+            "rdb_changes_since_last_save:{}\r\nrdb_bgsave_in_progress:{}\r\nrdb_last_save_time:{}\r\n\
+             rdb_last_bgsave_status:{}\r\nrdb_last_bgsave_time_sec:{}\r\nrdb_current_bgsave_time_sec:{}\r\n\
+             rdb_saves:{}\r\n",
+            revision.changes_since(&self.last_revision),
+            self.is_in_progress() as u8,
+            self.last_save.duration_since(time::UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+            if self.last_succeeded { "ok" } else { "err" },
+            seconds(self.last_duration),
+            seconds(self.in_progress.map(|began| now.saturating_duration_since(began))),
+            self.saves,
         )
     }
 }
 
 pub trait Snapshots {
-    fn save_snapshot(&self) -> io::Result<()>;
-    fn restore_most_recent_snapshot(&mut self) -> io::Result<()>;
+    fn save_snapshot(&self, directory: &path::Path, encoding: &Encoding) -> io::Result<()>;
+    /* The newest snapshot from no later than the revision, if given; and
+       where it came from, if there was one. */
+    fn restore_most_recent_snapshot(
+        &mut self,
+        directory: &path::Path,
+        until:     Option<&tx_log::Revision>,
+    ) -> io::Result<Option<path::PathBuf>>;
 }
 
-fn mk_snapshot_file(index: usize) -> SnapshotFile {
-    let path_name = format!("./data/snapshot-{index}.data");
-    let path = path::Path::new(&path_name);
-    SnapshotFile::new(path, index)
+fn mk_snapshot_file(directory: &path::Path, index: usize) -> SnapshotFile {
+    SnapshotFile::new(&directory.join(format!("snapshot-{index}.data")), index)
 }
 
-pub fn most_recent() -> io::Result<Option<SnapshotFile>> {
+pub fn most_recent(directory: &path::Path) -> io::Result<Option<SnapshotFile>> {
     let mut files = vec![];
-    find_all(path::Path::new("./data"), &mut files)?;
+    find_all(directory, &mut files)?;
     Ok(files.iter().max_by_key(|f| f.index).cloned())
 }
 
-pub fn allocate_new() -> io::Result<SnapshotFile> {
-    Ok(most_recent()?.map_or_else(
-        ||  mk_snapshot_file(0), 
-        |f| mk_snapshot_file(f.index + 1))
+/* The newest snapshot that reads back whole and is from no later than
+   `until`, passing over any that are not. Those with a header are passed
+   over on the revision in it alone; legacy ones have to be read first, to
+   ask `revision_of`. None if nothing was early enough, and an error if
+   nothing could be read at all. */
+pub fn restore_newest<D, Legacy, F>(
+    directory:   &path::Path,
+    until:       Option<&tx_log::Revision>,
+    revision_of: F,
+) -> io::Result<Option<(path::PathBuf, D)>>
+where
+    D: DeserializeOwned,
+    Legacy: DeserializeOwned + Into<D>,
+    F: Fn(&D) -> tx_log::Revision,
+{
+    let mut files = vec![];
+    find_all(directory, &mut files)?;
+    files.sort_by_key(|f| cmp::Reverse(f.index));
+    let early_enough = |revision: &tx_log::Revision| until.is_none_or(|until| revision <= until);
+    let mut readable = false;
+    for file in &files {
+        /* A header that cannot be read is for get_or_migrate to report. */
+        if let Some(Ok(Some(revision))) = until.map(|_| file.revision()) {
+            if !early_enough(&revision) {
+                readable = true;
+                continue
+            }
+        }
+        match file.get_or_migrate::<D, Legacy>() {
+            Ok(restored) if early_enough(&revision_of(&restored)) =>
+                return Ok(Some((file.path.clone(), restored))),
+            Ok(_) =>
+                readable = true,
+            Err(e) =>
+                println!("restore_newest: Skipping, `{e}`."),
+        }
+    }
+    if files.is_empty() || readable {
+        Ok(None)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("none of the {} snapshots in {} could be read", files.len(), directory.display()),
+        ))
+    }
+}
+
+/* Removes all but the newest few snapshots that read back whole, answering
+   the revision the oldest of those left starts at, if it can tell. Damaged
+   ones do not count, lest the last good one go in their favour; nothing
+   goes until there are enough good ones. */
+pub fn retain_newest(directory: &path::Path, keep: usize) -> io::Result<Option<tx_log::Revision>> {
+    let mut files = vec![];
+    find_all(directory, &mut files)?;
+    files.sort_by_key(|f| cmp::Reverse(f.index));
+    let mut whole = 0;
+    for (at, file) in files.iter().enumerate() {
+        if let Err(e) = file.verify_whole() {
+            println!("retain_newest: Not counting, `{e}`.");
+            continue
+        }
+        whole += 1;
+        if whole == keep || at + 1 == files.len() {
+            for older in &files[at + 1..] {
+                fs::remove_file(&older.path)?;
+            }
+            return Ok(file.revision().unwrap_or(None))
+        }
+    }
+    Ok(None)
+}
+
+pub fn allocate_new(directory: &path::Path) -> io::Result<SnapshotFile> {
+    Ok(most_recent(directory)?.map_or_else(
+        ||  mk_snapshot_file(directory, 0),
+        |f| mk_snapshot_file(directory, f.index + 1))
     )
 }
 
@@ -64,8 +530,9 @@ fn find_all(in_path: &path::Path, snapshots: &mut Vec<SnapshotFile>) -> io::Resu
         Some(SnapshotFile::new(path, index))
     }
 
-    let pattern = regex::Regex::new("snapshot-(\\d+)").map_err(|e|
-        io::Error::new(io::ErrorKind::Other, e.to_string())
+    /* Not the temporary files snapshots are written to first. */
+    let pattern = regex::Regex::new("^snapshot-(\\d+)\\.data$").map_err(|e|
+        io::Error::other(e.to_string())
     )?;
 
     for dir in fs::read_dir(in_path)? {
@@ -75,4 +542,194 @@ fn find_all(in_path: &path::Path, snapshots: &mut Vec<SnapshotFile>) -> io::Resu
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use rand::{distributions::Alphanumeric, Rng};
+
+    fn generate_name() -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(25)
+            .map(char::from)
+            .collect::<String>()
+    }
+
+    fn temp_snapshot() -> SnapshotFile {
+        SnapshotFile::new(&temp_dir().join(generate_name()), 0)
+    }
+
+    #[test]
+    fn save_status() {
+        let (at, instant) = (time::UNIX_EPOCH + time::Duration::from_secs(100), time::Instant::now());
+        let later = |secs| (at + time::Duration::from_secs(secs), instant + time::Duration::from_secs(secs));
+        let revision = tx_log::Revision::default().succeeding();
+        let mut status = SaveStatus::new(at, tx_log::Revision::default());
+        assert!(status.info(&revision, instant).contains("rdb_changes_since_last_save:1\r\n"));
+
+        status.begin(instant);
+        let info = status.info(&revision, later(3).1);
+        assert!(info.contains("rdb_bgsave_in_progress:1\r\n") && info.contains("rdb_current_bgsave_time_sec:3\r\n"));
+
+        status.finish(&Err(io::Error::other("disk full")), revision.clone(), later(4));
+        assert_eq!(status.last_save(), at);
+        assert!(status.info(&revision, instant).contains("rdb_last_bgsave_status:err\r\n"));
+
+        status.begin(instant);
+        status.finish(&Ok(()), revision.clone(), later(5));
+        let info = status.info(&revision, instant);
+        assert_eq!(status.last_save(), later(5).0);
+        assert!(info.contains("rdb_changes_since_last_save:0\r\n") && info.contains("rdb_last_save_time:105\r\n"));
+        assert!(info.contains("rdb_last_bgsave_status:ok\r\n") && info.contains("rdb_saves:1\r\n"));
+    }
+
+    #[test]
+    fn save_rules() {
+        let (at, instant) = (time::UNIX_EPOCH + time::Duration::from_secs(100), time::Instant::now());
+        let later = |secs| (at + time::Duration::from_secs(secs), instant + time::Duration::from_secs(secs));
+        let rules: config::SaveRules = "60 1 10 100".parse().unwrap();
+        let mut status = SaveStatus::new(at, tx_log::Revision::default());
+        assert!(!status.is_due(&rules, &tx_log::Revision::from(0), later(3600)));
+        assert!(!status.is_due(&rules, &tx_log::Revision::from(5), later(30)));
+        assert!(status.is_due(&rules, &tx_log::Revision::from(5), later(60)));
+        assert!(status.is_due(&rules, &tx_log::Revision::from(100), later(10)));
+
+        status.begin(later(60).1);
+        assert!(!status.is_due(&rules, &tx_log::Revision::from(100), later(60)));
+        /* Failed saves are retried, though not straight away. */
+        status.finish(&Err(io::Error::other("disk full")), tx_log::Revision::from(100), later(61));
+        assert!(!status.is_due(&rules, &tx_log::Revision::from(100), later(62)));
+        assert!(status.is_due(&rules, &tx_log::Revision::from(100), later(65)));
+    }
+
+    #[test]
+    fn retains_the_newest() {
+        let directory = temp_dir().join(generate_name());
+        fs::create_dir(&directory).unwrap();
+        for index in 0..4 {
+            mk_snapshot_file(&directory, index).put(&index, &tx_log::Revision::from(index * 10), &Encoding::default()).unwrap();
+        }
+        assert_eq!(retain_newest(&directory, 2).unwrap(), Some(tx_log::Revision::from(20)));
+        let mut files = vec![];
+        find_all(&directory, &mut files).unwrap();
+        let mut indices = files.iter().map(|f| f.index).collect::<Vec<_>>();
+        indices.sort();
+        assert_eq!(indices, vec![2, 3]);
+        assert_eq!(retain_newest(&directory, 5).unwrap(), Some(tx_log::Revision::from(20)));
+
+        /* A damaged one is kept, but does not count. */
+        for index in [4, 5] {
+            mk_snapshot_file(&directory, index).put(&index, &tx_log::Revision::from(index * 10), &Encoding::default()).unwrap();
+        }
+        let newest = mk_snapshot_file(&directory, 5);
+        let bytes = fs::read(newest.path()).unwrap();
+        fs::write(newest.path(), &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(retain_newest(&directory, 2).unwrap(), Some(tx_log::Revision::from(30)));
+        let mut files = vec![];
+        find_all(&directory, &mut files).unwrap();
+        let mut indices = files.iter().map(|f| f.index).collect::<Vec<_>>();
+        indices.sort();
+        assert_eq!(indices, vec![3, 4, 5]);
+        fs::write(mk_snapshot_file(&directory, 3).path(), b"").unwrap();
+        assert_eq!(retain_newest(&directory, 2).unwrap(), None);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn detects_damage() {
+        let snapshot = temp_snapshot();
+        snapshot.put(&(0..100u64).collect::<Vec<_>>(), &tx_log::Revision::default(), &Encoding::default()).unwrap();
+        assert!(!snapshot.temporary_path().exists());
+        let bytes = fs::read(&snapshot.path).unwrap();
+
+        let mut flipped = bytes.clone();
+        flipped[36 + 10] ^= 1;
+        fs::write(&snapshot.path, flipped).unwrap();
+        assert_eq!(snapshot.get::<Vec<u64>>().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        for length in [10, 36, bytes.len() - 1] {
+            fs::write(&snapshot.path, &bytes[..length]).unwrap();
+            assert_eq!(snapshot.get::<Vec<u64>>().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(snapshot.path).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_older_snapshots() {
+        let directory = temp_dir().join(generate_name());
+        fs::create_dir(&directory).unwrap();
+        let at = |index| mk_snapshot_file(&directory, index);
+        let restore_newest = |until: usize| restore_newest::<u64, u64, _>(
+            &directory, Some(&tx_log::Revision::from(until)), |&x| tx_log::Revision::from(x as usize)
+        );
+        assert!(restore_newest(9).unwrap().is_none());
+
+        at(1).put(&1u64, &tx_log::Revision::from(1), &Encoding::default()).unwrap();
+        at(2).put(&2u64, &tx_log::Revision::from(2), &Encoding::default()).unwrap();
+        fs::write(at(3).temporary_path(), b"half a snapshot").unwrap();
+        assert_eq!(restore_newest(9).unwrap(), Some((at(2).path, 2)));
+        assert_eq!(restore_newest(1).unwrap(), Some((at(1).path, 1)));
+        assert!(restore_newest(0).unwrap().is_none());
+
+        /* Too late by its header, which goes before what is in it. */
+        at(4).put(&3u64, &tx_log::Revision::from(5), &Encoding::default()).unwrap();
+        assert_eq!(restore_newest(4).unwrap(), Some((at(2).path, 2)));
+        assert_eq!(restore_newest(9).unwrap(), Some((at(4).path, 3)));
+        fs::remove_file(at(4).path()).unwrap();
+
+        let bytes = fs::read(at(2).path()).unwrap();
+        fs::write(at(2).path(), &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(restore_newest(9).unwrap(), Some((at(1).path, 1)));
+
+        fs::write(at(1).path(), b"").unwrap();
+        assert!(restore_newest(9).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn compresses() {
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Text(#[serde(with = "compressed")] String);
+
+        let texts = (0..50).map(|i| Text(format!("{i} ").repeat(1000))).collect::<Vec<_>>();
+        let size = |compression, compress_values_over| {
+            let snapshot = temp_snapshot();
+            snapshot.put(&texts, &tx_log::Revision::default(), &Encoding { compression, compress_values_over }).unwrap();
+            assert_eq!(snapshot.get::<Vec<Text>>().unwrap(), texts);
+            let size = fs::metadata(&snapshot.path).unwrap().len();
+            fs::remove_file(snapshot.path).unwrap();
+            size
+        };
+        let plain = size(config::Compression::No, 0);
+        assert!(size(config::Compression::Lz4, 0) < plain / 10);
+        assert!(size(config::Compression::Zstd, 0) < plain / 10);
+        assert!(size(config::Compression::No, 1000) < plain / 10);
+        /* Values shorter than that are left be. */
+        assert_eq!(size(config::Compression::No, 5000), plain);
+    }
+
+    #[test]
+    fn versions_and_migrates() {
+        let current = temp_snapshot();
+        current.put(&vec![1u64, 2, 3], &tx_log::Revision::from(7), &Encoding::default()).unwrap();
+        assert_eq!(current.get::<Vec<u64>>().unwrap(), vec![1, 2, 3]);
+        assert_eq!(current.revision().unwrap(), Some(tx_log::Revision::from(7)));
+
+        /* An unversioned file is read as the legacy type. */
+        let legacy = temp_snapshot();
+        fs::write(&legacy.path, bincode::serialize(&7u32).unwrap()).unwrap();
+        assert_eq!(legacy.get_or_migrate::<u64, u32>().unwrap(), 7);
+
+        let future = temp_snapshot();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&future.path, bytes).unwrap();
+        assert_eq!(future.get::<u64>().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        for file in [current, legacy, future] {
+            fs::remove_file(file.path).unwrap();
+        }
+    }
+}
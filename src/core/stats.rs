@@ -0,0 +1,31 @@
+use std::sync::atomic;
+
+/* Counters for INFO stats, since the server started. */
+#[derive(Default)]
+pub struct Statistics {
+    expired_keys:           atomic::AtomicU64,
+    expiry_cycle_cut_short: atomic::AtomicU64,
+}
+
+impl Statistics {
+    pub fn count_expired(&self, keys: usize) {
+        self.expired_keys.fetch_add(keys as u64, atomic::Ordering::Relaxed);
+    }
+
+    /* The background expiry cycle ran out of time with keys still due. */
+    pub fn count_expiry_cycle_cut_short(&self) {
+        self.expiry_cycle_cut_short.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn info(&self) -> String {
+        format!(
+            "# Stats\r\nexpired_keys:{}\r\nexpired_time_cap_reached_count:{}\r\n",
+            self.expired_keys(),
+            self.expiry_cycle_cut_short.load(atomic::Ordering::Relaxed),
+        )
+    }
+}
@@ -1,10 +1,13 @@
 use std::fs;
 use std::io;
-use std::io::{BufRead, Write};
+use std::io::{Read, Write};
 use std::path;
+use std::ops;
 use std::ops::{Deref, DerefMut};
 use std::time;
 use std::str;
+use std::fmt;
+use std::iter;
 use serde::{Deserialize, Serialize};
 use base64::{
     Engine as _, 
@@ -13,20 +16,65 @@ use base64::{
 
 use crate::core::resp;
 
-#[derive(Clone, Default, Serialize, Deserialize, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, PartialOrd)]
 pub struct Revision(usize);
 
 impl Revision {
     pub fn succeeding(&self) -> Self {
         Self(self.0 + 1)
     }
+
+    pub fn preceding(&self) -> Option<Self> {
+        self.0.checked_sub(1).map(Self)
+    }
+
+    pub fn changes_since(&self, earlier: &Self) -> usize {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl From<usize> for Revision {
+    fn from(revision: usize) -> Self {
+        Self(revision)
+    }
+}
+
+impl From<Revision> for usize {
+    fn from(revision: Revision) -> Self {
+        revision.0
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/* The log starts with what it is and in which format. One without this
+   is from before, with an entry per line: base64, and the message in it
+   as RESP. Now each entry is its length and CRC-32, a CRC-32 of those
+   two, so that a damaged length is not taken for a torn write, then the
+   entry. */
+const MAGIC: &[u8; 8] = b"PELILOG\0";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_LENGTH: u64 = 12;
+const RECORD_HEADER_LENGTH: u64 = 12;
+
+/* Variable-length integers: entries are small, and mostly lengths. */
+fn record_codec() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+}
+
+fn file_header() -> Vec<u8> {
+    [&MAGIC[..], &FORMAT_VERSION.to_le_bytes()].concat()
 }
 
 #[derive(Serialize, Deserialize)]
 struct LogEntry {
     at:       time::SystemTime,
     revision: Revision,
-    content:  String,
+    message:  resp::Message,
 }
 
 impl LogEntry {
@@ -34,14 +82,34 @@ impl LogEntry {
         Self {
             at, 
             revision: revision.clone(),
-            content: message.clone().into(),
+            message: message.clone(),
         }
     }
+
+    fn to_record(&self) -> io::Result<Vec<u8>> {
+        let payload = bincode::Options::serialize(record_codec(), self).map_err(|e|
+            io::Error::other(e.to_string())
+        )?;
+        let length = u32::try_from(payload.len()).map_err(|_|
+            io::Error::other("log entry too large")
+        )?;
+        let header = [length.to_le_bytes(), crc32fast::hash(&payload).to_le_bytes()].concat();
+        Ok([&header[..], &crc32fast::hash(&header).to_le_bytes(), &payload].concat())
+    }
+}
+
+/* An entry as written before the current format. */
+#[derive(Serialize, Deserialize)]
+struct LegacyEntry {
+    at:       time::SystemTime,
+    revision: Revision,
+    content:  String,
 }
 
 pub trait WriteTransactionSink {
     fn record_evidence(
         &mut self, 
+        at:       time::SystemTime,
         revision: &Revision, 
         message:  &resp::Message
     ) -> io::Result<()>;
@@ -50,15 +118,21 @@ pub trait WriteTransactionSink {
 impl <A> WriteTransactionSink for LoggedTransactions<A> {
     fn record_evidence(
         &mut self,
+        at:       time::SystemTime,
         revision: &Revision,
         message:  &resp::Message
     ) -> io::Result<()> {
         if !self.replaying {
             println!("record_write: appending to transaction log");
-            let entry = LogEntry::new(time::SystemTime::now(), revision, message);
-            self.log.append(entry)
+            let record = LogEntry::new(at, revision, message).to_record()?;
+            self.log.append_record(&record)?;
+            if let Some(rewritten) = &mut self.rewrite_buffer {
+                rewritten.push(record);
+            }
+            Ok(())
         } else {
-            Ok(println!("record_write: ignoring"))
+            println!("record_write: ignoring");
+            Ok(())
         }
     }
 }
@@ -67,25 +141,177 @@ pub struct LoggedTransactions<Wrapped> {
     log:        LogFile,
     underlying: Wrapped,
     replaying:  bool,
+    /* What was appended since a rewrite or truncation began, for the log
+       that replaces this one. */
+    rewrite_buffer: Option<Vec<Vec<u8>>>,
+    truncating:     bool,
+    rewrites:       u64,
+    truncations:    u64,
+    /* How often the log was replaced, by either. */
+    generation:     u64,
 }
 
 impl <Wrapped> LoggedTransactions<Wrapped> {
     pub fn new(underlying: Wrapped) -> Result<Self, io::Error> {
-        let default_path = path::Path::new("data/transactions.log");
+        Self::in_directory(path::Path::new("data"), underlying)
+    }
+
+    /* Where snapshots go too. */
+    pub fn in_directory(directory: &path::Path, underlying: Wrapped) -> Result<Self, io::Error> {
+        Self::open(&directory.join(LOG_FILE), underlying)
+    }
+
+    pub fn directory(&self) -> &path::Path {
+        self.log.path.parent().unwrap_or(path::Path::new("."))
+    }
 
+    pub fn open(path: &path::Path, underlying: Wrapped) -> Result<Self, io::Error> {
         Ok(Self {
-            log: LogFile::new(default_path)?,
+            log: LogFile::new(path)?,
             underlying,
             replaying: true,
+            rewrite_buffer: None,
+            truncating: false,
+            rewrites: 0,
+            truncations: 0,
+            generation: 0,
         })
     }
 
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite_buffer.is_some() && !self.truncating
+    }
+
+    /* Since the server started. */
+    pub fn rewrites(&self) -> u64 {
+        self.rewrites
+    }
+
+    pub fn truncations(&self) -> u64 {
+        self.truncations
+    }
+
+    /* Changes whenever the log file is replaced. */
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /* For appendfsync always: on disk before the write is answered. */
+    pub fn sync_log(&mut self, at: time::SystemTime) -> io::Result<()> {
+        if self.replaying { Ok(()) } else { self.log.sync(at) }
+    }
+
+    /* For appendfsync everysec, which should not hold up writes while the
+       disk catches up: sync through the handle once the lock is released,
+       then finish under it again. None if there is nothing to sync. */
+    pub fn begin_background_sync(&self) -> io::Result<Option<BackgroundSync>> {
+        if self.log.pending_fsync() == 0 {
+            return Ok(None)
+        }
+        Ok(Some(BackgroundSync { file: self.log.file.try_clone()?, size: self.log.size, generation: self.generation }))
+    }
+
+    pub fn finish_background_sync(&mut self, sync: BackgroundSync, at: time::SystemTime) {
+        /* Otherwise the file synced is not the log any more. */
+        if sync.generation == self.generation {
+            self.log.synced_size = self.log.synced_size.max(sync.size);
+            self.log.last_fsync = Some(at);
+        }
+    }
+
+    /* None if a rewrite is already under way. Until it finishes, whatever
+       is appended to the log is kept for the new one as well. */
+    pub fn begin_rewrite(&mut self) -> io::Result<Option<LogRewrite>> {
+        if self.rewrite_buffer.is_some() {
+            return Ok(None)
+        }
+        let rewrite = LogRewrite::new(&self.log.path)?;
+        self.rewrite_buffer = Some(vec![]);
+        Ok(Some(rewrite))
+    }
+
+    /* The rewritten log takes the place of the current one in one rename;
+       a crash either side of it leaves one or the other, whole. */
+    pub fn finish_rewrite(&mut self, mut rewrite: LogRewrite) -> io::Result<()> {
+        for record in self.rewrite_buffer.take().unwrap_or_default() {
+            rewrite.append_record(&record)?;
+        }
+        let last_fsync = self.log.last_fsync;
+        self.log = rewrite.install(&self.log.path)?;
+        self.log.last_fsync = last_fsync;
+        if std::mem::take(&mut self.truncating) {
+            self.truncations += 1;
+        } else {
+            self.rewrites += 1;
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    pub fn abandon_rewrite(&mut self, rewrite: LogRewrite) {
+        self.rewrite_buffer = None;
+        self.truncating = false;
+        let _ = fs::remove_file(&rewrite.path);
+    }
+
     pub fn transaction_log(&self) -> &LogFile {
         &self.log
     }
 
-    pub fn finalize_replay(&mut self) {
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    /* A log in the format from before is written out anew in the current
+       one, now that it has been read through. */
+    pub fn finalize_replay(&mut self) -> io::Result<()> {
         self.replaying = false;
+        if self.log.legacy {
+            self.migrate_log()?;
+        }
+        Ok(())
+    }
+
+    fn migrate_log(&mut self) -> io::Result<()> {
+        let mut rewrite = LogRewrite::new(&self.log.path)?;
+        let copied = self.log.replay(&Revision::default())?.entries().try_for_each(|(_, entry)|
+            entry.and_then(|entry| rewrite.append(entry.at, &entry.revision, &entry.message))
+        );
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&rewrite.path);
+            return Err(e)
+        }
+        let last_fsync = self.log.last_fsync;
+        self.log = rewrite.install(&self.log.path)?;
+        self.log.last_fsync = last_fsync;
+        println!("finalize_replay: Migrated the transaction log to format version {FORMAT_VERSION}.");
+        Ok(())
+    }
+
+    /* Everything before the offset goes, once snapshots cover it. As with
+       a rewrite, the rest is copied without holding anything, then goes in
+       with finish_rewrite. None if the log was replaced since the offset
+       was found, or is being. */
+    pub fn begin_truncation(&mut self, offset: u64, generation: u64) -> io::Result<Option<LogRewrite>> {
+        if self.rewrite_buffer.is_some() || generation != self.generation || offset <= self.log.start() {
+            return Ok(None)
+        }
+        let mut file = fs::File::open(&self.log.path)?;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(offset))?;
+        let mut rewrite = LogRewrite::new(&self.log.path)?;
+        rewrite.kept = Some(io::Read::take(file, self.log.size.saturating_sub(offset)));
+        self.rewrite_buffer = Some(vec![]);
+        self.truncating = true;
+        Ok(Some(rewrite))
+    }
+
+    /* Only while replaying, before anything new is written. */
+    pub fn discard_log_from(&mut self, offset: u64) -> io::Result<u64> {
+        if self.replaying {
+            self.log.discard_from(offset)
+        } else {
+            Err(io::Error::other("the log is only cut short during replay"))
+        }
     }
 }
 
@@ -113,38 +339,381 @@ impl ReplayView {
         Self { file, since }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = io::Result<resp::Message>> + '_ {
-        let reader = io::BufReader::new(&self.file);
-        reader.lines()
-              .map(|record| LogEntry::try_from(record?))
-              .skip_while(|entry| entry.as_ref().map_or(false, |e| e.revision < self.since))
-              .map(|record| record?.content.parse())
+    /* Any log, not only the one being written to. */
+    pub fn open(path: &path::Path, since: &Revision) -> io::Result<Self> {
+        Ok(Self::new(fs::File::open(path)?, since.clone()))
+    }
+
+    /* A rewritten log starts with every key as it was at one revision,
+       so that revision comes up a good many times over. */
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<(Revision, resp::Message)>> + '_ {
+        self.entries()
+            .map(|(_, entry)| entry)
+            .skip_while(|entry| entry.as_ref().is_ok_and(|e| e.revision < self.since))
+            .map(|entry| entry.map(|e| (e.revision, e.message)))
+    }
+
+    /* Every entry from the start, those before the revision too, and the
+       offset each starts at. A last line with no end to it was cut short
+       as it was written, and reads as UnexpectedEof. */
+    pub fn entries(&self) -> impl Iterator<Item = (u64, io::Result<Replayed>)> + '_ {
+        self.records().map(|(range, entry)| (range.start, entry))
+    }
+
+    /* Where the first entry from the revision on starts; what comes before
+       can go once a snapshot covers it. None if an entry on the way cannot
+       be read. */
+    pub fn offset_of(&self, revision: &Revision) -> Option<u64> {
+        let mut end = 0;
+        for (range, entry) in self.records() {
+            match entry {
+                Ok(entry) if entry.revision < *revision =>
+                    end = range.end,
+                Ok(_) =>
+                    return Some(range.start),
+                /* Being written even now. */
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof =>
+                    return Some(range.start),
+                Err(_) =>
+                    return None,
+            }
+        }
+        Some(end)
+    }
+
+    /* In the line format from before. */
+    pub fn is_legacy(&self) -> io::Result<bool> {
+        read_format(&mut io::BufReader::new(&self.file))
+    }
+
+    pub fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /* From the top, however often the view is gone through, in whichever
+       format the log is. */
+    fn records(&self) -> Box<dyn Iterator<Item = (ops::Range<u64>, io::Result<Replayed>)> + '_> {
+        let mut reader = io::BufReader::new(&self.file);
+        match read_format(&mut reader) {
+            Ok(true)  => Box::new(Self::lines(reader)),
+            Ok(false) => Box::new(Self::binary_records(reader)),
+            Err(e)    => Box::new(iter::once((0..0, Err(e)))),
+        }
+    }
+
+    fn binary_records<R: io::Read>(mut reader: R) -> impl Iterator<Item = (ops::Range<u64>, io::Result<Replayed>)> {
+        let mut offset = HEADER_LENGTH;
+        iter::from_fn(move || {
+            let at = offset;
+            let (length, entry) = Replayed::read_record(&mut reader)?;
+            offset += length;
+            Some((at..offset, entry))
+        })
+    }
+
+    fn lines<R: io::BufRead>(mut reader: R) -> impl Iterator<Item = (ops::Range<u64>, io::Result<Replayed>)> {
+        let mut offset = 0;
+        iter::from_fn(move || {
+            let mut line = vec![];
+            let at = offset;
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0)      => None,
+                Ok(length) => {
+                    offset += length as u64;
+                    if line.ends_with(b"\n") {
+                        Some((at..offset, Replayed::decode_line(&line)))
+                    } else {
+                        Some((at..offset, Err(torn_write())))
+                    }
+                },
+                Err(e)     => Some((at..at, Err(e))),
+            }
+        })
+    }
+}
+
+/* True for a log in the line format from before, positioned at its first
+   entry. A header cut short as the log was created counts as one whole:
+   the log is empty either way. */
+fn read_format<R: io::BufRead + io::Seek>(reader: &mut R) -> io::Result<bool> {
+    reader.rewind()?;
+    let mut header = vec![];
+    reader.by_ref().take(HEADER_LENGTH).read_to_end(&mut header)?;
+    if header.len() < HEADER_LENGTH as usize && file_header().starts_with(&header) {
+        return Ok(false)
+    }
+    if !header.starts_with(MAGIC) {
+        reader.rewind()?;
+        return Ok(true)
+    }
+    match u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap_or_default()) {
+        FORMAT_VERSION => Ok(false),
+        otherwise      => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown transaction log format version {otherwise}"),
+        )),
+    }
+}
+
+fn torn_write() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "torn write")
+}
+
+/* An entry read back from the log. */
+pub struct Replayed {
+    pub at:       time::SystemTime,
+    pub revision: Revision,
+    pub message:  resp::Message,
+}
+
+impl Replayed {
+    /* How many bytes it took up, and what was in them; None at the end of
+       the log. A record that ends early with the log was cut short as it
+       was written, and reads as UnexpectedEof; that takes a header that
+       checks out, or one the log ends in the middle of, with nothing after
+       it to lose. A header that does not check out cannot say where the
+       record ends, and reads as InvalidData. */
+    fn read_record<R: io::Read>(reader: &mut R) -> Option<(u64, io::Result<Self>)> {
+        let mut header = vec![];
+        if let Err(e) = reader.by_ref().take(RECORD_HEADER_LENGTH).read_to_end(&mut header) {
+            return Some((0, Err(e)))
+        }
+        match header.len() as u64 {
+            0                                     => return None,
+            short if short < RECORD_HEADER_LENGTH => return Some((short, Err(torn_write()))),
+            _whole                                => (),
+        }
+        if crc32fast::hash(&header[..8]).to_le_bytes()[..] != header[8..] {
+            return Some((RECORD_HEADER_LENGTH, Err(io::Error::new(io::ErrorKind::InvalidData, "damaged record header"))))
+        }
+        let length = u32::from_le_bytes(header[..4].try_into().unwrap_or_default());
+        let checksum = u32::from_le_bytes(header[4..8].try_into().unwrap_or_default());
+        /* Read as it comes, not allocated up front: a damaged length could
+           otherwise ask for any amount of memory. */
+        let mut payload = vec![];
+        if let Err(e) = reader.by_ref().take(length.into()).read_to_end(&mut payload) {
+            return Some((RECORD_HEADER_LENGTH, Err(e)))
+        }
+        let taken = RECORD_HEADER_LENGTH + payload.len() as u64;
+        if payload.len() < length as usize {
+            Some((taken, Err(torn_write())))
+        } else if crc32fast::hash(&payload) != checksum {
+            Some((taken, Err(io::Error::new(io::ErrorKind::InvalidData, "checksum mismatch"))))
+        } else {
+            Some((taken, Self::decode(&payload)))
+        }
+    }
+
+    fn decode(payload: &[u8]) -> io::Result<Self> {
+        let entry: LogEntry = bincode::Options::deserialize(record_codec(), payload).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        )?;
+        Ok(Self { at: entry.at, revision: entry.revision, message: entry.message })
+    }
+
+    fn decode_line(line: &[u8]) -> io::Result<Self> {
+        let record = str::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let entry = LegacyEntry::try_from(record.trim_end().to_string())?;
+        Ok(Self { at: entry.at, revision: entry.revision, message: entry.content.parse()? })
     }
+
+    /* Nothing before it matters. */
+    fn starts_afresh(&self) -> bool {
+        self.message.try_as_bulk_array().as_deref().is_some_and(|words|
+            words.first().is_some_and(|command| {
+                command.eq_ignore_ascii_case("FLUSHALL") || command.eq_ignore_ascii_case("FLUSHDB")
+            })
+        )
+    }
+}
+
+pub const LOG_FILE: &str = "transactions.log";
+
+/* How far to go when restoring an earlier state: up to a revision, or up
+   to the first entry written after a moment. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum PointInTime {
+    Revision(Revision),
+    Time(time::SystemTime),
+}
+
+impl PointInTime {
+    /* `--until-revision N`, or `--until-time T` in Unix seconds. */
+    pub fn from_flag(flag: &str, value: &str) -> Option<Self> {
+        match flag {
+            "--until-revision" =>
+                value.parse().ok().map(|revision| Self::Revision(Revision(revision))),
+            "--until-time" =>
+                value.parse::<f64>().ok()
+                    .and_then(|seconds| time::Duration::try_from_secs_f64(seconds).ok())
+                    .map(|since| Self::Time(time::UNIX_EPOCH + since)),
+            _otherwise =>
+                None,
+        }
+    }
+
+    /* The revision to stop short of in the log given, if any. Past its
+       last entry there is nothing to stop for. */
+    pub fn resolve(&self, log: &path::Path) -> io::Result<Option<Revision>> {
+        match self {
+            PointInTime::Revision(revision) =>
+                Ok(Some(revision.clone())),
+            PointInTime::Time(moment) => {
+                let replay = ReplayView::open(log, &Revision::default())?;
+                let later = replay.entries()
+                    .map_while(|(_, entry)| entry.ok())
+                    .find(|entry| entry.at > *moment);
+                Ok(later.map(|entry| entry.revision))
+            },
+        }
+    }
+}
+
+/* Whether a startup stops at the first inconsistent entry, or replays up
+   to it and leaves the rest out of the log. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestoreMode {
+    Strict, Recover,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Inconsistency {
+    Torn,
+    Unreadable(String),
+    Gap { expected: Revision, found: Revision },
+    Repeated { expected: Revision, found: Revision },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::Torn =>
+                write!(f, "the last entry was cut short as it was written"),
+            Inconsistency::Unreadable(error) =>
+                write!(f, "unreadable entry ({error})"),
+            Inconsistency::Gap { expected, found } =>
+                write!(f, "revision {found} where {expected} was expected, revisions are missing"),
+            Inconsistency::Repeated { expected, found } =>
+                write!(f, "revision {found} where {expected} was expected, revisions repeat"),
+        }
+    }
+}
+
+/* An entry written at a time before the one ahead of it. Each run takes
+   its time from the wall clock as it starts, so this is what a clock set
+   back between runs looks like: worth a warning, never a reason to stop. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeReversal {
+    pub revision: Revision,
+    pub previous: time::SystemTime,
+    pub found:    time::SystemTime,
 }
 
-impl TryFrom<String> for LogEntry {
+/* Revisions go up one entry at a time. The exceptions: a rewritten log
+   starts with any number of entries at one revision, the first of them
+   FLUSHALL, and since nothing before a FLUSHALL matters, revisions may skip
+   ahead to one. The first entry replayed must take up where the snapshot
+   left off, in the same way. Time should go up too, but is only noted. */
+pub struct Continuity {
+    since:       Option<Revision>,
+    previous:    Option<(time::SystemTime, Revision)>,
+    leading_run: bool,
+    replaying:   bool,
+    reversals:   Option<(TimeReversal, usize)>,
+}
+
+impl Continuity {
+    pub fn new(since: &Revision) -> Self {
+        Self { since: Some(since.clone()), ..Self::whole_log() }
+    }
+
+    /* For a log on its own, which starts wherever its first entry does. */
+    pub fn whole_log() -> Self {
+        Self { since: None, previous: None, leading_run: false, replaying: false, reversals: None }
+    }
+
+    /* An entry that cannot be read is as good as none. */
+    pub fn check_read(&mut self, entry: io::Result<Replayed>) -> Result<(bool, Replayed), Inconsistency> {
+        match entry {
+            Ok(entry) =>
+                self.check(&entry).map(|replay| (replay, entry)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof =>
+                Err(Inconsistency::Torn),
+            Err(e) =>
+                Err(Inconsistency::Unreadable(e.to_string())),
+        }
+    }
+
+    /* Whether to replay the entry, which the snapshot may cover already. */
+    pub fn check(&mut self, entry: &Replayed) -> Result<bool, Inconsistency> {
+        let afresh = entry.starts_afresh();
+        if let Some((_, revision)) = &self.previous {
+            let expected = revision.succeeding();
+            let repeat = self.leading_run && entry.revision == *revision;
+            if !repeat && entry.revision != expected && !(afresh && entry.revision > expected) {
+                return Err(if entry.revision < expected {
+                    Inconsistency::Repeated { expected, found: entry.revision.clone() }
+                } else {
+                    Inconsistency::Gap { expected, found: entry.revision.clone() }
+                })
+            }
+            self.leading_run &= repeat;
+        } else {
+            self.leading_run = afresh;
+        }
+
+        let since = self.since.get_or_insert_with(|| entry.revision.clone());
+        let replay = entry.revision >= *since;
+        if replay && !self.replaying && entry.revision != *since && !afresh {
+            return Err(Inconsistency::Gap { expected: since.clone(), found: entry.revision.clone() })
+        }
+        self.replaying |= replay;
+        if let Some((at, _)) = self.previous.as_ref().filter(|(at, _)| entry.at < *at) {
+            let reversal = TimeReversal { revision: entry.revision.clone(), previous: *at, found: entry.at };
+            self.reversals.get_or_insert((reversal, 0)).1 += 1;
+        }
+        self.previous = Some((entry.at, entry.revision.clone()));
+        Ok(replay)
+    }
+
+    /* The first time the log went back, and how many times it did. */
+    pub fn time_reversals(&self) -> Option<(TimeReversal, usize)> {
+        self.reversals.clone()
+    }
+
+    /* The last revision logged, if the log ends short of the snapshot:
+       no harm now, but the log alone no longer accounts for everything.
+       An empty log is no cause for concern; it may have started afresh
+       from the snapshot. */
+    pub fn ends_before_snapshot(&self) -> Option<Revision> {
+        let since = self.since.as_ref()?;
+        let (_, revision) = self.previous.as_ref()?;
+        (revision.succeeding() < *since).then(|| revision.clone())
+    }
+}
+
+impl TryFrom<String> for LegacyEntry {
     type Error = io::Error;
 
     /* Error handling is really bad at this point. */
 
     fn try_from(record: String) -> Result<Self, Self::Error> {
         let bytes = base64_codec.decode(record).map_err(|e|
-            io::Error::new(io::ErrorKind::Other, e.to_string())
+            io::Error::other(e.to_string())
         )?;
         bincode::deserialize(&bytes).map_err(|e|
-            io::Error::new(io::ErrorKind::Other, e.to_string())
+            io::Error::other(e.to_string())
         )
     }
 }
 
-impl TryFrom<LogEntry> for String {
+#[cfg(test)]
+impl TryFrom<LegacyEntry> for String {
     type Error = io::Error;
 
-    /* Error handling is really bad at this point. */
-
-    fn try_from(entry: LogEntry) -> Result<Self, Self::Error> {
+    fn try_from(entry: LegacyEntry) -> Result<Self, Self::Error> {
         let data = bincode::serialize(&entry).map_err(|e|
-            io::Error::new(io::ErrorKind::Other, e.to_string())
+            io::Error::other(e.to_string())
         )?;
         Ok(base64_codec.encode(data))
     }
@@ -153,29 +722,173 @@ impl TryFrom<LogEntry> for String {
 pub struct LogFile {
     path: path::PathBuf,
     file: fs::File,
+    size: u64,
+    /* In the line format, until replayed and written out anew. */
+    legacy: bool,
+    /* The size after the last rewrite, or at startup: what growth is
+       measured against. */
+    base_size: u64,
+    /* What was there at startup counts as synced. */
+    synced_size: u64,
+    last_fsync:  Option<time::SystemTime>,
+}
+
+pub struct BackgroundSync {
+    file:       fs::File,
+    size:       u64,
+    generation: u64,
+}
+
+impl BackgroundSync {
+    pub fn run(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
 }
 
 impl LogFile {
     fn new(at: &path::Path) -> io::Result<Self> {
-        Ok(Self {
-            path: at.into(),
-            file: fs::File::options().append(true).create(true).open(at)?,
-        })
+        if let Some(parent) = at.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::File::options().read(true).append(true).create(true).open(at)?;
+        let legacy = read_format(&mut io::BufReader::new(&file))?;
+        if !legacy && file.metadata()?.len() < HEADER_LENGTH {
+            file.set_len(0)?;
+            file.write_all(&file_header())?;
+        }
+        let size = file.metadata()?.len();
+        Ok(Self { path: at.into(), file, size, legacy, base_size: size, synced_size: size, last_fsync: None })
     }
 
+    #[cfg(test)]
     fn append(&mut self, entry: LogEntry) -> io::Result<()> {
-        let record: String = entry.try_into()?;
-        self.file.write_all(format!("{record}\r\n").as_bytes())
-        /* if now > fs_sync deadline { file.fs_sync() } */
+        let record = entry.to_record()?;
+        self.append_record(&record)
+    }
+
+    fn append_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.legacy {
+            return Err(io::Error::other("the transaction log is in the old format until replayed"))
+        }
+        self.file.write_all(record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /* Where the first entry is. */
+    fn start(&self) -> u64 {
+        if self.legacy { 0 } else { HEADER_LENGTH }
+    }
+
+    /* No entries, only the header. */
+    pub fn is_empty(&self) -> bool {
+        self.size <= self.start()
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
     }
 
-    fn sync(&self) -> io::Result<()> {
-        self.file.sync_all()
+    pub fn base_size(&self) -> u64 {
+        self.base_size
+    }
+
+    fn sync(&mut self, at: time::SystemTime) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.synced_size = self.size;
+        self.last_fsync = Some(at);
+        Ok(())
+    }
+
+    /* Bytes written that may not be on disk yet. */
+    pub fn pending_fsync(&self) -> u64 {
+        self.size - self.synced_size
+    }
+
+    pub fn last_fsync(&self) -> Option<time::SystemTime> {
+        self.last_fsync
     }
 
     pub fn replay(&self, since: &Revision) -> io::Result<ReplayView> {
         Ok(ReplayView::new(fs::File::open(&self.path)?, since.clone()))
     }
+
+    pub fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    /* Everything from the offset on moves to a `.discarded` file next to
+       the log, for whoever wants to look; the number of bytes moved. */
+    fn discard_from(&mut self, offset: u64) -> io::Result<u64> {
+        let mut tail = vec![];
+        let mut file = fs::File::open(&self.path)?;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(offset))?;
+        io::Read::read_to_end(&mut file, &mut tail)?;
+
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".discarded");
+        let mut discarded = fs::File::options().append(true).create(true).open(path::PathBuf::from(name))?;
+        discarded.write_all(&tail)?;
+        discarded.sync_all()?;
+
+        self.file.set_len(offset)?;
+        self.file.sync_all()?;
+        self.size = offset;
+        self.base_size = self.base_size.min(offset);
+        self.synced_size = offset;
+        Ok(tail.len() as u64)
+    }
+}
+
+/* A log written from scratch next to the current one, to replace it. */
+pub struct LogRewrite {
+    path:   path::PathBuf,
+    writer: io::BufWriter<fs::File>,
+    /* For a truncation, what stays of the current log as it was then. */
+    kept:   Option<io::Take<fs::File>>,
+}
+
+impl LogRewrite {
+    fn new(replacing: &path::Path) -> io::Result<Self> {
+        let mut name = replacing.as_os_str().to_owned();
+        name.push(".rewrite");
+        let path = path::PathBuf::from(name);
+        let file = fs::File::options().write(true).create(true).truncate(true).open(&path)?;
+        let mut writer = io::BufWriter::new(file);
+        writer.write_all(&file_header())?;
+        Ok(Self { path, writer, kept: None })
+    }
+
+    /* What a truncation keeps, copied over. */
+    pub fn copy_kept(&mut self) -> io::Result<()> {
+        if let Some(kept) = &mut self.kept {
+            io::copy(kept, &mut self.writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn append(&mut self, at: time::SystemTime, revision: &Revision, message: &resp::Message) -> io::Result<()> {
+        let record = LogEntry::new(at, revision, message).to_record()?;
+        self.append_record(&record)
+    }
+
+    fn append_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.writer.write_all(record)
+    }
+
+    fn install(self, at: &path::Path) -> io::Result<LogFile> {
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&self.path, at)?;
+        if let Some(parent) = at.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::File::open(parent)?.sync_all()?;
+        }
+        LogFile::new(at)
+    }
 }
 
 #[cfg(test)]
@@ -189,7 +902,7 @@ mod tests {
         LogEntry { 
             at: time::SystemTime::now(), 
             revision: Revision::default(), 
-            content: m.into(),
+            message: m,
         }
     }
 
@@ -226,7 +939,7 @@ mod tests {
 
         let log = LogFile::new(&path).unwrap();
         assert_eq!(
-            log.replay(&rev.succeeding()).unwrap().iter().collect::<Result<Vec<resp::Message>, io::Error>>().unwrap(),
+            log.replay(&rev.succeeding()).unwrap().iter().map(|entry| entry.map(|(_, message)| message)).collect::<Result<Vec<resp::Message>, io::Error>>().unwrap(),
             vec![mk_string("OK2"), mk_string("OK3")]
         )
     }
@@ -241,7 +954,7 @@ mod tests {
 
         let log = LogFile::new(&path).unwrap();
         assert_eq!(
-            log.replay(&Revision::default()).unwrap().iter().collect::<Result<Vec<resp::Message>, io::Error>>().unwrap(), 
+            log.replay(&Revision::default()).unwrap().iter().map(|entry| entry.map(|(_, message)| message)).collect::<Result<Vec<resp::Message>, io::Error>>().unwrap(), 
             vec![
                 resp::Message::BulkString("Hi, mom".to_string()),
                 resp::Message::Integer(427)
@@ -262,12 +975,281 @@ mod tests {
         for m in ms.iter() {
             log.append(log_entry(m.clone())).unwrap();
         }
-        log.sync().unwrap();
+        log.sync(time::SystemTime::now()).unwrap();
 
         let log = LogFile::new(&path).unwrap();
         assert_eq!(
-            log.replay(&Revision::default()).unwrap().iter().collect::<Result<Vec<resp::Message>, io::Error>>().unwrap(),
+            log.replay(&Revision::default()).unwrap().iter().map(|entry| entry.map(|(_, message)| message)).collect::<Result<Vec<resp::Message>, io::Error>>().unwrap(),
             ms
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rewrite_takes_over() {
+        fn replayed(path: &path::Path) -> Vec<(Revision, resp::Message)> {
+            let log = LogFile::new(path).unwrap();
+            log.replay(&Revision::default()).unwrap().iter().collect::<io::Result<Vec<_>>>().unwrap()
+        }
+
+        let now = time::SystemTime::now();
+        let message = |text: &str| resp::Message::BulkString(text.to_string());
+        let path = temp_file();
+        let mut logged = LoggedTransactions::open(&path, ()).unwrap();
+        logged.finalize_replay().unwrap();
+
+        let revision = Revision::default();
+        logged.record_evidence(now, &revision, &message("before")).unwrap();
+        let mut rewrite = logged.begin_rewrite().unwrap().unwrap();
+        assert!(logged.begin_rewrite().unwrap().is_none());
+        logged.record_evidence(now, &revision.succeeding(), &message("during")).unwrap();
+        rewrite.append(now, &revision, &message("base")).unwrap();
+        assert_eq!(replayed(&path).len(), 2);
+
+        logged.finish_rewrite(rewrite).unwrap();
+        assert!(!logged.is_rewriting());
+        logged.record_evidence(now, &revision.succeeding().succeeding(), &message("after")).unwrap();
+        assert_eq!(
+            replayed(&path),
+            vec![
+                (revision.clone(), message("base")),
+                (revision.succeeding(), message("during")),
+                (revision.succeeding().succeeding(), message("after")),
+            ]
+        );
+        assert_eq!(logged.transaction_log().size(), fs::metadata(&path).unwrap().len());
+        assert!(logged.transaction_log().base_size() < logged.transaction_log().size());
+
+        let rewrite = logged.begin_rewrite().unwrap().unwrap();
+        let temporary = rewrite.path.clone();
+        logged.abandon_rewrite(rewrite);
+        assert!(!temporary.exists());
+        assert_eq!(replayed(&path).len(), 3);
+    }
+
+    #[test]
+    fn fsync_bookkeeping() {
+        let now = time::SystemTime::now();
+        let message = resp::Message::BulkString("entry".to_string());
+        let mut logged = LoggedTransactions::open(&temp_file(), ()).unwrap();
+        logged.finalize_replay().unwrap();
+        assert!(logged.begin_background_sync().unwrap().is_none());
+
+        logged.record_evidence(now, &Revision::default(), &message).unwrap();
+        let pending = logged.transaction_log().pending_fsync();
+        assert_eq!(pending, logged.transaction_log().size() - HEADER_LENGTH);
+        let sync = logged.begin_background_sync().unwrap().unwrap();
+        logged.record_evidence(now, &Revision::default(), &message).unwrap();
+        sync.run().unwrap();
+        logged.finish_background_sync(sync, now);
+        assert_eq!(logged.transaction_log().pending_fsync(), pending);
+        assert_eq!(logged.transaction_log().last_fsync(), Some(now));
+
+        /* Whatever it synced was replaced meanwhile. */
+        let sync = logged.begin_background_sync().unwrap().unwrap();
+        let rewrite = logged.begin_rewrite().unwrap().unwrap();
+        logged.finish_rewrite(rewrite).unwrap();
+        sync.run().unwrap();
+        logged.finish_background_sync(sync, now + time::Duration::from_secs(1));
+        assert_eq!(logged.transaction_log().last_fsync(), Some(now));
+
+        logged.sync_log(now).unwrap();
+        assert_eq!(logged.transaction_log().pending_fsync(), 0);
+    }
+
+    #[test]
+    fn continuity() {
+        let now = time::SystemTime::now();
+        let entry = |revision: usize, seconds: u64, command: &str| Replayed {
+            at:       now + time::Duration::from_secs(seconds),
+            revision: Revision(revision),
+            message:  resp::Message::make_bulk_array(&[command.to_string()]),
+        };
+        let check = |since: usize, entries: &[Replayed]| {
+            let mut continuity = Continuity::new(&Revision(since));
+            entries.iter().map(|entry| continuity.check(entry)).collect::<Result<Vec<_>, _>>()
+        };
+
+        assert_eq!(check(1, &[entry(0, 0, "SET"), entry(1, 0, "SET"), entry(2, 1, "DEL")]), Ok(vec![false, true, true]));
+        /* As rewritten, at the revision before the snapshot's, and after. */
+        let rewritten = [entry(4, 0, "FLUSHALL"), entry(4, 0, "SET"), entry(4, 0, "RPUSH"), entry(5, 1, "SET")];
+        assert_eq!(check(4, &rewritten), Ok(vec![true, true, true, true]));
+        assert_eq!(check(5, &rewritten), Ok(vec![false, false, false, true]));
+        assert_eq!(check(2, &[entry(0, 0, "SET"), entry(7, 0, "FLUSHALL"), entry(8, 0, "SET")]), Ok(vec![false, true, true]));
+
+        assert_eq!(
+            check(0, &[entry(0, 0, "SET"), entry(2, 0, "SET")]),
+            Err(Inconsistency::Gap { expected: Revision(1), found: Revision(2) })
+        );
+        assert_eq!(
+            check(0, &[entry(0, 0, "SET"), entry(1, 0, "SET"), entry(1, 0, "SET")]),
+            Err(Inconsistency::Repeated { expected: Revision(2), found: Revision(1) })
+        );
+        /* Time going back is noted, and nothing more. */
+        let mut continuity = Continuity::new(&Revision(0));
+        for entry in [entry(0, 5, "SET"), entry(1, 4, "SET"), entry(2, 9, "SET"), entry(3, 1, "SET")] {
+            assert_eq!(continuity.check(&entry), Ok(true));
+        }
+        assert_eq!(continuity.time_reversals(), Some((TimeReversal {
+            revision: Revision(1),
+            previous: now + time::Duration::from_secs(5),
+            found:    now + time::Duration::from_secs(4),
+        }, 2)));
+        assert_eq!(Continuity::new(&Revision(0)).time_reversals(), None);
+        assert_eq!(
+            check(3, &[entry(5, 0, "SET")]),
+            Err(Inconsistency::Gap { expected: Revision(3), found: Revision(5) })
+        );
+
+        let mut continuity = Continuity::new(&Revision(3));
+        continuity.check(&entry(0, 0, "SET")).unwrap();
+        assert_eq!(continuity.ends_before_snapshot(), Some(Revision(0)));
+        assert_eq!(Continuity::new(&Revision(3)).ends_before_snapshot(), None);
+        assert_eq!(Continuity::new(&Revision(0)).ends_before_snapshot(), None);
+    }
+
+    #[test]
+    fn torn_tail() {
+        let path = temp_file();
+        let mut written = LoggedTransactions::open(&path, ()).unwrap();
+        written.finalize_replay().unwrap();
+        written.record_evidence(time::SystemTime::now(), &Revision(0), &resp::Message::BulkString("whole".to_string())).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, [&bytes[..], &bytes[HEADER_LENGTH as usize..bytes.len() - 2]].concat()).unwrap();
+
+        let log = LogFile::new(&path).unwrap();
+        let replay = log.replay(&Revision(0)).unwrap();
+        let mut continuity = Continuity::whole_log();
+        let checked = replay.entries()
+            .map(|(offset, entry)| (offset, continuity.check_read(entry).map(|(replay, _)| replay)))
+            .collect::<Vec<_>>();
+        assert_eq!(checked, vec![(HEADER_LENGTH, Ok(true)), (bytes.len() as u64, Err(Inconsistency::Torn))]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn discards_the_tail() {
+        let now = time::SystemTime::now();
+        let path = temp_file();
+        let mut written = LoggedTransactions::open(&path, ()).unwrap();
+        written.finalize_replay().unwrap();
+        written.record_evidence(now, &Revision(0), &resp::Message::BulkString("kept".to_string())).unwrap();
+        let offset = written.transaction_log().size();
+        written.record_evidence(now, &Revision(1), &resp::Message::BulkString("dropped".to_string())).unwrap();
+        let size = written.transaction_log().size();
+
+        let mut logged = LoggedTransactions::open(&path, ()).unwrap();
+
+        let entries = logged.transaction_log().replay(&Revision(0)).unwrap().entries().map(|(at, _)| at).collect::<Vec<_>>();
+        assert_eq!(entries, vec![HEADER_LENGTH, offset]);
+        assert_eq!(logged.discard_log_from(offset).unwrap(), size - offset);
+        assert_eq!(fs::metadata(&path).unwrap().len(), offset);
+        let mut discarded = path.as_os_str().to_owned();
+        discarded.push(".discarded");
+        assert_eq!(fs::metadata(&discarded).unwrap().len(), size - offset);
+
+        logged.finalize_replay().unwrap();
+        assert!(logged.discard_log_from(0).is_err());
+        fs::remove_file(discarded).unwrap();
+    }
+
+    #[test]
+    fn drops_what_snapshots_cover() {
+        let now = time::SystemTime::now();
+        let path = temp_file();
+        let mut logged = LoggedTransactions::open(&path, ()).unwrap();
+        logged.finalize_replay().unwrap();
+        let mut offsets = vec![];
+        for revision in 0..4 {
+            offsets.push(logged.transaction_log().size());
+            logged.record_evidence(now, &Revision(revision), &resp::Message::BulkString(revision.to_string())).unwrap();
+        }
+        let size = logged.transaction_log().size();
+        let view = logged.transaction_log().replay(&Revision(0)).unwrap();
+        assert_eq!(view.offset_of(&Revision(0)), Some(offsets[0]));
+        assert_eq!(view.offset_of(&Revision(2)), Some(offsets[2]));
+        assert_eq!(view.offset_of(&Revision(9)), Some(size));
+
+        /* Not if the log was replaced in between. */
+        let generation = logged.generation();
+        assert!(logged.begin_truncation(offsets[2], generation + 1).unwrap().is_none());
+        let mut truncation = logged.begin_truncation(offsets[2], generation).unwrap().unwrap();
+        assert!(!logged.is_rewriting() && logged.begin_rewrite().unwrap().is_none());
+        /* What is written meanwhile stays too. */
+        logged.record_evidence(now, &Revision(4), &resp::Message::BulkString("4".to_string())).unwrap();
+        truncation.copy_kept().unwrap();
+        logged.finish_rewrite(truncation).unwrap();
+        assert_eq!((logged.rewrites(), logged.truncations(), logged.generation()), (0, 1, generation + 1));
+        logged.record_evidence(now, &Revision(5), &resp::Message::BulkString("5".to_string())).unwrap();
+
+        let reopened = LoggedTransactions::open(&path, ()).unwrap();
+        let revisions = reopened.transaction_log().replay(&Revision(0)).unwrap().iter()
+            .map(|entry| entry.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(revisions, vec![Revision(2), Revision(3), Revision(4), Revision(5)]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn migrates_line_logs() {
+        let now = time::SystemTime::now();
+        let path = temp_file();
+        let lines = (0..2).map(|revision| {
+            let entry = LegacyEntry { at: now, revision: Revision(revision), content: resp::Message::Integer(revision as i64).into() };
+            String::try_from(entry).unwrap() + "\r\n"
+        });
+        fs::write(&path, lines.collect::<String>()).unwrap();
+
+        let mut logged = LoggedTransactions::open(&path, ()).unwrap();
+        assert!(logged.transaction_log().is_legacy());
+        let replayed = |logged: &LoggedTransactions<()>| logged.transaction_log().replay(&Revision(0)).unwrap().iter()
+            .map(|entry| entry.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(replayed(&logged), vec![(Revision(0), resp::Message::Integer(0)), (Revision(1), resp::Message::Integer(1))]);
+
+        logged.finalize_replay().unwrap();
+        assert!(!logged.transaction_log().is_legacy());
+        assert!(fs::read(&path).unwrap().starts_with(MAGIC));
+        logged.record_evidence(now, &Revision(2), &resp::Message::Integer(2)).unwrap();
+        let reopened = LoggedTransactions::open(&path, ()).unwrap();
+        assert_eq!(replayed(&reopened).len(), 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn damaged_records() {
+        let path = temp_file();
+        let mut log = LogFile::new(&path).unwrap();
+        assert!(log.is_empty());
+        log.append(log_entry(resp::Message::BulkString("checked".to_string()))).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let read = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            let log = LogFile::new(&path).unwrap();
+            let entries = log.replay(&Revision(0)).unwrap().entries().map(|(_, entry)| entry.map_err(|e| e.kind())).collect::<Vec<_>>();
+            entries.into_iter().map(|entry| entry.map(|entry| entry.message)).collect::<Vec<_>>()
+        };
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(read(&flipped), vec![Err(io::ErrorKind::InvalidData)]);
+        assert_eq!(read(&bytes[..bytes.len() - 1]), vec![Err(io::ErrorKind::UnexpectedEof)]);
+        assert_eq!(read(&bytes[..HEADER_LENGTH as usize + 3]), vec![Err(io::ErrorKind::UnexpectedEof)]);
+
+        /* A damaged length is no torn write, however far it reaches. */
+        let twice = [&bytes[..], &bytes[HEADER_LENGTH as usize..]].concat();
+        assert_eq!(read(&twice).len(), 2);
+        let mut lengthened = twice.clone();
+        lengthened[HEADER_LENGTH as usize + 1] ^= 1;
+        assert_eq!(read(&lengthened)[0], Err(io::ErrorKind::InvalidData));
+
+        /* Cut short as the log was created: it starts over. */
+        assert!(read(&bytes[..5]).is_empty());
+        assert_eq!(fs::read(&path).unwrap(), file_header());
+
+        let mut future = bytes.clone();
+        future[MAGIC.len()] += 1;
+        fs::write(&path, future).unwrap();
+        assert_eq!(LogFile::new(&path).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,57 +1,48 @@
 use std::io;
 use std::time;
+use rand::seq::IteratorRandom;
 
 use crate::commands;
 use crate::core;
+use crate::core::domain::cursors;
+use crate::core::notifications::EventClass;
 use crate::core::resp;
 use crate::globs;
 use resp::Message;
 
-pub enum ScanResult {
-    Complete(Vec<String>),
-    Chunk(usize, Vec<String>),
+pub enum Ttl {
+    UnknownKey,
+    Eternal,
+    ExpiresAt(time::SystemTime),
 }
 
-impl ScanResult {
-    const DEFAULT_CHUNK_SIZE: usize = 10;
-
-    fn to_owned(xs: &[&str]) -> Vec<String> {
-        xs.iter().map(|&x| x.into()).collect()   
-    }
-
-    fn complete(content: &[&str]) -> ScanResult {
-        Self::Complete(Self::to_owned(content))
-    }
-
-    fn chunk(offset: usize, content: &[&str]) -> ScanResult {
-        Self::Chunk(offset, Self::to_owned(content))
-    }
-
-    #[cfg(test)]
-    fn get_data(&self) -> Vec<String> {
+impl Ttl {
+    /* TTL and PTTL. */
+    fn remaining(&self, unit: commands::TimeUnit, now: time::SystemTime) -> Message {
         match self {
-            Self::Complete(xs) | Self::Chunk(_, xs) => xs.to_vec(),
+            Ttl::ExpiresAt(at) =>
+                Message::Integer(unit.round(at.duration_since(now).unwrap_or_default())),
+            _otherwise =>
+                self.deadline(unit),
         }
     }
-}
 
-impl From<ScanResult> for Message {
-    fn from(scan: ScanResult) -> Self {
-        fn make_reply_message(cursor: usize, content: &[String]) -> Message {
-            let content = Message::make_bulk_array(content);
-            Message::make_array(vec![Message::Integer(cursor as i64), content])
-        }
-
-        match scan {
-            ScanResult::Complete(xs)      => make_reply_message(0, &xs),
-            ScanResult::Chunk(cursor, xs) => make_reply_message(cursor, &xs),
+    /* EXPIRETIME and PEXPIRETIME. */
+    fn deadline(&self, unit: commands::TimeUnit) -> Message {
+        match self {
+            Ttl::UnknownKey    => Message::Integer(-2),
+            Ttl::Eternal       => Message::Integer(-1),
+            Ttl::ExpiresAt(at) => Message::Integer(unit.count(at.duration_since(time::UNIX_EPOCH).unwrap_or_default())),
         }
     }
 }
-pub enum Ttl {
-    UnknownKey,
-    Eternal,
-    ExpiresIn(time::Duration),
+
+/* What EXPIRE and its relatives did. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expiry {
+    Ignored,
+    Set,
+    Deleted,
 }
 
 pub trait Generic {
@@ -59,21 +50,33 @@ pub trait Generic {
     fn filter_keys(&self, pattern: &str) -> Vec<String>;
     fn scan_keys(
         &self, 
-        cursor: usize, 
+        cursor: u64, 
         pattern: Option<&str>, 
         count: Option<usize>, 
         tpe: Option<&str>
-    ) -> ScanResult;
+    ) -> cursors::Page<String>;
     fn type_of_key(&self, key: &str) -> Option<String>;
     fn key_exists(&self, key: &str) -> bool;
+    fn expire(
+        &mut self,
+        key: &str,
+        at: time::SystemTime,
+        condition: &commands::ExpiryCondition,
+        now: time::SystemTime
+    ) -> Expiry;
+    fn persist(&mut self, key: &str) -> bool;
+    fn count_existing(&self, keys: &[String]) -> usize;
+    fn random_key(&self) -> Option<String>;
+    fn delete_keys(&mut self, keys: &[String]) -> Vec<String>;
+    fn rename(&mut self, from: &str, to: &str, only_if_absent: bool) -> Option<bool>;
+    fn copy(&mut self, from: &str, to: &str, replace: bool) -> bool;
 }
 
 impl Generic for core::State {
     fn get_ttl(&self, key: &str) -> Ttl {
-        let now = time::SystemTime::now();
-        if let Some(ttl) = self.ttl_remaining(key, &now) {
-            Ttl::ExpiresIn(ttl)
-        } else if self.filter_keys(key).is_empty() {
+        if let Some(at) = self.deadline(key) {
+            Ttl::ExpiresAt(at)
+        } else if !self.key_exists(key) {
             Ttl::UnknownKey
         } else {
             Ttl::Eternal
@@ -83,64 +86,114 @@ impl Generic for core::State {
     fn filter_keys(&self, pattern: &str) -> Vec<String> {
         let glob = globs::Glob::new(pattern);
         self.keys()
-            .filter_map(|s| glob.as_ref().and_then(|p| p.matches(s).then(|| s.to_string())))
+            .filter(|s| glob.matches(s))
+            .cloned()
             .collect()
     }
 
+    /* MATCH and TYPE narrow down what is counted, so every page but the
+       last has COUNT keys in it. */
     fn scan_keys(
         &self,
-        cursor: usize,
+        cursor: u64,
         pattern: Option<&str>,
         count: Option<usize>,
-        _tpe: Option<&str>
-    ) -> ScanResult {
-        let combined_size = self.strings.len() + self.lists.len();
-        let count = count.unwrap_or(ScanResult::DEFAULT_CHUNK_SIZE);
-        let glob = pattern.and_then(globs::Glob::new);
-        let content =
-            self.keys()
-                .skip(cursor).take(count)
-                .filter_map(|s|
-                      if let Some(g) = glob.as_ref() {
-                          g.matches(s).then_some(s.as_str())
-                      } else {
-                          Some(s.as_str())
-                      }
-                 )
-                .collect::<Vec<&str>>();
-        if cursor + count > combined_size {
-            ScanResult::complete(&content)
-        } else {
-            ScanResult::chunk(cursor + count + 1, &content)
-        }
+        tpe: Option<&str>
+    ) -> cursors::Page<String> {
+        let glob = pattern.map(globs::Glob::new);
+        let page = self.positions().page(cursor, count.unwrap_or(cursors::DEFAULT_COUNT), |key| {
+            glob.as_ref().is_none_or(|g| g.matches(key))
+                && tpe.is_none_or(|t| self.value(key).is_some_and(|v| v.type_name().eq_ignore_ascii_case(t)))
+        });
+        cursors::Page { cursor: page.cursor, items: page.items.into_iter().cloned().collect() }
     }
 
     fn type_of_key(&self, key: &str) -> Option<String> {
-        if self.strings.contains_key(key) {
-            Some("string".to_string())
-        } else if self.lists.contains_key(key) {
-            Some("list".to_string())
-        } else if self.sorted_sets.contains_key(key) {
-            Some("zset".to_string())
+        self.value(key).map(|value| value.type_name().to_string())
+    }
+
+    fn key_exists(&self, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    /* A deadline already past deletes the key, once the condition holds. */
+    fn expire(
+        &mut self,
+        key: &str,
+        at: time::SystemTime,
+        condition: &commands::ExpiryCondition,
+        now: time::SystemTime
+    ) -> Expiry {
+        if !self.key_exists(key) || !condition.admits(self.deadline(key), at) {
+            Expiry::Ignored
+        } else if at <= now {
+            self.delete_keys(&[key.to_string()]);
+            Expiry::Deleted
         } else {
+            self.expire_at(key, at);
+            Expiry::Set
+        }
+    }
+
+    fn persist(&mut self, key: &str) -> bool {
+        let persisted = self.key_exists(key) && self.deadline(key).is_some();
+        self.clear_ttl(key);
+        persisted
+    }
+
+    /* A key mentioned twice counts twice. */
+    fn count_existing(&self, keys: &[String]) -> usize {
+        keys.iter().filter(|key| self.key_exists(key)).count()
+    }
+
+    fn random_key(&self) -> Option<String> {
+        self.keys().choose(&mut rand::thread_rng()).cloned()
+    }
+
+    /* The keys that were there to be deleted. */
+    fn delete_keys(&mut self, keys: &[String]) -> Vec<String> {
+        keys.iter()
+            .filter(|key| self.remove_key(key))
+            .cloned()
+            .collect()
+    }
+
+    /* None when there is no `from`, false when RENAMENX finds `to` taken. */
+    fn rename(&mut self, from: &str, to: &str, only_if_absent: bool) -> Option<bool> {
+        if !self.key_exists(from) {
             None
+        } else if only_if_absent && self.key_exists(to) {
+            Some(false)
+        } else {
+            self.rename_key(from, to);
+            Some(true)
         }
     }
 
-    fn key_exists(&self, key: &str) -> bool {
-        self.strings.keys()
-            .chain(self.lists.keys())
-            .any(|k| *k == key)
+    fn copy(&mut self, from: &str, to: &str, replace: bool) -> bool {
+        if !replace && self.key_exists(to) {
+            false
+        } else {
+            self.copy_key(from, to)
+        }
     }
 }
 
-impl From<Ttl> for Message {
-    fn from(value: Ttl) -> Self {
-        match value {
-            Ttl::UnknownKey     => Message::Integer(-2),
-            Ttl::Eternal        => Message::Integer(-1),
-            Ttl::ExpiresIn(ttl) => Message::Integer(ttl.as_secs() as i64),
-        }
+pub fn expire_at(key: &str, at: time::SystemTime) -> Message {
+    Message::make_bulk_array(&[
+        "PEXPIREAT".to_string(), key.to_string(), commands::epoch_millis(at).to_string(),
+    ])
+}
+
+/* The log gets the outcome rather than the command, which depended on the
+   time and on a condition: an absolute deadline replays the same. */
+fn journal_expiry(key: &str, at: time::SystemTime, expiry: &Expiry) -> core::Journal {
+    match expiry {
+        Expiry::Set     => core::Journal::Rewritten(expire_at(key, at)),
+        Expiry::Deleted => core::Journal::Rewritten(Message::make_bulk_array(&[
+            "DEL".to_string(), key.to_string(),
+        ])),
+        Expiry::Ignored => core::Journal::Nothing,
     }
 }
 
@@ -158,29 +211,84 @@ pub fn apply(
                 state.begin_reading()?
                      .scan_keys(*cursor, pattern.as_deref(), *count, tpe.as_deref())
             )),
-        commands::Generic::Ttl(key) =>
-            Ok(Message::from(
-                state.begin_reading()?.get_ttl(key)
-            )),
-        commands::Generic::Expire(key, ttl) => {
-            /* There are return values here. 1 for set, 0 for non-existant key. */
-            state.apply_transaction(&command, |data| {
-                data.register_ttl(
-                    &key.to_string(), 
-                    time::SystemTime::now(), 
-                    time::Duration::from_secs(*ttl)
-                );
-                Message::Integer(1)
-            })
+        commands::Generic::Ttl(key, unit) =>
+            Ok(state.begin_reading()?.get_ttl(key).remaining(*unit, state.clock().now())),
+        commands::Generic::ExpireTime(key, unit) =>
+            Ok(state.begin_reading()?.get_ttl(key).deadline(*unit)),
+        commands::Generic::Expire { key, at, condition } => {
+            let now = state.clock().now();
+            let Some(at) = at.resolve(now) else {
+                return Ok(Message::Error { prefix: resp::ErrorPrefix::Err, message: "invalid expire time".to_string() })
+            };
+            let expiry = state.apply_journaled_transaction(&command, |data| {
+                /* A deadline in the log was checked when it was set. */
+                let now = if data.is_replaying() { time::UNIX_EPOCH } else { now };
+                let expiry = data.expire(key, at, condition, now);
+                (expiry, journal_expiry(key, at, &expiry))
+            })?;
+            match expiry {
+                Expiry::Set     => state.notify(EventClass::Generic, "expire", key)?,
+                Expiry::Deleted => state.notify(EventClass::Generic, "del", key)?,
+                Expiry::Ignored => (),
+            }
+            Ok(Message::Integer((expiry != Expiry::Ignored) as i64))
         },
-        commands::Generic::Exists(key) =>
+        commands::Generic::Persist(key) => {
+            let persisted = state.apply_transaction(&command, |data| data.persist(key))?;
+            if persisted {
+                state.notify(EventClass::Generic, "persist", key)?;
+            }
+            Ok(Message::Integer(persisted as i64))
+        },
+        commands::Generic::Exists(keys) | commands::Generic::Touch(keys) =>
             Ok(Message::Integer(
-                if state.begin_reading()?.key_exists(&key.to_string()) {
-                    1
-                } else {
-                    0
-                }
+                state.begin_reading()?.count_existing(keys) as i64
             )),
+        /* Values are freed in place either way. */
+        commands::Generic::Delete(keys) | commands::Generic::Unlink(keys) => {
+            let deleted = state.apply_transaction(&command, |data| data.delete_keys(keys))?;
+            for key in &deleted {
+                state.notify(EventClass::Generic, "del", key)?;
+            }
+            Ok(Message::Integer(deleted.len() as i64))
+        },
+        /* Neither a missing key nor a refusal changes anything, so neither
+           goes in the log. */
+        commands::Generic::Rename { from, to, only_if_absent } =>
+            match state.apply_journaled_transaction(&command, |data| {
+                let renamed = data.rename(from, to, *only_if_absent);
+                (renamed, if renamed == Some(true) { core::Journal::AsIssued } else { core::Journal::Nothing })
+            })? {
+                None =>
+                    Ok(Message::Error { prefix: resp::ErrorPrefix::Err, message: "no such key".to_string() }),
+                Some(renamed) => {
+                    if renamed {
+                        state.notify(EventClass::Generic, "rename_from", from)?;
+                        state.notify(EventClass::Generic, "rename_to", to)?;
+                    }
+                    if *only_if_absent {
+                        Ok(Message::Integer(renamed as i64))
+                    } else {
+                        Ok(Message::SimpleString("OK".to_string()))
+                    }
+                },
+            },
+        commands::Generic::Copy { from, to, replace } => {
+            let copied = state.apply_journaled_transaction(&command, |data| {
+                let copied = data.copy(from, to, *replace);
+                (copied, if copied { core::Journal::AsIssued } else { core::Journal::Nothing })
+            })?;
+            if copied {
+                state.notify(EventClass::Generic, "copy_to", to)?;
+            }
+            Ok(Message::Integer(copied as i64))
+        },
+        commands::Generic::FlushAll => {
+            state.apply_transaction(&command, |data| data.remove_all_keys())?;
+            Ok(Message::SimpleString("OK".to_string()))
+        },
+        commands::Generic::RandomKey =>
+            Ok(state.begin_reading()?.random_key().map_or(Message::Nil, Message::BulkString)),
         commands::Generic::Type(key) =>
             Ok(Message::SimpleString(
                 state.begin_reading()?
@@ -200,9 +308,9 @@ mod tests {
     use crate::core::tx_log;
     
     fn make_domain() -> Result<core::State, io::Error> {
-        Ok(tx_log::LoggedTransactions::new(
+        tx_log::LoggedTransactions::new(
             ttl::Lifetimes::new(core::Datasets::new())
-        )?)
+        )
     }
 
     #[test]
@@ -210,8 +318,8 @@ mod tests {
         let mut st = make_domain().unwrap();
         st.set("users:427", "value");
         st.set("users:428", "value2");
-        st.append("sweden:users", "element", false);
-        st.append("sweden:users:429", "element", false);
+        st.append("sweden:users", "element", false).unwrap();
+        st.append("sweden:users:429", "element", false).unwrap();
 
         let filter = |pat: &str| {
             let mut xs = st.filter_keys(pat);
@@ -228,16 +336,129 @@ mod tests {
         let mut st = make_domain().unwrap();
         st.set("users:427", "value");
         st.set("users:428", "value2");
-        st.append("sweden:users", "element", false);
-        st.append("sweden:users:429", "element", false);
+        st.append("sweden:users", "element", false).unwrap();
+        st.append("sweden:users:429", "element", false).unwrap();
 
         let filter = |pat: &str| {
-            let mut xs = st.scan_keys(0, Some(pat), None, None).get_data();
+            let mut xs = st.scan_keys(0, Some(pat), None, None).items;
             xs.sort();
             xs
         };
 
         assert_eq!(filter("users:*"), vec!["users:427", "users:428"]);
         assert_eq!(filter("*users"), vec!["sweden:users"]);
+        assert_eq!(st.scan_keys(0, None, None, Some("list")).items.len(), 2);
+        assert_eq!(st.scan_keys(0, Some("users:*"), None, Some("LIST")).items.len(), 0);
+
+        st.delete_keys(&["users:427".to_string()]);
+        st.rename("users:428", "users:430", false);
+        assert_eq!(st.scan_keys(0, Some("users:*"), None, None).items, vec!["users:430"]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn scan_while_writing() {
+        let mut st = make_domain().unwrap();
+        for i in 0..100 {
+            st.set(&format!("old:{i}"), "value");
+        }
+
+        let (mut cursor, mut seen, mut round) = (0, vec![], 0);
+        loop {
+            let page = st.scan_keys(cursor, Some("old:*"), Some(3), None);
+            seen.extend(page.items);
+            if page.cursor == 0 {
+                break
+            }
+            cursor = page.cursor;
+            round += 1;
+            st.set(&format!("new:{round}"), "value");
+            st.set(&format!("old:{}", 100 + round), "value");
+        }
+        seen.sort();
+        seen.dedup();
+        assert!((0..100).all(|i| seen.contains(&format!("old:{i}"))));
+    }
+
+    #[test]
+    fn expiry_family() {
+        use commands::ExpiryCondition::*;
+
+        let mut st = make_domain().unwrap();
+        let now = time::SystemTime::now();
+        let later = |secs| now + time::Duration::from_secs(secs);
+        st.set("a", "1");
+
+        assert_eq!(st.expire("nope", later(10), &Always, now), Expiry::Ignored);
+        assert_eq!(st.expire("a", later(10), &IfSome, now), Expiry::Ignored);
+        assert_eq!(st.expire("a", later(10), &IfLater, now), Expiry::Ignored);
+        assert_eq!(st.expire("a", later(10), &IfNone, now), Expiry::Set);
+        assert_eq!(st.expire("a", later(20), &IfNone, now), Expiry::Ignored);
+        assert_eq!(st.expire("a", later(5), &IfLater, now), Expiry::Ignored);
+        assert_eq!(st.expire("a", later(20), &IfLater, now), Expiry::Set);
+        assert_eq!(st.expire("a", later(30), &IfSooner, now), Expiry::Ignored);
+        assert_eq!(st.expire("a", later(15), &IfSomeAndSooner, now), Expiry::Set);
+
+        let ttl = st.get_ttl("a");
+        assert_eq!(ttl.remaining(commands::TimeUnit::Seconds, now), Message::Integer(15));
+        assert_eq!(ttl.remaining(commands::TimeUnit::Milliseconds, now), Message::Integer(15000));
+        assert_eq!(
+            ttl.deadline(commands::TimeUnit::Seconds),
+            Message::Integer(later(15).duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64)
+        );
+
+        assert!(st.persist("a"));
+        assert!(!st.persist("a"));
+        assert_eq!(st.get_ttl("a").remaining(commands::TimeUnit::Seconds, now), Message::Integer(-1));
+        assert_eq!(st.expire("a", later(1), &IfSooner, now), Expiry::Set);
+        assert_eq!(st.expire("a", now, &Always, now), Expiry::Deleted);
+        assert!(!st.key_exists("a"));
+        assert_eq!(st.get_ttl("a").deadline(commands::TimeUnit::Seconds), Message::Integer(-2));
+    }
+
+    #[test]
+    fn journals_absolute_deadlines() {
+        let at = time::UNIX_EPOCH + time::Duration::from_millis(1_700_000_000_123);
+        match journal_expiry("a", at, &Expiry::Set) {
+            core::Journal::Rewritten(message) => assert_eq!(
+                message,
+                Message::make_bulk_array(&["PEXPIREAT".to_string(), "a".to_string(), "1700000000123".to_string()])
+            ),
+            _otherwise => panic!("expected a rewrite"),
+        }
+        match journal_expiry("a", at, &Expiry::Deleted) {
+            core::Journal::Rewritten(message) =>
+                assert_eq!(message, Message::make_bulk_array(&["DEL".to_string(), "a".to_string()])),
+            _otherwise => panic!("expected a rewrite"),
+        }
+        assert!(matches!(journal_expiry("a", at, &Expiry::Ignored), core::Journal::Nothing));
+    }
+
+    #[test]
+    fn key_lifecycle() {
+        let mut st = make_domain().unwrap();
+        let now = time::SystemTime::now();
+        st.set("a", "1");
+        st.append("b", "element", false).unwrap();
+        st.register_ttl("a", now, time::Duration::from_secs(100));
+
+        assert_eq!(st.count_existing(&["a".into(), "a".into(), "nope".into()]), 2);
+        assert_eq!(st.rename("nope", "c", false), None);
+        assert_eq!(st.rename("a", "b", true), Some(false));
+        assert_eq!(st.rename("a", "c", false), Some(true));
+        assert!(!st.key_exists("a"));
+        assert_eq!(st.ttl_remaining("a", &now), None);
+        assert_eq!(st.ttl_remaining("c", &now), Some(time::Duration::from_secs(100)));
+
+        assert!(!st.copy("c", "b", false));
+        assert!(st.copy("c", "b", true));
+        assert_eq!(st.type_of_key("b"), Some("string".to_string()));
+        assert_eq!(st.ttl_remaining("b", &now), Some(time::Duration::from_secs(100)));
+        assert!(!st.copy("nope", "d", true));
+
+        assert_eq!(st.delete_keys(&["b".into(), "b".into(), "nope".into()]), vec!["b".to_string()]);
+        assert_eq!(st.ttl_remaining("b", &now), None);
+        assert_eq!(st.random_key(), Some("c".to_string()));
+        st.delete_keys(&["c".into()]);
+        assert_eq!(st.random_key(), None);
+    }
+}
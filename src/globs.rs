@@ -1,17 +1,113 @@
-pub struct Glob(regex::Regex);
+use std::iter;
+use std::str;
 
-impl Glob {
-    pub fn new(pattern: &str) -> Option<Self> {
-        let mut buf = String::from(pattern).replace('*', ".+");
-        buf.insert(0, '^');
-        buf.push('$');
+/* A pattern the way Redis reads it in KEYS, SCAN MATCH, PSUBSCRIBE and
+   CONFIG GET. Every pattern is valid: whatever would be a syntax error
+   elsewhere, Redis gives some meaning, and so does this. */
+#[derive(Clone, Debug)]
+pub struct Glob(Vec<Token>);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(char),
+    AnyOne,
+    AnySequence,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn accepts(&self, c: char) -> bool {
+        match self {
+            Token::Literal(x)                => *x == c,
+            Token::AnyOne                    => true,
+            Token::AnySequence               => true,
+            Token::Class { negated, ranges } =>
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated,
+        }
+    }
 
-        let re = regex::Regex::new(&buf);
-        Some(Self(re.ok()?))
+    /* After the opening bracket. An unterminated class runs to the end of
+       the pattern, and `x-y` is a range whatever y is, as in Redis. */
+    fn class(chars: &mut iter::Peekable<str::Chars<'_>>) -> Self {
+        let negated = chars.next_if_eq(&'^').is_some();
+        let mut ranges = vec![];
+        loop {
+            match chars.next() {
+                None | Some(']') =>
+                    break,
+                Some('\\') if chars.peek().is_some() => {
+                    let escaped = chars.next().unwrap_or('\\');
+                    ranges.push((escaped, escaped));
+                },
+                Some(start) => {
+                    let mut lookahead = chars.clone();
+                    match (lookahead.next(), lookahead.next()) {
+                        (Some('-'), Some(end)) => {
+                            *chars = lookahead;
+                            ranges.push((start.min(end), start.max(end)));
+                        },
+                        _otherwise =>
+                            ranges.push((start, start)),
+                    }
+                },
+            }
+        }
+        Token::Class { negated, ranges }
     }
+}
 
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let mut chars = pattern.chars().peekable();
+        let mut tokens = vec![];
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*'  => {
+                    while chars.next_if_eq(&'*').is_some() {}
+                    Token::AnySequence
+                },
+                '?'  => Token::AnyOne,
+                '['  => Token::class(&mut chars),
+                /* A trailing backslash stands for itself. */
+                '\\' => Token::Literal(chars.next().unwrap_or('\\')),
+                c    => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Self(tokens)
+    }
+
+    /* Only the most recent star ever needs revisiting: anything an earlier
+       one could still absorb, the later one can too. */
     pub fn matches(&self, candidate: &str) -> bool {
-        self.0.is_match(candidate)
+        let Self(tokens) = self;
+        let (mut token, mut at) = (0, 0);
+        let mut backtrack = None;
+        loop {
+            let next = candidate[at..].chars().next();
+            match (tokens.get(token), next) {
+                (Some(Token::AnySequence), _) => {
+                    token += 1;
+                    backtrack = Some((token, at));
+                    continue
+                },
+                (Some(expected), Some(c)) if expected.accepts(c) => {
+                    token += 1;
+                    at += c.len_utf8();
+                    continue
+                },
+                (None, None) =>
+                    return true,
+                _otherwise =>
+                    (),
+            }
+
+            /* Let the last star swallow one more character and retry. */
+            let Some((resume, from)) = backtrack else { return false };
+            let Some(c) = candidate[from..].chars().next() else { return false };
+            backtrack = Some((resume, from + c.len_utf8()));
+            (token, at) = (resume, from + c.len_utf8());
+        }
     }
 }
 
@@ -21,7 +117,128 @@ mod tests {
 
     #[test]
     fn over_eager() {
-        assert!(!Glob::new("users:*").unwrap().matches("sweden:users:429"));
-        assert!(!Glob::new("*:users").unwrap().matches("sweden:users:429"));
+        assert!(!Glob::new("users:*").matches("sweden:users:429"));
+        assert!(!Glob::new("*:users").matches("sweden:users:429"));
     }
-}
\ No newline at end of file
+
+    /* Checked against stringmatchlen in Redis 7, which compares bytes where
+       this compares characters; the two only part ways outside ASCII. */
+    #[test]
+    fn redis_compatibility() {
+        let table = [
+            ("",              "",             true),
+            ("",              "a",            false),
+            ("a",             "",             false),
+            ("abc",           "abc",          true),
+            ("abc",           "abcd",         false),
+            ("abc",           "ABC",          false),
+
+            ("*",             "",             true),
+            ("*",             "anything",     true),
+            ("a*",            "a",            true),
+            ("a*",            "abc",          true),
+            ("a*",            "ba",           false),
+            ("*a",            "a",            true),
+            ("*a",            "bca",          true),
+            ("*a",            "ab",           false),
+            ("a*b",           "ab",           true),
+            ("a*b",           "axxxb",        true),
+            ("a*b",           "axxxbx",       false),
+            ("a*b*c",         "abc",          true),
+            ("a*b*c",         "axbxbxc",      true),
+            ("a*b*c",         "axbxcxb",      false),
+            ("***",           "",             true),
+            ("a**b",          "aXb",          true),
+            ("*a*a*a*a*b",    "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", false),
+            ("user:*",        "user:",        true),
+            ("user:*",        "user:42",      true),
+            ("*:42",          "user:42",      true),
+
+            ("?",             "",             false),
+            ("?",             "a",            true),
+            ("?",             "ab",           false),
+            ("a?c",           "abc",          true),
+            ("a?c",           "ac",           false),
+            ("??*",           "a",            false),
+            ("??*",           "ab",           true),
+            ("*?",            "",             false),
+            ("*?",            "x",            true),
+            ("?",             "é",            true),
+
+            ("[abc]",         "a",            true),
+            ("[abc]",         "c",            true),
+            ("[abc]",         "d",            false),
+            ("[abc]",         "",             false),
+            ("[abc]",         "ab",           false),
+            ("[^abc]",        "a",            false),
+            ("[^abc]",        "d",            true),
+            ("[^abc]",        "",             false),
+            ("[a-z]",         "m",            true),
+            ("[a-z]",         "A",            false),
+            ("[z-a]",         "m",            true),
+            ("[^a-z]",        "m",            false),
+            ("[^a-z]",        "5",            true),
+            ("[a-cx-z]",      "y",            true),
+            ("[a-cx-z]",      "m",            false),
+            ("[0-9]*",        "7up",          true),
+            ("h[ae]llo",      "hello",        true),
+            ("h[ae]llo",      "hallo",        true),
+            ("h[ae]llo",      "hillo",        false),
+            ("h[^e]llo",      "hallo",        true),
+            ("h[^e]llo",      "hello",        false),
+            ("[]",            "a",            false),
+            ("[]a",           "a",            false),
+            ("[^]",           "a",            true),
+            ("[",             "",             false),
+            ("[",             "[",            false),
+            ("[a",            "a",            true),
+            ("[a",            "b",            false),
+            ("[ab",           "b",            true),
+            ("[a-",           "a",            true),
+            ("[a-",           "-",            true),
+            ("[-a]",          "-",            true),
+            ("[a-]",          "_",            true),
+            ("[a-]x",         "`",            true),
+            ("[\\]]",         "]",            true),
+            ("[\\-]",         "-",            true),
+            ("[\\^a]",        "^",            true),
+            ("[a\\]",         "]",            true),
+            ("[a\\]",         "\\",           false),
+            ("[*]",           "*",            true),
+            ("[*]",           "a",            false),
+            ("[?]",           "?",            true),
+            ("[?]",           "a",            false),
+
+            ("\\*",           "*",            true),
+            ("\\*",           "a",            false),
+            ("\\?",           "?",            true),
+            ("\\?",           "a",            false),
+            ("\\[a]",         "[a]",          true),
+            ("\\[a]",         "a",            false),
+            ("\\a",           "a",            true),
+            ("\\\\",          "\\",           true),
+            ("a\\",           "a\\",          true),
+            ("a\\",           "a",            false),
+            ("*\\*",          "star*",        true),
+            ("*\\*",          "star",         false),
+
+            (".",             ".",            true),
+            (".",             "a",            false),
+            ("a.c",           "abc",          false),
+            ("(a|b)",         "(a|b)",        true),
+            ("(a|b)",         "a",            false),
+            ("a+",            "aa",           false),
+            ("a+",            "a+",           true),
+            ("^a$",           "^a$",          true),
+            ("{a,b}",         "a",            false),
+            ("key.*",         "key.1",        true),
+            ("key.*",         "keyx1",        false),
+        ];
+        for (pattern, candidate, expected) in table {
+            assert_eq!(
+                Glob::new(pattern).matches(candidate), expected,
+                "{pattern:?} against {candidate:?}"
+            );
+        }
+    }
+}
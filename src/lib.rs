@@ -3,6 +3,7 @@ pub mod core;
 pub mod generic;
 pub mod connections;
 pub mod server;
+pub mod pubsub;
 pub mod globs;
 
 
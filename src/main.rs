@@ -1,18 +1,56 @@
+use std::env;
 use std::io;
+use std::path;
+use std::sync;
+use std::time;
 
 use rusty_pelican::core::*;
 
 
+fn flag<'a>(arguments: &'a [String], name: &str) -> Option<&'a str> {
+    let at = arguments.iter().position(|argument| argument == name)?;
+    arguments.get(at + 1).map(String::as_str)
+}
+
 fn main() -> io::Result<()> {
-    let data = tx_log::LoggedTransactions::new(
+    let arguments = env::args().collect::<Vec<_>>();
+    /* Start from the data as it was at some point, copied into a new
+       directory first, rather than from all of it. */
+    let point_in_time = ["--until-revision", "--until-time"].iter()
+        .find_map(|name| flag(&arguments, name).map(|value| (name, value)))
+        .map(|(name, value)| tx_log::PointInTime::from_flag(name, value).ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{name}: cannot make sense of `{value}`"))
+        ))
+        .transpose()?;
+    let directory = match (&point_in_time, flag(&arguments, "--into")) {
+        (Some(_), Some(into)) => path::Path::new(into),
+        (Some(_), None)       => return Err(io::Error::new(io::ErrorKind::InvalidInput, "--into: a new data directory is needed")),
+        (None, _)             => path::Path::new("data"),
+    };
+    let data = tx_log::LoggedTransactions::in_directory(
+        directory,
         domain::ttl::Lifetimes::new(Datasets::default())
     )?;
 
     println!("Starting ...");
     let mut state = StateContext::new(data);
-    state.restore_from_disk()?;
+    if arguments.iter().any(|arg| arg == "--manual-clock") {
+        /* Time stands still but for DEBUG ADVANCE-CLOCK. */
+        state = state.with_clock(sync::Arc::new(clock::ManualClock::new(time::SystemTime::now())));
+    }
+    /* Rather than refuse to start, replay the log up to where it goes wrong. */
+    let mode = if arguments.iter().any(|arg| arg == "--recover") {
+        tx_log::RestoreMode::Recover
+    } else {
+        tx_log::RestoreMode::Strict
+    };
+    let report = match &point_in_time {
+        Some(point) => state.restore_point_in_time(path::Path::new("data"), point)?,
+        None        => state.restore_from_disk(mode)?,
+    };
+    print!("{report}");
 
     println!("Running.");
     let run_loop = RunLoop::new(state, "127.0.0.1:8080")?;
     run_loop.execute()
-}
\ No newline at end of file
+}
@@ -0,0 +1,58 @@
+use std::io;
+use crate::commands;
+use crate::core;
+use crate::core::channels;
+use crate::core::resp;
+
+fn confirmation(kind: &str, channel: Option<&str>, count: usize) -> resp::Message {
+    resp::Message::make_array(vec![
+        resp::Message::BulkString(kind.to_string()),
+        channel.map_or(resp::Message::Nil, |name| resp::Message::BulkString(name.to_string())),
+        resp::Message::Integer(count as i64),
+    ])
+}
+
+/* Every subscribed or unsubscribed channel gets a confirmation of its own;
+   unsubscribing from nothing in particular means from everything. */
+fn confirm_each<F>(
+    kind:        &str,
+    channels:    &[String],
+    subscriber:  &mut channels::Subscriber,
+    mut perform: F,
+) -> io::Result<Vec<resp::Message>>
+where
+    F: FnMut(&mut channels::Subscriber, &str) -> io::Result<usize>
+{
+    if channels.is_empty() {
+        Ok(vec![confirmation(kind, None, subscriber.subscription_count())])
+    } else {
+        channels.iter()
+            .map(|name| Ok(confirmation(kind, Some(name), perform(subscriber, name)?)))
+            .collect()
+    }
+}
+
+pub fn apply(
+    state:      &core::StateContext,
+    subscriber: &mut channels::Subscriber,
+    command:    &commands::PubSub
+) -> io::Result<Vec<resp::Message>> {
+    match command {
+        commands::PubSub::Subscribe(names) =>
+            confirm_each("subscribe", names, subscriber, channels::Subscriber::subscribe),
+        commands::PubSub::Unsubscribe(names) => {
+            let names = if names.is_empty() { subscriber.channel_names() } else { names.clone() };
+            confirm_each("unsubscribe", &names, subscriber, channels::Subscriber::unsubscribe)
+        },
+        commands::PubSub::PatternSubscribe(patterns) =>
+            confirm_each("psubscribe", patterns, subscriber, channels::Subscriber::subscribe_pattern),
+        commands::PubSub::PatternUnsubscribe(patterns) => {
+            let patterns = if patterns.is_empty() { subscriber.channel_patterns() } else { patterns.clone() };
+            confirm_each("punsubscribe", &patterns, subscriber, channels::Subscriber::unsubscribe_pattern)
+        },
+        commands::PubSub::Publish(channel, message) =>
+            Ok(vec![resp::Message::Integer(
+                state.publish(channel, message)? as i64
+            )]),
+    }
+}
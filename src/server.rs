@@ -1,9 +1,59 @@
 use std::io;
+use std::time;
 use crate::commands;
 use crate::core;
 use crate::core::resp;
 use crate::generic::*;
-use crate::core::snapshots::Snapshots;
+
+fn info(state: &core::StateContext, topic: &commands::Topic) -> io::Result<String> {
+    match topic {
+        commands::Topic::Everything => {
+            let sections = [
+                commands::Topic::Server, commands::Topic::Persistence, commands::Topic::Stats, commands::Topic::Keyspace,
+            ].iter()
+                .map(|topic| info(state, topic))
+                .collect::<io::Result<Vec<_>>>()?;
+            Ok(sections.join("\r\n"))
+        },
+        commands::Topic::Server =>
+            Ok("# Server\r\nredis_version:7.0.9\r\n".to_string()),
+        commands::Topic::Keyspace => {
+            let state = state.begin_reading()?;
+            Ok(format!(
+                "# Keyspace\r\ndb0:keys={},expires={},avg_ttl=0\r\n",
+                state.keys().count(), state.expiring_count()
+            ))
+        },
+        commands::Topic::Stats =>
+            Ok(state.stats().info()),
+        commands::Topic::Persistence => {
+            let appendfsync = state.config()?.appendfsync;
+            /* A SAVE holds on to the save status while it waits to read. */
+            let revision = state.begin_reading()?.revision();
+            let saves = state.saves()?.info(&revision, state.clock().instant());
+            let state = state.begin_reading()?;
+            let log = state.transaction_log();
+            /* Unix seconds, or -1 if never. */
+            let last_fsync = log.last_fsync()
+                .map_or(-1, |at| at.duration_since(time::UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64));
+            Ok(format!(
+                "# Persistence\r\n{saves}aof_enabled:1\r\naof_rewrite_in_progress:{}\r\naof_rewrites:{}\r\n\
+                 aof_truncations:{}\r\naof_current_size:{}\r\naof_base_size:{}\r\naof_fsync:{appendfsync}\r\n\
+                 aof_last_fsync:{last_fsync}\r\naof_pending_fsync_bytes:{}\r\n",
+                state.is_rewriting() as u8, state.rewrites(), state.truncations(), log.size(), log.base_size(), log.pending_fsync(),
+            ))
+        },
+        commands::Topic::Named(topic) =>
+            Ok(format!("Info about {topic}")),
+    }
+}
+
+fn save_in_progress() -> resp::Message {
+    resp::Message::Error {
+        prefix: resp::ErrorPrefix::Err,
+        message: "Background save already in progress".to_string(),
+    }
+}
 
 pub fn apply(
     state:   &core::StateContext,
@@ -19,25 +69,63 @@ pub fn apply(
                 prefix: resp::ErrorPrefix::Err,
                 message: "Unsupported command".to_string(),
             }),
-        commands::ServerManagement::Info(commands::Topic::Keyspace) => {
-            let keys = state.begin_reading()?.filter_keys("*");
-            let keyspace = format!("# Keyspace\r\ndb0:keys={},expires=0,avg_ttl=0\r\n", keys.len());
-            Ok(resp::Message::BulkString(keyspace))
-        },
-        commands::ServerManagement::Info(commands::Topic::Server) =>
-            Ok(resp::Message::BulkString(
-                "# Server\r\nredis_version:7.0.9\r\n".to_string()
-            )),
-        commands::ServerManagement::Info(commands::Topic::Named(topic)) =>
-            Ok(resp::Message::BulkString(format!("Info about {topic}"))),
+        commands::ServerManagement::Info(topic) =>
+            Ok(resp::Message::BulkString(info(state, topic)?)),
 //            Ok(resp::Message::Error {
 //                prefix: resp::ErrorPrefix::Err,
 //                message: "Unsupported command".to_string(),
 //            }),
-        commands::ServerManagement::BgSave => {
-            /* thread::spawn(move || ... ) */
-            state.begin_reading()?.save_snapshot()?;
+        commands::ServerManagement::BgSave =>
+            if state.save_in_background()? {
+                Ok(resp::Message::SimpleString("Background saving started".to_string()))
+            } else {
+                Ok(save_in_progress())
+            },
+        commands::ServerManagement::Save =>
+            if state.save()? {
+                Ok(resp::Message::SimpleString("OK".to_string()))
+            } else {
+                Ok(save_in_progress())
+            },
+        commands::ServerManagement::LastSave => {
+            let at = state.saves()?.last_save();
+            Ok(resp::Message::Integer(at.duration_since(time::UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64)))
+        },
+        commands::ServerManagement::ConfigGet(pattern) => {
+            let parameters = state.config()?.get(pattern).into_iter()
+                .flat_map(|(name, value)| [name, value])
+                .collect::<Vec<_>>();
+            Ok(resp::Message::make_bulk_array(&parameters))
+        },
+        commands::ServerManagement::ConfigSet(name, value) =>
+            match state.reconfigure()?.set(name, value) {
+                Ok(())       => Ok(resp::Message::SimpleString("OK".to_string())),
+                Err(message) => Ok(resp::Message::Error { prefix: resp::ErrorPrefix::Err, message }),
+            },
+        commands::ServerManagement::BgRewriteAof =>
+            if state.rewrite_log()? {
+                Ok(resp::Message::SimpleString("Background append only file rewriting started".to_string()))
+            } else {
+                Ok(resp::Message::Error {
+                    prefix: resp::ErrorPrefix::Err,
+                    message: "Background append only file rewriting already in progress".to_string(),
+                })
+            },
+        commands::ServerManagement::Debug(commands::DebugOption::SetActiveExpire(enabled)) => {
+            state.set_active_expiry(*enabled);
             Ok(resp::Message::SimpleString("OK".to_string()))
         },
+        commands::ServerManagement::Debug(commands::DebugOption::AdvanceClock(millis)) =>
+            match state.clock().as_manual() {
+                Some(clock) => {
+                    clock.advance(time::Duration::from_millis(*millis));
+                    Ok(resp::Message::SimpleString("OK".to_string()))
+                },
+                None =>
+                    Ok(resp::Message::Error {
+                        prefix: resp::ErrorPrefix::Err,
+                        message: "the clock only advances when started with --manual-clock".to_string(),
+                    }),
+            },
 }
 }
\ No newline at end of file